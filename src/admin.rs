@@ -0,0 +1,128 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Instant;
+
+use serde_json::{json, Value as JsonValue};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task;
+
+use crate::data::Data;
+use crate::stats::Stats;
+
+/// HTTP API exposing the control features of the simulator to test harnesses.
+///
+/// Only the request line is interpreted, every response is JSON and closes the connection.
+#[derive(Clone)]
+pub struct Admin {
+    start: Instant,
+    stats: Arc<Stats>,
+    data: watch::Receiver<Vec<Data>>,
+    active: watch::Sender<bool>,
+    reload: watch::Sender<()>,
+}
+
+impl Admin {
+    pub fn new(
+        start: Instant,
+        stats: Arc<Stats>,
+        data: watch::Receiver<Vec<Data>>,
+        active: watch::Sender<bool>,
+        reload: watch::Sender<()>,
+    ) -> Self {
+        Admin {
+            start,
+            stats,
+            data,
+            active,
+            reload,
+        }
+    }
+
+    /// Serve requests accepted on `listener` until it fails.
+    pub async fn run(self, listener: TcpListener) -> Result<(), io::Error> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let admin = self.clone();
+            task::spawn(async move {
+                if let Err(e) = admin.handle(stream).await {
+                    log::debug!("Admin connection from {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    async fn handle(&self, stream: TcpStream) -> Result<(), io::Error> {
+        let mut stream = BufReader::new(stream);
+        let mut request = String::new();
+        stream.read_line(&mut request).await?;
+        // skip the headers, no endpoint takes a body
+        let mut header = String::new();
+        while stream.read_line(&mut header).await? > 0 && header.trim_end() != "" {
+            header.clear();
+        }
+        let mut parts = request.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let path = parts.next().unwrap_or_default();
+        log::debug!("Admin request {} {}", method, path);
+        let (status, body) = self.respond(method, path);
+        let body = serde_json::to_vec(&body)?;
+        let head = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            body.len()
+        );
+        let stream = stream.get_mut();
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&body).await?;
+        stream.shutdown().await
+    }
+
+    fn respond(&self, method: &str, path: &str) -> (&'static str, JsonValue) {
+        match (method, path) {
+            ("GET", "/topics") => {
+                let data = self.data.borrow();
+                let topics = data.iter().flat_map(Data::topics).collect::<Vec<_>>();
+                ("200 OK", json!(topics))
+            }
+            ("GET", "/stats") => (
+                "200 OK",
+                json!({
+                    "uptime_ms": self.start.elapsed().as_millis() as u64,
+                    "active": *self.active.borrow(),
+                    "messages": self.stats.messages(),
+                    "bytes": self.stats.bytes(),
+                    "reconnects": self.stats.reconnects(),
+                    "deduplicated": self.stats.deduplicated(),
+                    "stale": self.stats.stale(),
+                    "shed": self.stats.shed(),
+                }),
+            ),
+            ("POST", "/pause") => {
+                if self.active.send_replace(false) {
+                    log::info!("Paused publishing on admin request");
+                }
+                ("200 OK", json!({ "active": false }))
+            }
+            ("POST", "/resume") => {
+                if !self.active.send_replace(true) {
+                    log::info!("Resumed publishing on admin request");
+                }
+                ("200 OK", json!({ "active": true }))
+            }
+            ("POST", "/reload") => {
+                self.reload.send_replace(());
+                ("202 Accepted", json!({ "reload": "requested" }))
+            }
+            (_, "/topics") | (_, "/stats") | (_, "/pause") | (_, "/resume") | (_, "/reload") => (
+                "405 Method Not Allowed",
+                json!({ "error": format!("{} is not supported on {}", method, path) }),
+            ),
+            _ => (
+                "404 Not Found",
+                json!({ "error": format!("Unknown endpoint {}", path) }),
+            ),
+        }
+    }
+}
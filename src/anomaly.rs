@@ -0,0 +1,126 @@
+use std::borrow::Cow;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use rand::{rngs::StdRng, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::data::{Context, Endian, Value};
+
+/// Value that is usually published as is, but occasionally spikes or drops out.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Anomalous {
+    base: Box<Value>,
+    /// Offset added to the base value on a spike, negative spikes go down.
+    #[serde(default)]
+    spike_magnitude: f64,
+    #[serde(default)]
+    spike_probability: f64,
+    /// Probability of skipping the publish entirely.
+    #[serde(default)]
+    dropout_probability: f64,
+    /// Seed of the anomalies, derived from `--seed` and the topic without one.
+    #[serde(default)]
+    seed: Option<u64>,
+    /// Generator of the anomalies, seeded on the first publish.
+    #[serde(skip)]
+    rng: Arc<Mutex<Option<StdRng>>>,
+}
+
+/// Outcome of a publish of an anomalous value.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Anomaly {
+    None,
+    Spike,
+    Dropout,
+}
+
+impl Anomalous {
+    /// Get the value published without anomalies.
+    pub fn base(&self) -> &Value {
+        &self.base
+    }
+
+    /// Get the probabilities of a spike and a dropout.
+    pub fn probabilities(&self) -> (f64, f64) {
+        (self.spike_probability, self.dropout_probability)
+    }
+
+    /// Check that the probabilities are valid and don't exceed 1 together.
+    pub fn check(&self) -> Result<(), io::Error> {
+        let valid = |p: f64| (0. ..=1.).contains(&p);
+        if !valid(self.spike_probability)
+            || !valid(self.dropout_probability)
+            || self.spike_probability + self.dropout_probability > 1.
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "spike probability {} and dropout probability {} must be within 0 and 1 together",
+                    self.spike_probability, self.dropout_probability
+                ),
+            ));
+        }
+        if !self.spike_magnitude.is_finite() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("spike magnitude {} is not finite", self.spike_magnitude),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Draw the anomaly of the next publish.
+    pub fn draw(&self, context: &Context) -> Anomaly {
+        let mut rng = self.rng.lock().unwrap();
+        let roll = rng
+            .get_or_insert_with(|| context.rng(self.seed))
+            .gen::<f64>();
+        if roll < self.dropout_probability {
+            Anomaly::Dropout
+        } else if roll < self.dropout_probability + self.spike_probability {
+            Anomaly::Spike
+        } else {
+            Anomaly::None
+        }
+    }
+
+    /// Resolve the base value and apply a spike to it.
+    ///
+    /// A dropout can only skip a whole publish, so it leaves the base value as is.
+    pub fn apply(&self, anomaly: Anomaly, context: &Context) -> Result<Cow<'_, Value>, io::Error> {
+        let value = self.base.resolve(context)?;
+        if anomaly != Anomaly::Spike {
+            return Ok(value);
+        }
+        let magnitude = self.spike_magnitude;
+        let mut spiked = value.into_owned();
+        match &mut spiked {
+            Value::Int { value, .. } => *value = value.saturating_add(magnitude.round() as i128),
+            Value::UInt { value, .. } => {
+                *value = (*value as f64 + magnitude).round().max(0.) as u128
+            }
+            Value::Float { value, .. } | Value::TextNumber { value, .. } => *value += magnitude,
+            value => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("can't spike the non-numeric value {:?}", value),
+                ))
+            }
+        }
+        Ok(Cow::Owned(spiked))
+    }
+
+    /// Continue the anomalies of `previous` if it has the same seed.
+    pub fn adopt_state(&mut self, previous: &Anomalous) {
+        if self.seed == previous.seed {
+            self.rng = Arc::clone(&previous.rng);
+        }
+        self.base.adopt_state(&previous.base);
+    }
+
+    /// Pass the defaults of the entry on to the base value.
+    pub fn inherit(&mut self, endian: Endian, align: usize) {
+        self.base.inherit(endian, align);
+    }
+}
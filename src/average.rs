@@ -0,0 +1,79 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::data::{Data, Value};
+
+/// Recent values of the topics that moving averages and stats are computed over.
+#[derive(Default)]
+pub struct Averages {
+    /// Recorded values and the largest window over them per source topic.
+    sources: Mutex<HashMap<String, (usize, VecDeque<f64>)>>,
+}
+
+impl Averages {
+    /// Track the sources of the moving averages in `vals`, forgetting sources no longer used.
+    pub fn track(&self, vals: &[Data]) {
+        let mut windows = HashMap::new();
+        for val in vals {
+            for (_, value) in val.values() {
+                value.walk(&mut |value| {
+                    let (source, window) = match value {
+                        Value::MovingAverage { source, window, .. } => (source, *window),
+                        Value::Stats { stats } => (&stats.source, stats.window),
+                        _ => return,
+                    };
+                    let max = windows.entry(source.as_str()).or_insert(0);
+                    *max = (*max).max(window);
+                });
+            }
+        }
+        let mut sources = self.sources.lock().unwrap();
+        sources.retain(|source, _| windows.contains_key(source.as_str()));
+        for (source, window) in windows {
+            let (max, values) = sources.entry(source.to_string()).or_default();
+            *max = window;
+            while values.len() > window {
+                values.pop_front();
+            }
+        }
+    }
+
+    /// Record a published value of `topic` if it is the source of a moving average or stats.
+    pub fn record(&self, topic: &str, value: f64) {
+        if let Some((window, values)) = self.sources.lock().unwrap().get_mut(topic) {
+            if values.len() == *window {
+                values.pop_front();
+            }
+            values.push_back(value);
+        }
+    }
+
+    /// Get the mean of the last `window` values of `source`, `None` before the first value.
+    pub fn mean(&self, source: &str, window: usize) -> Option<f64> {
+        let sources = self.sources.lock().unwrap();
+        let (_, values) = sources.get(source)?;
+        let recent = values.iter().rev().take(window);
+        let count = recent.len();
+        if count == 0 {
+            return None;
+        }
+        Some(recent.sum::<f64>() / count as f64)
+    }
+
+    /// Get the minimum, maximum and mean of the last `window` values of `source`, `None` before
+    /// the first value.
+    pub fn summary(&self, source: &str, window: usize) -> Option<(f64, f64, f64)> {
+        let sources = self.sources.lock().unwrap();
+        let (_, values) = sources.get(source)?;
+        let recent = values.iter().rev().take(window);
+        let count = recent.len();
+        if count == 0 {
+            return None;
+        }
+        let (min, max, sum) = recent.fold(
+            (f64::INFINITY, f64::NEG_INFINITY, 0.),
+            |(min, max, sum), value| (min.min(*value), max.max(*value), sum + value),
+        );
+        Some((min, max, sum / count as f64))
+    }
+}
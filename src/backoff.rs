@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Exponential backoff between reconnect attempts.
+///
+/// The delay doubles after every attempt up to `max`, each wait is randomly shortened by up to half
+/// so simulators losing their connection at the same time don't reconnect in lockstep.
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Backoff {
+            min,
+            max,
+            current: min,
+        }
+    }
+
+    /// Get the delay before the next attempt and increase the following one.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current.mul_f64(rand::thread_rng().gen_range(0.5..=1.));
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    /// Start over at the minimum delay.
+    pub fn reset(&mut self) {
+        self.current = self.min;
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use anyhow::{Context as _, Result};
+use rand::{rngs::StdRng, SeedableRng};
+use rumqttc::{Publish, Request, Sender};
+use tokio::sync::watch;
+
+use crate::data::{Context, Data};
+use crate::encoder::EncoderKind;
+
+/// Throughput benchmark publishing a fixed number of messages as fast as the event loop accepts
+/// them, regardless of the entries' intervals.
+pub struct Bench {
+    count: u64,
+    started: OnceLock<Instant>,
+    messages: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Bench {
+    pub fn new(count: u64) -> Self {
+        Bench {
+            count,
+            started: OnceLock::new(),
+            messages: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// Publish the entries in turn until `count` messages were handed to the event loop.
+    ///
+    /// The clock starts with the first connect, so connecting isn't part of the measurement.
+    pub async fn run(
+        self: Arc<Self>,
+        data: Vec<Data>,
+        encoder: EncoderKind,
+        context: Context,
+        sink: Sender<Request>,
+        mut connects: watch::Receiver<u64>,
+    ) -> Result<()> {
+        while *connects.borrow_and_update() == 0 {
+            connects.changed().await?;
+        }
+        self.started.get_or_init(Instant::now);
+        let mut rng = StdRng::from_entropy();
+        'publish: loop {
+            let before = self.messages.load(Ordering::Relaxed);
+            for val in &data {
+                let encoder = val.encoder().unwrap_or(encoder).encoder();
+                let context = Context {
+                    index: val.index(),
+                    topic: Arc::from(val.topic()),
+                    entry_rng: val.rng(),
+                    constants: val.constants(),
+                    ..context.clone()
+                };
+                for (component, value) in val.values() {
+                    let failed = || format!("Failed to serialize {}", val.topic());
+                    for value in value.resolve_all(&context).with_context(failed)? {
+                        let mut buf = Vec::new();
+                        value
+                            .serialize(encoder, &context, &mut buf)
+                            .and_then(|()| val.finish_payload(&mut buf))
+                            .with_context(failed)?;
+                        let topics = val.publish_topics(component, &buf, &mut rng);
+                        for (topic, qos) in val.publish_targets(topics) {
+                            if self.messages.load(Ordering::Relaxed) == self.count {
+                                break 'publish;
+                            }
+                            context.stats.record_publish(&topic, buf.len());
+                            let mut msg = Publish::new(topic, qos, buf.clone());
+                            msg.retain = val.retain();
+                            sink.send(Request::Publish(msg))
+                                .await
+                                .expect("Eventloop rx seems to be dead.");
+                            self.messages.fetch_add(1, Ordering::Relaxed);
+                            self.bytes.fetch_add(buf.len() as u64, Ordering::Relaxed);
+                        }
+                    }
+                }
+            }
+            if self.messages.load(Ordering::Relaxed) == before {
+                anyhow::bail!("The data doesn't produce any messages to benchmark");
+            }
+        }
+        Ok(())
+    }
+
+    /// Log the achieved throughput, called once the broker acknowledged the messages.
+    pub fn report(&self) {
+        let started = match self.started.get() {
+            Some(started) => started,
+            None => {
+                log::warn!("The benchmark never started");
+                return;
+            }
+        };
+        let secs = started.elapsed().as_secs_f64();
+        let messages = self.messages.load(Ordering::Relaxed);
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        log::info!(
+            "Benchmark sent {} messages ({} bytes) in {:.3}s, {:.0} msg/s, {:.0} bytes/s",
+            messages,
+            bytes,
+            secs,
+            messages as f64 / secs,
+            bytes as f64 / secs
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use rumqttc::ClientConfig;
+use tokio::io::copy_bidirectional;
+use tokio::net::{lookup_host, TcpListener, TcpSocket, TcpStream};
+use tokio::task;
+use tokio_rustls::webpki::{DNSName, DNSNameRef};
+use tokio_rustls::TlsConnector;
+
+use crate::proxy::HttpProxy;
+
+/// Way of connecting to the broker that rumqttc doesn't support itself.
+#[derive(Clone)]
+pub enum Route {
+    /// Straight to the broker, for connections the forwarder secures itself.
+    Direct,
+    /// Through a socket bound to a local address.
+    Bind(SocketAddr),
+    /// Through a tunnel of an HTTP proxy.
+    HttpProxy(HttpProxy),
+}
+
+impl Route {
+    async fn connect(&self, broker: &(String, u16)) -> Result<TcpStream, io::Error> {
+        match self {
+            Route::Direct => TcpStream::connect((broker.0.as_str(), broker.1)).await,
+            Route::Bind(local) => connect(*local, broker).await,
+            Route::HttpProxy(proxy) => proxy.connect(broker).await,
+        }
+    }
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Route::Direct => write!(f, "directly"),
+            Route::Bind(local) => write!(f, "from {}", local),
+            Route::HttpProxy(proxy) => write!(f, "through proxy {}", proxy),
+        }
+    }
+}
+
+/// Forwarder of broker connections along a `Route`.
+///
+/// rumqttc doesn't expose the socket it connects with, so the client connects to a loopback
+/// listener instead and every accepted connection is forwarded to the broker.
+pub struct Forwarder {
+    listener: TcpListener,
+    route: Route,
+    broker: (String, u16),
+    /// TLS the forwarded connections are secured with and the name the broker is verified for.
+    tls: Option<(TlsConnector, DNSName)>,
+}
+
+impl Forwarder {
+    /// Listen on a loopback port for connections to forward to `broker` along `route`.
+    pub async fn bind(route: Route, broker: (String, u16)) -> Result<Self, io::Error> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        Ok(Forwarder {
+            listener,
+            route,
+            broker,
+            tls: None,
+        })
+    }
+
+    /// Secure the forwarded connections with TLS, presenting and verifying `server_name` rather
+    /// than the broker's host, so the client connects to the loopback listener without TLS.
+    pub fn tls(mut self, config: ClientConfig, server_name: &str) -> Result<Self, io::Error> {
+        let name = DNSNameRef::try_from_ascii_str(server_name).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a valid DNS name", server_name),
+            )
+        })?;
+        self.tls = Some((TlsConnector::from(Arc::new(config)), name.to_owned()));
+        Ok(self)
+    }
+
+    /// Get the loopback address the client should connect to.
+    pub fn addr(&self) -> Result<SocketAddr, io::Error> {
+        self.listener.local_addr()
+    }
+
+    /// Forward accepted connections until the listener fails.
+    pub async fn run(self) -> Result<(), io::Error> {
+        loop {
+            let (mut inbound, _) = self.listener.accept().await?;
+            let route = self.route.clone();
+            let broker = self.broker.clone();
+            let tls = self.tls.clone();
+            task::spawn(async move {
+                let mut outbound = match route.connect(&broker).await {
+                    Ok(outbound) => outbound,
+                    Err(e) => {
+                        log::error!(
+                            "Failed to connect to {}:{} {}: {}",
+                            broker.0,
+                            broker.1,
+                            route,
+                            e
+                        );
+                        return;
+                    }
+                };
+                let forwarded = match tls {
+                    Some((connector, name)) => {
+                        match connector.connect(name.as_ref(), outbound).await {
+                            Ok(mut outbound) => {
+                                copy_bidirectional(&mut inbound, &mut outbound).await
+                            }
+                            Err(e) => {
+                                log::error!("TLS handshake with {} failed: {}", broker.0, e);
+                                return;
+                            }
+                        }
+                    }
+                    None => copy_bidirectional(&mut inbound, &mut outbound).await,
+                };
+                if let Err(e) = forwarded {
+                    log::debug!("Forwarded connection closed: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// Connect to `broker` through a socket bound to `local`.
+async fn connect(local: SocketAddr, broker: &(String, u16)) -> Result<TcpStream, io::Error> {
+    let addr = lookup_host((broker.0.as_str(), broker.1))
+        .await?
+        .find(|addr| addr.is_ipv4() == local.is_ipv4())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no address of {} matches the family of {}", broker.0, local),
+            )
+        })?;
+    let socket = if local.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    socket.set_reuseaddr(true)?;
+    socket.bind(local)?;
+    socket.connect(addr).await
+}
+
+#[cfg(test)]
+mod tests {
+    use rumqttc::{certs, pkcs8_private_keys};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::runtime;
+    use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+    use tokio_rustls::TlsAcceptor;
+
+    use super::*;
+
+    const CA_CERT: &[u8] = include_bytes!("../testdata/ca.pem");
+    const CERT: &[u8] = include_bytes!("../testdata/cert.pem");
+    const KEY: &[u8] = include_bytes!("../testdata/key.pem");
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn client_config() -> ClientConfig {
+        let mut config = ClientConfig::new();
+        config.root_store.add_pem_file(&mut &CA_CERT[..]).unwrap();
+        config
+    }
+
+    /// Forward a message to a broker on a loopback address with a certificate for broker.test,
+    /// verified as `server_name`, and get the message if the handshake succeeded.
+    fn forward(server_name: &str) -> Option<Vec<u8>> {
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        let key = pkcs8_private_keys(&mut &KEY[..]).unwrap().remove(0);
+        config
+            .set_single_cert(certs(&mut &CERT[..]).unwrap(), key)
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        block_on(async {
+            let broker = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+            let port = broker.local_addr().unwrap().port();
+            let forwarder = Forwarder::bind(Route::Direct, ("127.0.0.1".to_string(), port))
+                .await
+                .unwrap()
+                .tls(client_config(), server_name)
+                .unwrap();
+            let mut client = TcpStream::connect(forwarder.addr().unwrap()).await.unwrap();
+            task::spawn(forwarder.run());
+            client.write_all(b"ping").await.unwrap();
+            let (stream, _) = broker.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.ok()?;
+            let mut received = vec![0; 4];
+            stream.read_exact(&mut received).await.ok()?;
+            Some(received)
+        })
+    }
+
+    #[test]
+    fn forwarded_connections_verify_the_server_name_instead_of_the_host() {
+        assert_eq!(forward("broker.test").as_deref(), Some(&b"ping"[..]));
+        assert_eq!(forward("other.test"), None);
+    }
+
+    #[test]
+    fn server_names_must_be_dns_names() {
+        for name in ["127.0.0.1", "broker test", ""] {
+            let forwarder = block_on(async {
+                let broker = ("127.0.0.1".to_string(), 1883);
+                let forwarder = Forwarder::bind(Route::Direct, broker).await.unwrap();
+                forwarder.tls(client_config(), name)
+            });
+            let e = forwarder.err().unwrap();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{}", name);
+        }
+    }
+}
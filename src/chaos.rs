@@ -0,0 +1,79 @@
+use std::str::FromStr;
+
+use rand::{rngs::StdRng, Rng};
+
+/// Probabilities of the faults injected by `--chaos`.
+///
+/// No random numbers are drawn for faults with a probability of zero, so the sequences of
+/// `--seed` only change with the faults that are enabled.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Chaos {
+    /// Probability of dropping a publish, the default of entries without their own drop rate.
+    pub drop: f64,
+    /// Probability of publishing a message twice.
+    pub duplicate: f64,
+    /// Probability of flipping a random bit of a payload.
+    pub corrupt: f64,
+    /// Probability of disconnecting from the broker on a tick, which then reconnects.
+    pub flap: f64,
+}
+
+impl FromStr for Chaos {
+    type Err = String;
+
+    /// Parse comma separated `fault=probability` pairs, e.g. `drop=0.1,corrupt=0.01`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chaos = Chaos::default();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (fault, probability) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected fault=probability, got {}", pair))?;
+            let probability = probability
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|p| (0. ..=1.).contains(p))
+                .ok_or_else(|| format!("{} is not a probability between 0 and 1", probability))?;
+            let target = match fault.trim() {
+                "drop" => &mut chaos.drop,
+                "duplicate" => &mut chaos.duplicate,
+                "corrupt" => &mut chaos.corrupt,
+                "flap" => &mut chaos.flap,
+                fault => {
+                    return Err(format!(
+                        "unknown fault {}, expected drop, duplicate, corrupt or flap",
+                        fault
+                    ))
+                }
+            };
+            *target = probability;
+        }
+        Ok(chaos)
+    }
+}
+
+impl Chaos {
+    /// Check whether the next publish is sent twice.
+    pub fn duplicates(&self, rng: &mut StdRng) -> bool {
+        roll(self.duplicate, rng)
+    }
+
+    /// Check whether to disconnect from the broker on this tick.
+    pub fn flaps(&self, rng: &mut StdRng) -> bool {
+        roll(self.flap, rng)
+    }
+
+    /// Flip a random bit of `payload` with the corruption probability, returns whether it did.
+    pub fn corrupt(&self, payload: &mut [u8], rng: &mut StdRng) -> bool {
+        if payload.is_empty() || !roll(self.corrupt, rng) {
+            return false;
+        }
+        let bit = rng.gen_range(0..payload.len() * 8);
+        payload[bit / 8] ^= 1 << (bit % 8);
+        true
+    }
+}
+
+fn roll(probability: f64, rng: &mut StdRng) -> bool {
+    probability > 0. && rng.gen::<f64>() < probability
+}
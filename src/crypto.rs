@@ -0,0 +1,272 @@
+use std::borrow::Cow;
+use std::io;
+use std::path::PathBuf;
+
+use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::data::{Endian, Value};
+
+/// Value encrypted before publishing.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Encrypted {
+    algorithm: Cipher,
+    key: Secret,
+    iv: Secret,
+    inner: Box<Value>,
+}
+
+impl Encrypted {
+    /// Get the cipher the value is encrypted with.
+    pub fn algorithm(&self) -> Cipher {
+        self.algorithm
+    }
+
+    /// Get the value that is encrypted.
+    pub fn inner(&self) -> &Value {
+        &self.inner
+    }
+
+    /// Pass the defaults of the entry on to the inner value.
+    pub fn inherit(&mut self, endian: Endian, align: usize) {
+        self.inner.inherit(endian, align);
+    }
+
+    /// Encrypt the serialized inner value.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let key = self.key.load()?;
+        let iv = self.iv.load()?;
+        let (key_len, iv_len) = self.algorithm.lengths();
+        check_length("key", &key, key_len, self.algorithm)?;
+        check_length("iv", &iv, iv_len, self.algorithm)?;
+        match self.algorithm {
+            Cipher::Aes128Cbc => {
+                let cipher = cbc::Encryptor::<aes::Aes128>::new_from_slices(&key, &iv)
+                    .map_err(|e| invalid(e.to_string()))?;
+                Ok(cipher.encrypt_padded_vec_mut::<Pkcs7>(plaintext))
+            }
+            Cipher::Aes256Gcm => Aes256Gcm::new_from_slice(&key)
+                .map_err(|e| invalid(e.to_string()))?
+                .encrypt(Nonce::from_slice(&iv), plaintext)
+                .map_err(|e| invalid(e.to_string())),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub enum Cipher {
+    /// AES-128 in CBC mode with PKCS#7 padding.
+    Aes128Cbc,
+    /// AES-256 in GCM mode, the 16 byte tag is appended to the ciphertext.
+    Aes256Gcm,
+}
+
+impl Cipher {
+    /// Get the key and IV lengths in bytes.
+    fn lengths(self) -> (usize, usize) {
+        match self {
+            Cipher::Aes128Cbc => (16, 16),
+            Cipher::Aes256Gcm => (32, 12),
+        }
+    }
+}
+
+/// Value published along with an HMAC of its serialized bytes.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Signed {
+    algorithm: MacAlgorithm,
+    key: Secret,
+    inner: Box<Value>,
+    #[serde(default)]
+    placement: Placement,
+    /// Number of leading digest bytes to keep, the whole digest by default.
+    #[serde(default)]
+    truncate: Option<usize>,
+}
+
+impl Signed {
+    /// Get the algorithm the value is signed with.
+    pub fn algorithm(&self) -> MacAlgorithm {
+        self.algorithm
+    }
+
+    /// Get the value that is signed.
+    pub fn inner(&self) -> &Value {
+        &self.inner
+    }
+
+    /// Pass the defaults of the entry on to the inner value.
+    pub fn inherit(&mut self, endian: Endian, align: usize) {
+        self.inner.inherit(endian, align);
+    }
+
+    /// Check that the truncated digest length is possible.
+    pub fn check(&self) -> Result<(), io::Error> {
+        match self.truncate {
+            Some(len) if len == 0 || len > self.algorithm.len() => Err(invalid(format!(
+                "{:?} digests can only be truncated to 1 to {} bytes, not {}",
+                self.algorithm,
+                self.algorithm.len(),
+                len
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Add the digest of the serialized inner value to it.
+    pub fn sign(&self, payload: &[u8]) -> Result<Vec<u8>, io::Error> {
+        self.check()?;
+        let key = self.key.load()?;
+        let digest = match self.algorithm {
+            MacAlgorithm::HmacSha256 => {
+                let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key)
+                    .map_err(|e| invalid(e.to_string()))?;
+                mac.update(payload);
+                mac.finalize().into_bytes().to_vec()
+            }
+        };
+        let digest = &digest[..self.truncate.unwrap_or(digest.len())];
+        let mut signed = Vec::with_capacity(payload.len() + digest.len());
+        match self.placement {
+            Placement::Prefix => {
+                signed.extend_from_slice(digest);
+                signed.extend_from_slice(payload);
+            }
+            Placement::Suffix => {
+                signed.extend_from_slice(payload);
+                signed.extend_from_slice(digest);
+            }
+        }
+        Ok(signed)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub enum MacAlgorithm {
+    HmacSha256,
+}
+
+impl MacAlgorithm {
+    /// Get the length of the digest in bytes.
+    fn len(self) -> usize {
+        match self {
+            MacAlgorithm::HmacSha256 => 32,
+        }
+    }
+}
+
+/// Position of a digest relative to the signed bytes.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub enum Placement {
+    #[serde(alias = "prefix")]
+    Prefix,
+    #[default]
+    #[serde(alias = "suffix")]
+    Suffix,
+}
+
+/// Hex encoded bytes, given inline, read from a file or from an environment variable.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Secret {
+    Hex(String),
+    File { file: PathBuf },
+    Env { env: String },
+}
+
+impl Secret {
+    fn load(&self) -> Result<Vec<u8>, io::Error> {
+        let hex = match self {
+            Secret::Hex(hex) => Cow::Borrowed(hex.as_str()),
+            Secret::File { file } => Cow::Owned(std::fs::read_to_string(file)?),
+            Secret::Env { env } => {
+                Cow::Owned(std::env::var(env).map_err(|e| invalid(format!("{}: {}", env, e)))?)
+            }
+        };
+        hex::decode(hex.trim()).map_err(|e| invalid(e.to_string()))
+    }
+}
+
+fn check_length(name: &str, bytes: &[u8], len: usize, cipher: Cipher) -> Result<(), io::Error> {
+    if bytes.len() != len {
+        return Err(invalid(format!(
+            "{:?} requires a {} byte {}, got {} bytes",
+            cipher,
+            len,
+            name,
+            bytes.len()
+        )));
+    }
+    Ok(())
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use aes::cipher::BlockDecryptMut;
+
+    use super::*;
+
+    const PLAINTEXT: &[u8] = b"\x00\x2a hello world";
+
+    fn encrypted(config: serde_json::Value) -> Encrypted {
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn aes_128_cbc_round_trip() {
+        let key = [0x11; 16];
+        let iv = [0x22; 16];
+        let value = encrypted(serde_json::json!({
+            "algorithm": "Aes128Cbc",
+            "key": hex::encode(key),
+            "iv": hex::encode(iv),
+            "inner": {"value": 42},
+        }));
+        let ciphertext = value.encrypt(PLAINTEXT).unwrap();
+        assert_ne!(ciphertext, PLAINTEXT);
+        let plaintext = cbc::Decryptor::<aes::Aes128>::new_from_slices(&key, &iv)
+            .unwrap()
+            .decrypt_padded_vec_mut::<Pkcs7>(&ciphertext)
+            .unwrap();
+        assert_eq!(plaintext, PLAINTEXT);
+    }
+
+    #[test]
+    fn aes_256_gcm_round_trip() {
+        let key = [0x33; 32];
+        let iv = [0x44; 12];
+        std::env::set_var("MQTT_SIMULATOR_TEST_GCM_KEY", hex::encode(key));
+        let value = encrypted(serde_json::json!({
+            "algorithm": "Aes256Gcm",
+            "key": {"env": "MQTT_SIMULATOR_TEST_GCM_KEY"},
+            "iv": hex::encode(iv),
+            "inner": {"value": 42},
+        }));
+        let ciphertext = value.encrypt(PLAINTEXT).unwrap();
+        assert_eq!(ciphertext.len(), PLAINTEXT.len() + 16);
+        let plaintext = Aes256Gcm::new_from_slice(&key)
+            .unwrap()
+            .decrypt(Nonce::from_slice(&iv), ciphertext.as_slice())
+            .unwrap();
+        assert_eq!(plaintext, PLAINTEXT);
+    }
+
+    #[test]
+    fn wrong_key_length_is_rejected() {
+        let value = encrypted(serde_json::json!({
+            "algorithm": "Aes256Gcm",
+            "key": "0011",
+            "iv": hex::encode([0; 12]),
+            "inner": {"value": 42},
+        }));
+        assert!(value.encrypt(PLAINTEXT).is_err());
+    }
+}
@@ -1,196 +1,3272 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
 
-use serde::Deserialize;
+use chrono::format::{Item, StrftimeItems};
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+use rumqttc::QoS;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
 
-#[derive(Clone, Debug, Deserialize)]
+use crate::anomaly::Anomaly;
+use crate::average::Averages;
+use crate::encoder::{Encoder, EncoderKind};
+use crate::stats::{ConnectionState, Stats};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Data {
-    topic: String,
-    data: Value,
+    topic: Topics,
+    /// Allow topics starting with `$`, which brokers reserve for their own use.
+    #[serde(default)]
+    system_topic: bool,
+    #[serde(flatten)]
+    payload: Payload,
+    #[serde(default)]
+    encoder: Option<EncoderKind>,
+    #[serde(default)]
+    payload_hash: PayloadHash,
+    #[serde(default)]
+    nonce: Nonce,
+    /// Checksum appended to the serialized payload.
+    #[serde(default)]
+    checksum: Option<Checksum>,
+    /// Compression of the serialized payload including its checksum.
+    #[serde(default)]
+    compression: Compression,
+    /// Endianness of the nested values that don't set their own.
+    #[serde(default)]
+    endian: Option<Endian>,
+    /// Alignment of the fields of nested frames that don't set their own.
+    #[serde(default)]
+    align: Option<usize>,
+    #[serde(default)]
+    drop_rate: Option<f64>,
+    #[serde(default)]
+    dedup: Option<bool>,
+    #[serde(default)]
+    interval_ms: Option<u64>,
+    /// Range the `interval_ms` of every device is drawn from when the entry is expanded.
+    #[serde(default)]
+    interval_range_ms: Option<IntervalRange>,
+    #[serde(default)]
+    jitter_ms: Option<u64>,
+    #[serde(default)]
+    send_delay_ms: Option<u64>,
+    /// Delay of the first publish, staggering entries with the same interval.
+    #[serde(default)]
+    phase_ms: Option<u64>,
+    #[serde(default)]
+    missed_ticks: MissedTicks,
+    #[serde(default)]
+    publish_probability: Option<Probability>,
+    #[serde(default)]
+    active_window: Option<ActiveWindow>,
+    #[serde(default)]
+    qos: Option<Qos>,
+    #[serde(default)]
+    retain: bool,
+    #[serde(default)]
+    qos_rotation: Vec<Qos>,
+    #[serde(default)]
+    qos_fanout: bool,
+    #[serde(default)]
+    client_id: Option<String>,
+    /// Name of the only broker the entry is published to, instead of every broker.
+    #[serde(default)]
+    broker: Option<String>,
+    /// Number of copies of the entry to publish, `{i}` in their topics is replaced by their index.
+    #[serde(default)]
+    repeat: Option<usize>,
+    /// Random constants of every device, drawn once when the entry is expanded.
+    #[serde(default)]
+    constants: BTreeMap<String, ConstantRange>,
+    /// Index of the entry within its fleet, assigned during expansion.
+    #[serde(skip)]
+    index: usize,
+    /// Values of the `constants` drawn for this device.
+    #[serde(skip)]
+    baked: Arc<BTreeMap<String, Constant>>,
+    #[serde(skip)]
+    publishes: Arc<AtomicUsize>,
+    /// Generator of the random values, seeded on the first publish.
+    #[serde(skip)]
+    rng: Arc<Mutex<Option<StdRng>>>,
+}
+
+impl Data {
+    /// Get the values to publish, paired with the subtopic of their component.
+    pub fn values(&self) -> Vec<(Option<&str>, &Value)> {
+        match &self.payload {
+            Payload::Data(value) => vec![(None, value)],
+            Payload::Components(components) => components
+                .iter()
+                .map(|(component, value)| (Some(component.as_str()), value))
+                .collect(),
+        }
+    }
+
+    /// Check whether the data is published as a group of components.
+    pub fn is_group(&self) -> bool {
+        matches!(self.payload, Payload::Components(_))
+    }
+
+    /// Get a reference to the data's topic, the first one if it is published to several.
+    pub fn topic(&self) -> &str {
+        self.topics().first().map_or("", String::as_str)
+    }
+
+    /// Get all topics the data is published to.
+    pub fn topics(&self) -> &[String] {
+        match &self.topic {
+            Topics::One(topic) => std::slice::from_ref(topic),
+            Topics::Many(topics) => topics,
+        }
+    }
+
+    /// Get the configured topic of `component`, the data's topic if it isn't a group.
+    pub fn component_topic(&self, component: Option<&str>) -> Cow<'_, str> {
+        match component {
+            Some(component) => Cow::Owned(format!("{}/{}", self.topic(), component)),
+            None => Cow::Borrowed(self.topic()),
+        }
+    }
+
+    /// Get the client id of the data's own MQTT session, if it doesn't use the shared one.
+    pub fn client_id(&self) -> Option<&str> {
+        self.client_id.as_deref()
+    }
+
+    /// Get the name of the only broker the entry is published to, if it isn't published to all.
+    pub fn broker(&self) -> Option<&str> {
+        self.broker.as_deref()
+    }
+
+    /// Get the index of the entry within its fleet, 0 if it wasn't expanded.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Get the generator shared by the random values of the entry.
+    pub fn rng(&self) -> Arc<Mutex<Option<StdRng>>> {
+        Arc::clone(&self.rng)
+    }
+
+    /// Get the constants drawn for the device, by name.
+    pub fn constants(&self) -> Arc<BTreeMap<String, Constant>> {
+        Arc::clone(&self.baked)
+    }
+
+    /// Replace `token` in the topics and client id by `value`.
+    fn replace_token(&mut self, token: &str, value: &str) {
+        match &mut self.topic {
+            Topics::One(topic) => *topic = topic.replace(token, value),
+            Topics::Many(topics) => {
+                for topic in topics {
+                    *topic = topic.replace(token, value);
+                }
+            }
+        }
+        self.client_id = self.client_id.take().map(|id| id.replace(token, value));
+    }
+
+    /// Draw the constants and interval of the device from a generator seeded by `seed` plus its
+    /// fleet index.
+    ///
+    /// `{name}` in the topics and client id is replaced by the value of the constant `name`.
+    fn bake(&mut self, seed: Option<u64>) {
+        if self.constants.is_empty() && self.interval_range_ms.is_none() {
+            return;
+        }
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(self.index as u64)),
+            None => StdRng::from_entropy(),
+        };
+        let baked = self
+            .constants
+            .iter()
+            .map(|(name, range)| (name.clone(), range.draw(&mut rng)))
+            .collect::<BTreeMap<_, _>>();
+        for (name, value) in &baked {
+            self.replace_token(&format!("{{{}}}", name), &value.to_string());
+        }
+        self.baked = Arc::new(baked);
+        if let Some(range) = &self.interval_range_ms {
+            self.interval_ms = Some(rng.gen_range(range.min..=range.max));
+        }
+    }
+
+    /// Take over the state of the generators of `previous` that have the same type at the same place.
+    fn adopt_state(&mut self, previous: &Data) {
+        self.publishes = Arc::clone(&previous.publishes);
+        self.rng = Arc::clone(&previous.rng);
+        // devices keep their identity unless their constants changed
+        if self.constants == previous.constants {
+            self.baked = Arc::clone(&previous.baked);
+        }
+        match (&mut self.payload, &previous.payload) {
+            (Payload::Data(value), Payload::Data(old)) => value.adopt_state(old),
+            (Payload::Components(components), Payload::Components(old)) => {
+                for (component, value) in components {
+                    if let Some(old) = old.get(component) {
+                        value.adopt_state(old);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Pass the endianness and alignment of the entry on to its values that don't set their own.
+    fn inherit_defaults(&mut self) {
+        let endian = self.endian.unwrap_or_default();
+        let align = self.align.unwrap_or(1);
+        if let Some(checksum) = &mut self.checksum {
+            checksum.endian.get_or_insert(endian);
+        }
+        match &mut self.payload {
+            Payload::Data(value) => value.inherit(endian, align),
+            Payload::Components(components) => {
+                for value in components.values_mut() {
+                    value.inherit(endian, align);
+                }
+            }
+        }
+    }
+
+    /// Get whether identical consecutive payloads are skipped, if it overrides the global setting.
+    pub fn dedup(&self) -> Option<bool> {
+        self.dedup
+    }
+
+    /// Get the fraction of publishes to drop, if it overrides the global one.
+    pub fn drop_rate(&self) -> Option<f64> {
+        self.drop_rate
+    }
+
+    /// Get the time between publishes, if it overrides the global send interval.
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval_ms.map(Duration::from_millis)
+    }
+
+    /// Get the maximum random deviation from the interval, if it overrides the global jitter.
+    pub fn jitter(&self) -> Option<Duration> {
+        self.jitter_ms.map(Duration::from_millis)
+    }
+
+    /// Append the checksum of the entry to a serialized payload and compress it, if the entry sets
+    /// either.
+    pub fn finish_payload(&self, payload: &mut Vec<u8>) -> Result<(), io::Error> {
+        if let Some(checksum) = &self.checksum {
+            let mut sum = Vec::new();
+            checksum.write(payload, &mut sum)?;
+            payload.extend(sum);
+        }
+        self.compression.apply(payload)
+    }
+
+    /// Get the time before the first publish of the entry if it sets one.
+    pub fn phase(&self) -> Option<Duration> {
+        self.phase_ms.map(Duration::from_millis)
+    }
+
+    /// Get how publishes missed while falling behind are handled.
+    pub fn missed_ticks(&self) -> MissedTicks {
+        self.missed_ticks
+    }
+
+    /// Get the time publishes are held before they are sent, if it overrides the global delay.
+    pub fn send_delay(&self) -> Option<Duration> {
+        self.send_delay_ms.map(Duration::from_millis)
+    }
+
+    /// Get the probability of publishing the entry at `now`, 1 unless configured.
+    pub fn publish_probability(&self, now: SystemTime) -> f64 {
+        self.publish_probability
+            .as_ref()
+            .map_or(1., |probability| probability.at(now))
+    }
+
+    /// Check whether the entry is published at `now`, always unless it has an active window.
+    pub fn is_active(&self, now: SystemTime) -> bool {
+        self.active_window
+            .as_ref()
+            .is_none_or(|window| window.contains(now))
+    }
+
+    /// Check whether the data is published as retained messages.
+    pub fn retain(&self) -> bool {
+        self.retain
+    }
+
+    /// Get the QoS for the next publish.
+    ///
+    /// Advances through `qos_rotation` on every call, defaults to `qos` or `AtLeastOnce`.
+    pub fn next_qos(&self) -> QoS {
+        if self.qos_rotation.is_empty() {
+            return self.qos.map_or(QoS::AtLeastOnce, |qos| qos.0);
+        }
+        let n = self.publishes.fetch_add(1, Ordering::Relaxed);
+        self.qos_rotation[n % self.qos_rotation.len()].0
+    }
+
+    /// Get the topics and QoS levels to publish a payload on `topics` with.
+    ///
+    /// With `qos_fanout` the payload is published on `topic/qos<n>` for every QoS level, otherwise
+    /// once on every topic with the next QoS.
+    pub fn publish_targets(&self, topics: Vec<String>) -> Vec<(String, QoS)> {
+        if self.qos_fanout {
+            topics
+                .iter()
+                .flat_map(|topic| {
+                    [QoS::AtMostOnce, QoS::AtLeastOnce, QoS::ExactlyOnce]
+                        .iter()
+                        .map(move |qos| (format!("{}/qos{}", topic, *qos as u8), *qos))
+                })
+                .collect()
+        } else {
+            let qos = self.next_qos();
+            topics.into_iter().map(|topic| (topic, qos)).collect()
+        }
+    }
+
+    /// Get the topics to publish `payload` of `component` under.
+    ///
+    /// Substitutes the `{payload_hash}` token with the hash of the payload and appends the
+    /// component as a subtopic.
+    pub fn publish_topics(
+        &self,
+        component: Option<&str>,
+        payload: &[u8],
+        rng: &mut dyn RngCore,
+    ) -> Vec<String> {
+        self.topics()
+            .iter()
+            .map(|topic| {
+                let mut topic = Cow::Borrowed(topic.as_str());
+                if topic.contains(PAYLOAD_HASH_TOKEN) {
+                    let hash = self.payload_hash.digest(payload);
+                    topic = Cow::Owned(topic.replace(PAYLOAD_HASH_TOKEN, &hash));
+                }
+                if topic.contains(NONCE_TOKEN) {
+                    let nonce = self.nonce.generate(rng);
+                    topic = Cow::Owned(topic.replace(NONCE_TOKEN, &nonce));
+                }
+                match component {
+                    Some(component) => format!("{}/{}", topic, component),
+                    None => topic.into_owned(),
+                }
+            })
+            .collect()
+    }
+
+    /// Get the data's encoder, if it overrides the global one.
+    pub fn encoder(&self) -> Option<EncoderKind> {
+        self.encoder
+    }
+
+    /// Set the data's encoder to `encoder` unless it is already overridden.
+    pub fn resolve_encoder(&mut self, encoder: EncoderKind) {
+        self.encoder.get_or_insert(encoder);
+    }
+
+    /// Read the contents of file values, so publishing doesn't block on the file system.
+    fn read_files(&self) -> Result<(), io::Error> {
+        let mut result = Ok(());
+        for (_, top) in self.values() {
+            top.walk(&mut |value| {
+                if let (Ok(()), Value::File { path, bytes }) = (&result, value) {
+                    match std::fs::read(path) {
+                        Ok(contents) => {
+                            bytes.get_or_init(|| contents);
+                        }
+                        Err(e) => {
+                            result = Err(io::Error::new(
+                                e.kind(),
+                                format!("failed to read {}: {}", path.display(), e),
+                            ))
+                        }
+                    }
+                }
+            });
+        }
+        result.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", self.topic(), e)))
+    }
+
+    /// Check the values for errors that can't be detected while deserializing.
+    pub fn validate(&self) -> Result<(), io::Error> {
+        if self.topics().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "entry has an empty list of topics",
+            ));
+        }
+        for topic in self.topics() {
+            for (component, _) in self.values() {
+                let topic = match component {
+                    Some(component) => Cow::Owned(format!("{}/{}", topic, component)),
+                    None => Cow::Borrowed(topic.as_str()),
+                };
+                validate_topic(&topic, self.system_topic).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("topic {:?} {}", topic, e),
+                    )
+                })?;
+            }
+        }
+        if self.nonce.alphabet.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: nonce alphabet is empty", self.topic()),
+            ));
+        }
+        if self.qos.is_some() && !self.qos_rotation.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: qos and qos_rotation are mutually exclusive",
+                    self.topic()
+                ),
+            ));
+        }
+        if let Some(align) = self.align.filter(|align| !align.is_power_of_two()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: align {} is not a power of two", self.topic(), align),
+            ));
+        }
+        // sessions of their own only connect to the first broker
+        if self.client_id.is_some() && self.broker.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: client_id and broker are mutually exclusive",
+                    self.topic()
+                ),
+            ));
+        }
+        if self.repeat == Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: repeat is 0", self.topic()),
+            ));
+        }
+        if self.interval_ms == Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: interval_ms is 0", self.topic()),
+            ));
+        }
+        match &self.interval_range_ms {
+            Some(_) if self.interval_ms.is_some() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}: interval_ms and interval_range_ms are mutually exclusive",
+                        self.topic()
+                    ),
+                ));
+            }
+            Some(range) if range.min == 0 || range.min > range.max => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}: interval range {}..={} is empty or starts at 0",
+                        self.topic(),
+                        range.min,
+                        range.max
+                    ),
+                ));
+            }
+            _ => {}
+        }
+        for (name, range) in &self.constants {
+            range
+                .validate(name)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", self.topic(), e)))?;
+        }
+        if let Some(window) = self.active_window.as_ref().filter(|w| w.start == w.end) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: active window starts and ends at {}",
+                    self.topic(),
+                    String::from(window.start)
+                ),
+            ));
+        }
+        if let Some(probability) = &self.publish_probability {
+            probability
+                .validate()
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", self.topic(), e)))?;
+        }
+        let mut result = Ok(());
+        for (_, top) in self.values() {
+            top.walk(&mut |value| {
+                if result.is_err() {
+                    return;
+                }
+                match value {
+                    Value::Expr(expr) => result = expr.compile().map(drop),
+                    Value::SysFile(file) => result = file.check(),
+                    #[cfg(feature = "sqlite")]
+                    Value::Sqlite(source) => result = source.check(),
+                    Value::Signed(signed) => result = signed.check(),
+                    Value::Anomalous(anomalous) => result = anomalous.check(),
+                    Value::Frame {
+                        align: Some(align), ..
+                    } if !align.is_power_of_two() => {
+                        result = Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("frame align {} is not a power of two", align),
+                        ))
+                    }
+                    // without a BOM, which depends on the command line
+                    Value::String {
+                        value,
+                        encoding,
+                        framing,
+                    } => result = framing.write(value, *encoding, false, &mut io::sink()),
+                    Value::Samples {
+                        kind,
+                        values,
+                        width,
+                        ..
+                    } => result = kind.check(values, *width),
+                    Value::Tail(_) if !std::ptr::eq(value, top) => {
+                        result = Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "tail values can't be nested in other values",
+                        ))
+                    }
+                    Value::MonotonicTime {
+                        min_gap_ms,
+                        max_gap_ms,
+                        ..
+                    } if min_gap_ms > max_gap_ms => {
+                        result = Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "min_gap_ms {} exceeds max_gap_ms {}",
+                                min_gap_ms, max_gap_ms
+                            ),
+                        ))
+                    }
+                    Value::MovingAverage { window: 0, .. }
+                    | Value::Stats {
+                        stats: StatsWindow { window: 0, .. },
+                    } => result = Err(io::Error::new(io::ErrorKind::InvalidData, "window is 0")),
+                    Value::Waveform { period_ms: 0, .. } => {
+                        result = Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "waveform period_ms is 0",
+                        ))
+                    }
+                    Value::DutyCycle {
+                        on_ms: 0,
+                        off_ms: 0,
+                    } => {
+                        result = Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "duty cycle on_ms and off_ms are 0",
+                        ))
+                    }
+                    Value::LineProtocol { fields, .. } if fields.is_empty() => {
+                        result = Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "line protocol value has no fields",
+                        ))
+                    }
+                    Value::DateTime { format, .. }
+                        if StrftimeItems::new(format).any(|item| item == Item::Error) =>
+                    {
+                        result = Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("invalid date time format {:?}", format),
+                        ))
+                    }
+                    Value::LineProtocol {
+                        timestamp: Some(TimestampFormat::Rfc3339),
+                        ..
+                    } => {
+                        result = Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "line protocol timestamps must be unix times",
+                        ))
+                    }
+                    Value::FixedPoint {
+                        value,
+                        scale,
+                        width,
+                        ..
+                    } => result = fixed_point(*value, *scale, *width).map(drop),
+                    Value::Enum {
+                        value, variants, ..
+                    } if !variants.contains_key(value) => {
+                        result = Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "unknown variant {}, expected one of {}",
+                                value,
+                                variants.keys().cloned().collect::<Vec<_>>().join(", ")
+                            ),
+                        ))
+                    }
+                    Value::Bits { fields, width, .. } => {
+                        result = pack_bits(fields, *width).map(drop)
+                    }
+                    // out of range bytes don't parse as raw bytes and fall back to a JSON object
+                    Value::JSON(serde_json::Value::Object(map))
+                        if map.len() == 1 && map.contains_key("bytes") =>
+                    {
+                        if let Err(e) = Vec::<u8>::deserialize(&map["bytes"]) {
+                            result = Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("raw bytes: {}", e),
+                            ))
+                        }
+                    }
+                    Value::Phased { phases, .. } if phases.is_empty() => {
+                        result = Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "phased value has no phases",
+                        ))
+                    }
+                    Value::RandomWalk {
+                        start,
+                        step_stddev,
+                        min,
+                        max,
+                        ..
+                    } if !(min <= start
+                        && start <= max
+                        && min.is_finite()
+                        && max.is_finite()
+                        && *step_stddev >= 0.
+                        && step_stddev.is_finite()) =>
+                    {
+                        result = Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "random walk start {} outside of {}..={} or step_stddev {} negative or not finite",
+                                start, min, max, step_stddev
+                            ),
+                        ))
+                    }
+                    Value::RandomInt { min, max, .. } if min > max => {
+                        result = Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("random min {} exceeds max {}", min, max),
+                        ))
+                    }
+                    Value::RandomFloat { min, max, .. }
+                        if !(min <= max && min.is_finite() && max.is_finite()) =>
+                    {
+                        result = Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("random range {}..={} is empty or not finite", min, max),
+                        ))
+                    }
+                    Value::Flags {
+                        active,
+                        mapping,
+                        width,
+                        ..
+                    } => {
+                        if let Some(flag) = active.iter().find(|flag| !mapping.contains_key(*flag))
+                        {
+                            result = Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("unknown flag {}", flag),
+                            ))
+                        } else if let Some((flag, bit)) =
+                            mapping.iter().find(|(_, bit)| **bit as u32 >= width.bits())
+                        {
+                            result = Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!(
+                                    "bit {} of flag {} exceeds {} bit width",
+                                    bit,
+                                    flag,
+                                    width.bits()
+                                ),
+                            ))
+                        }
+                    }
+                    _ => (),
+                }
+            });
+        }
+        result.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", self.topic(), e)))
+    }
+}
+
+/// Syntax of a data file.
+#[derive(Copy, Clone, Debug)]
+pub enum Format {
+    Json,
+    Yaml,
+    /// TOML documents are tables, so the entries are an array of tables under `entries`.
+    Toml,
+}
+
+impl Format {
+    /// Detect the format from the extension of `path`, `None` if the extension isn't known.
+    pub fn from_path(path: &str) -> Option<Self> {
+        match Path::new(path).extension()?.to_str()? {
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "toml" => Some(Format::Toml),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TomlData<T> {
+    entries: Vec<T>,
+}
+
+/// Replace `${VAR}` and `${VAR:-default}` in `text` by the environment variable `VAR`.
+///
+/// Other uses of `$` are kept as-is, a variable that isn't set and has no default is an error.
+fn substitute_env(text: &str) -> Result<Cow<'_, str>, io::Error> {
+    if !text.contains("${") {
+        return Ok(Cow::Borrowed(text));
+    }
+    let mut substituted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        substituted.push_str(&rest[..start]);
+        let placeholder = &rest[start + 2..];
+        let inner = placeholder.find('}').map(|end| &placeholder[..end]);
+        let (name, default) = match inner {
+            Some(inner) => match inner.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (inner, None),
+            },
+            None => ("", None),
+        };
+        let is_name =
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        match inner {
+            Some(inner) if is_name => {
+                // like in shells the default also replaces an empty value
+                let value = match (std::env::var(name), default) {
+                    (Ok(value), Some(default)) if value.is_empty() => default.to_string(),
+                    (Ok(value), _) => value,
+                    (Err(std::env::VarError::NotPresent), Some(default)) => default.to_string(),
+                    (Err(e), _) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("environment variable {}: {}", name, e),
+                        ))
+                    }
+                };
+                substituted.push_str(&value);
+                rest = &placeholder[inner.len() + 1..];
+            }
+            _ => {
+                substituted.push_str("${");
+                rest = placeholder;
+            }
+        }
+    }
+    substituted.push_str(rest);
+    Ok(Cow::Owned(substituted))
+}
+
+/// Sample the standard normal distribution with the Box-Muller transform.
+fn normal<R: Rng>(rng: &mut R) -> f64 {
+    // 1 - x keeps the logarithm finite, gen yields values in [0, 1)
+    let radius = (-2. * (1. - rng.gen::<f64>()).ln()).sqrt();
+    radius * (std::f64::consts::TAU * rng.gen::<f64>()).cos()
+}
+
+/// Check that `topic` is a valid topic to publish to.
+fn validate_topic(topic: &str, system_topic: bool) -> Result<(), &'static str> {
+    if topic.is_empty() {
+        return Err("is empty");
+    }
+    if topic.contains(['+', '#']) {
+        return Err("contains a wildcard");
+    }
+    if topic.contains('\0') {
+        return Err("contains a null character");
+    }
+    if topic.starts_with('$') && !system_topic {
+        return Err("starts with $, which brokers reserve, set system_topic to allow it");
+    }
+    Ok(())
+}
+
+/// Parse and validate the entries of a data file.
+///
+/// The constants of the devices are drawn from `seed`, from entropy without one.
+pub fn load(text: &str, format: Format, seed: Option<u64>) -> Result<Vec<Data>, io::Error> {
+    fn invalid<E: std::fmt::Display>(e: E) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+    }
+
+    fn parse<T: serde::de::DeserializeOwned>(
+        text: &str,
+        format: Format,
+    ) -> Result<Vec<T>, io::Error> {
+        Ok(match format {
+            Format::Json => serde_json::from_str(text)?,
+            Format::Yaml => serde_yaml::from_str(text).map_err(invalid)?,
+            Format::Toml => {
+                toml::from_str::<TomlData<T>>(text)
+                    .map_err(invalid)?
+                    .entries
+            }
+        })
+    }
+
+    let text = &*substitute_env(text)?;
+    let mut vals = parse::<Data>(text, format)?;
+    for val in &mut vals {
+        val.inherit_defaults();
+        val.validate()?;
+    }
+    // copies of a fleet don't share the contents, so files are only read once they're expanded
+    let vals = expand(vals, seed)?;
+    for val in &vals {
+        val.read_files()?;
+    }
+    Ok(vals)
+}
+
+/// Resolve and serialize every value of `vals` once, like on their first publish.
+///
+/// Entries without their own encoder are set to `encoder`, so serializing them shows the
+/// resolved data.
+pub fn check(vals: &mut [Data], encoder: EncoderKind, context: &Context) -> Result<(), io::Error> {
+    context.averages.track(vals);
+    for val in vals {
+        val.resolve_encoder(encoder);
+        let encoder = val.encoder().unwrap_or(encoder).encoder();
+        let context = Context {
+            index: val.index(),
+            topic: Arc::from(val.topic()),
+            entry_rng: val.rng(),
+            constants: val.constants(),
+            ..context.clone()
+        };
+        let failed = |e: io::Error| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to serialize {}: {}", val.topic(), e),
+            )
+        };
+        for (component, value) in val.values() {
+            let mut buf = Vec::new();
+            let value = value.resolve(&context).map_err(failed)?;
+            Value::serialize(&value, encoder, &context, &mut buf)
+                .and_then(|()| val.finish_payload(&mut buf))
+                .map_err(failed)?;
+            if let Some(number) = value.number(&context) {
+                context
+                    .averages
+                    .record(&val.component_topic(component), number);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Carry the state of counters and other generators of `previous` entries over to reloaded ones.
+///
+/// Entries are matched by their topics, values only keep their state where their type is unchanged.
+pub fn migrate_state(previous: &[Data], vals: &mut [Data]) {
+    let mut by_topics = HashMap::new();
+    for old in previous {
+        by_topics.entry(old.topics()).or_insert(old);
+    }
+    for val in vals {
+        if let Some(old) = by_topics.get(val.topics()) {
+            val.adopt_state(old);
+        }
+    }
+}
+
+/// Merge the entries of an `overlay` data file into the ones of the files before it.
+///
+/// An overlay entry replaces the first entry with the same topics it didn't replace yet, in place
+/// so it keeps its schedule. Entries with new topics are appended.
+pub fn merge(mut vals: Vec<Data>, overlay: Vec<Data>) -> Vec<Data> {
+    let base = vals.len();
+    let mut replaced = vec![false; base];
+    for val in overlay {
+        let position = (0..base).find(|&i| !replaced[i] && vals[i].topics() == val.topics());
+        match position {
+            Some(i) => {
+                replaced[i] = true;
+                vals[i] = val;
+            }
+            None => vals.push(val),
+        }
+    }
+    vals
+}
+
+/// Topics of the entries that differ between two revisions of the data.
+#[derive(Debug, Default)]
+pub struct Changes {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for Changes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut sep = "";
+        for (label, topics) in [
+            ("added", &self.added),
+            ("removed", &self.removed),
+            ("changed", &self.changed),
+        ] {
+            if !topics.is_empty() {
+                write!(f, "{}{}: {}", sep, label, topics.join(", "))?;
+                sep = "; ";
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compare the entries of `vals` against `previous` by their topics and serialized values.
+///
+/// Entries are expected to have adopted the state of their predecessors, so only changes to the
+/// configuration are reported.
+pub fn changes(previous: &[Data], vals: &[Data]) -> Changes {
+    let mut old = HashMap::new();
+    for val in previous {
+        old.entry(val.topics())
+            .or_insert_with(|| serde_json::to_value(val).ok());
+    }
+    let mut changes = Changes::default();
+    let mut seen = HashSet::new();
+    for val in vals {
+        if !seen.insert(val.topics()) {
+            continue;
+        }
+        let label = val.topics().join(",");
+        match old.get(val.topics()) {
+            None => changes.added.push(label),
+            Some(before) if *before != serde_json::to_value(val).ok() => {
+                changes.changed.push(label)
+            }
+            Some(_) => {}
+        }
+    }
+    for val in previous {
+        if seen.insert(val.topics()) {
+            changes.removed.push(val.topics().join(","));
+        }
+    }
+    changes
+}
+
+/// Replace the entries with a `repeat` count by a fleet of that many copies.
+///
+/// `{i}` in the topics and client id of a copy is replaced by its index within the fleet. Every
+/// entry draws its constants and interval afterwards, seeded by `seed` plus its index.
+fn expand(vals: Vec<Data>, seed: Option<u64>) -> Result<Vec<Data>, io::Error> {
+    let mut expanded = Vec::with_capacity(vals.len());
+    for mut val in vals {
+        let repeat = match val.repeat {
+            Some(repeat) => repeat,
+            None => {
+                val.bake(seed);
+                expanded.push(val);
+                continue;
+            }
+        };
+        // copies are deserialized anew so they don't share the state of counters and the like
+        let template = serde_json::to_value(&val)?;
+        for index in 0..repeat {
+            let mut copy = serde_json::from_value::<Data>(template.clone())?;
+            copy.replace_token(INDEX_TOKEN, &index.to_string());
+            copy.repeat = None;
+            copy.index = index;
+            copy.bake(seed);
+            expanded.push(copy);
+        }
+    }
+    Ok(expanded)
+}
+
+/// One or several topics an entry is published to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Topics {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// The published value of a `Data` entry.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Payload {
+    /// A single value published under the entry's topic.
+    Data(Value),
+    /// Values published together under subtopics of the entry's topic.
+    Components(BTreeMap<String, Value>),
+}
+
+/// MQTT QoS level, configured either as `0`, `1`, `2` or by name.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+#[serde(try_from = "QosLevel", into = "u8")]
+pub struct Qos(pub QoS);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum QosLevel {
+    Level(u8),
+    Name(String),
+}
+
+impl TryFrom<QosLevel> for Qos {
+    type Error = String;
+
+    fn try_from(level: QosLevel) -> Result<Self, Self::Error> {
+        let qos = match level {
+            QosLevel::Level(0) => QoS::AtMostOnce,
+            QosLevel::Level(1) => QoS::AtLeastOnce,
+            QosLevel::Level(2) => QoS::ExactlyOnce,
+            QosLevel::Name(name) if name == "AtMostOnce" => QoS::AtMostOnce,
+            QosLevel::Name(name) if name == "AtLeastOnce" => QoS::AtLeastOnce,
+            QosLevel::Name(name) if name == "ExactlyOnce" => QoS::ExactlyOnce,
+            QosLevel::Level(level) => {
+                return Err(format!("invalid QoS {}, expected 0, 1 or 2", level))
+            }
+            QosLevel::Name(name) => {
+                return Err(format!(
+                    "invalid QoS {}, expected AtMostOnce, AtLeastOnce or ExactlyOnce",
+                    name
+                ))
+            }
+        };
+        Ok(Qos(qos))
+    }
+}
+
+impl std::str::FromStr for Qos {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let level = match s.parse() {
+            Ok(level) => QosLevel::Level(level),
+            Err(_) => QosLevel::Name(s.to_string()),
+        };
+        Qos::try_from(level)
+    }
+}
+
+impl From<Qos> for u8 {
+    fn from(qos: Qos) -> Self {
+        qos.0 as u8
+    }
+}
+
+const PAYLOAD_HASH_TOKEN: &str = "{payload_hash}";
+const INDEX_TOKEN: &str = "{i}";
+const NONCE_TOKEN: &str = "{nonce}";
+
+/// Hash substituted for `{payload_hash}` in topics.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PayloadHash {
+    #[serde(default)]
+    algorithm: HashAlgorithm,
+    /// Number of leading hex digits to keep.
+    #[serde(default)]
+    length: Option<usize>,
+}
+
+impl PayloadHash {
+    fn digest(&self, payload: &[u8]) -> String {
+        let mut digest = match self.algorithm {
+            HashAlgorithm::Sha1 => hex::encode(Sha1::digest(payload)),
+            HashAlgorithm::Sha256 => hex::encode(Sha256::digest(payload)),
+        };
+        if let Some(length) = self.length {
+            digest.truncate(length);
+        }
+        digest
+    }
+}
+
+/// Handling of publishes that are missed because the sender fell behind, like tokio's
+/// `MissedTickBehavior`.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub enum MissedTicks {
+    /// Publish the missed ticks right away until the schedule caught up.
+    Burst,
+    /// Publish once and continue one interval after the late publish.
+    #[default]
+    Delay,
+    /// Publish once and continue at the next tick of the original schedule.
+    Skip,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+/// Probability of publishing an entry on a tick.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Probability {
+    Constant(f64),
+    /// Curve repeating every `period_s` seconds of wall clock time, `points` pair offsets into
+    /// the period with the probability at that offset.
+    Curve {
+        period_s: u64,
+        points: Vec<(f64, f64)>,
+    },
+}
+
+impl Probability {
+    /// Get the probability at `now`, linearly interpolated between the points of a curve.
+    ///
+    /// Curves wrap around, between the last and the first point the probability moves towards
+    /// the first point of the next period.
+    pub fn at(&self, now: SystemTime) -> f64 {
+        let (period, points) = match self {
+            Probability::Constant(probability) => return *probability,
+            Probability::Curve { period_s, points } => (*period_s as f64, points),
+        };
+        let now = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let offset = now % period;
+        let next = points.iter().position(|(at, _)| *at > offset);
+        let (from, to) = match next {
+            Some(0) => {
+                let (at, probability) = points[points.len() - 1];
+                ((at - period, probability), points[0])
+            }
+            Some(next) => (points[next - 1], points[next]),
+            None => {
+                let (at, probability) = points[0];
+                (points[points.len() - 1], (at + period, probability))
+            }
+        };
+        if to.0 == from.0 {
+            return to.1;
+        }
+        from.1 + (to.1 - from.1) * (offset - from.0) / (to.0 - from.0)
+    }
+
+    fn validate(&self) -> Result<(), io::Error> {
+        let invalid = |msg: String| Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        let in_range = |probability: f64| (0. ..=1.).contains(&probability);
+        match self {
+            Probability::Constant(probability) if !in_range(*probability) => invalid(format!(
+                "publish probability {} is not between 0 and 1",
+                probability
+            )),
+            Probability::Constant(_) => Ok(()),
+            Probability::Curve { period_s: 0, .. } => {
+                invalid("publish probability period is 0".to_string())
+            }
+            Probability::Curve { points, .. } if points.is_empty() => {
+                invalid("publish probability curve has no points".to_string())
+            }
+            Probability::Curve { period_s, points } => {
+                let mut previous = 0.;
+                for (at, probability) in points {
+                    if *at < previous || *at > *period_s as f64 {
+                        return invalid(format!(
+                            "publish probability offset {} is out of order or beyond the period",
+                            at
+                        ));
+                    }
+                    if !in_range(*probability) {
+                        return invalid(format!(
+                            "publish probability {} is not between 0 and 1",
+                            probability
+                        ));
+                    }
+                    previous = *at;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Range a constant of every device is drawn from.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConstantRange {
+    min: f64,
+    max: f64,
+    /// Draw a whole number between `min` and `max`, both included.
+    #[serde(default)]
+    integer: bool,
+}
+
+impl ConstantRange {
+    fn validate(&self, name: &str) -> Result<(), io::Error> {
+        let invalid = |msg: String| Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        let identifier = name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !identifier {
+            return invalid(format!("constant {:?} is not a valid name", name));
+        }
+        // the names expressions and topics already refer to
+        if ["i", "t", "seq", "nonce", "payload_hash"].contains(&name) {
+            return invalid(format!("constant {} shadows a built-in name", name));
+        }
+        let empty = if self.integer {
+            self.min.ceil() > self.max.floor()
+        } else {
+            self.min >= self.max
+        };
+        if empty {
+            return invalid(format!(
+                "constant {} has an empty range from {} to {}",
+                name, self.min, self.max
+            ));
+        }
+        Ok(())
+    }
+
+    fn draw(&self, rng: &mut StdRng) -> Constant {
+        if self.integer {
+            Constant::Int(rng.gen_range(self.min.ceil() as i64..=self.max.floor() as i64))
+        } else {
+            Constant::Float(rng.gen_range(self.min..self.max))
+        }
+    }
+}
+
+/// Milliseconds the interval of every device is drawn from, both included.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub struct IntervalRange {
+    min: u64,
+    max: u64,
+}
+
+/// Constant of a device, drawn once when its entry was loaded.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Int(value) => write!(f, "{}", value),
+            Constant::Float(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Time of day an entry is published in, from `start` until before `end`.
+///
+/// Windows ending before they start cross midnight.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub struct ActiveWindow {
+    start: TimeOfDay,
+    end: TimeOfDay,
+    /// Offset of the times from UTC, e.g. `+02:00`.
+    #[serde(default)]
+    utc_offset: UtcOffset,
+}
+
+impl ActiveWindow {
+    fn contains(&self, now: SystemTime) -> bool {
+        let now = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let time = (now + self.utc_offset.0 as i64).rem_euclid(86400) as u32;
+        let (start, end) = (self.start.0, self.end.0);
+        if start <= end {
+            (start..end).contains(&time)
+        } else {
+            time >= start || time < end
+        }
+    }
+}
+
+/// Seconds since midnight, configured as `HH:MM` or `HH:MM:SS`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay(u32);
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(time: String) -> Result<Self, Self::Error> {
+        let mut parts = time.split(':').map(str::parse::<u32>);
+        let secs = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(h)), Some(Ok(m)), s, None) if h < 24 && m < 60 => match s {
+                None => Some(h * 3600 + m * 60),
+                Some(Ok(s)) if s < 60 => Some(h * 3600 + m * 60 + s),
+                Some(_) => None,
+            },
+            _ => None,
+        };
+        secs.map(TimeOfDay)
+            .ok_or_else(|| format!("invalid time of day {}, expected HH:MM or HH:MM:SS", time))
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> Self {
+        let (h, m, s) = (time.0 / 3600, time.0 / 60 % 60, time.0 % 60);
+        if s == 0 {
+            format!("{:02}:{:02}", h, m)
+        } else {
+            format!("{:02}:{:02}:{:02}", h, m, s)
+        }
+    }
+}
+
+/// Offset from UTC in seconds, configured as `+HH:MM` or `-HH:MM`.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct UtcOffset(i32);
+
+impl TryFrom<String> for UtcOffset {
+    type Error = String;
+
+    fn try_from(offset: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid UTC offset {}, expected +HH:MM or -HH:MM", offset);
+        let (sign, time) = match offset.split_at_checked(1) {
+            Some(("+", time)) => (1, time),
+            Some(("-", time)) => (-1, time),
+            _ => return Err(invalid()),
+        };
+        match TimeOfDay::try_from(time.to_string()) {
+            Ok(TimeOfDay(secs)) if time.len() == 5 => Ok(UtcOffset(sign * secs as i32)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl From<UtcOffset> for String {
+    fn from(offset: UtcOffset) -> Self {
+        let sign = if offset.0 < 0 { '-' } else { '+' };
+        let secs = offset.0.unsigned_abs();
+        format!("{}{:02}:{:02}", sign, secs / 3600, secs / 60 % 60)
+    }
+}
+
+/// Random string substituted for `{nonce}` in topics.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Nonce {
+    #[serde(default = "Nonce::default_length")]
+    length: usize,
+    #[serde(default = "Nonce::default_alphabet")]
+    alphabet: String,
+}
+
+impl Default for Nonce {
+    fn default() -> Self {
+        Nonce {
+            length: Nonce::default_length(),
+            alphabet: Nonce::default_alphabet(),
+        }
+    }
+}
+
+impl Nonce {
+    fn default_length() -> usize {
+        8
+    }
+
+    fn default_alphabet() -> String {
+        "0123456789abcdefghijklmnopqrstuvwxyz".to_string()
+    }
+
+    fn generate(&self, rng: &mut dyn RngCore) -> String {
+        let alphabet = self.alphabet.chars().collect::<Vec<_>>();
+        (0..self.length)
+            .map(|_| alphabet[rng.gen_range(0..alphabet.len())])
+            .collect()
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Bool(bool),
+    TextNumber {
+        value: f64,
+        decimals: usize,
+        #[serde(default)]
+        pad: usize,
+        #[serde(default)]
+        sign: bool,
+        #[serde(default)]
+        encoding: StringEncoding,
+        #[serde(default)]
+        bom: Option<bool>,
+    },
+    /// Number published as a signed integer scaled by `scale`, e.g. 23.5 as 235 with a scale of 10.
+    ///
+    /// Listed before integers, which would accept an integral `value` and ignore the scale.
+    FixedPoint {
+        value: f64,
+        scale: f64,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: IntWidth,
+    },
+    /// Decimal strings are only integers with a `width` of 128, otherwise they stay strings.
+    #[serde(deserialize_with = "wide_uint::deserialize")]
+    UInt {
+        #[serde(serialize_with = "wide_uint::serialize")]
+        value: u128,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: IntWidth,
+    },
+    #[serde(deserialize_with = "wide_int::deserialize")]
+    Int {
+        #[serde(serialize_with = "wide_int::serialize")]
+        value: i128,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: IntWidth,
+    },
+    Float {
+        value: f64,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: FloatWidth,
+        #[serde(default)]
+        rounding: Rounding,
+        #[serde(default)]
+        denormals: Denormals,
+        /// Fail the publish instead of sending NaN or an infinity.
+        #[serde(default)]
+        require_finite: bool,
+    },
+    /// Named variant published as the signed integer it maps to.
+    ///
+    /// Listed before strings, which would accept the variant name and ignore the mapping.
+    Enum {
+        value: String,
+        variants: BTreeMap<String, i64>,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: IntWidth,
+    },
+    String {
+        value: String,
+        #[serde(default)]
+        encoding: StringEncoding,
+        #[serde(default)]
+        framing: Framing,
+    },
+    /// Raw bytes written verbatim.
+    Bytes {
+        bytes: Vec<u8>,
+    },
+    Array(Vec<Value>),
+    /// Array preceded by its item count or byte length.
+    PrefixedArray {
+        items: Vec<Value>,
+        #[serde(default)]
+        prefix: ArrayPrefix,
+        #[serde(default)]
+        endian: Option<Endian>,
+    },
+    Uptime {
+        unit: TimeUnit,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: IntWidth,
+    },
+    Index {
+        index: IntFormat,
+    },
+    /// Number of times the simulator reconnected to the broker.
+    ReconnectCount {
+        reconnects: IntFormat,
+    },
+    /// Number of times the data was reloaded since the simulator started.
+    ReloadCount {
+        reloads: IntFormat,
+    },
+    /// State of the connection to the broker as an unsigned integer code.
+    ConnectionState {
+        connection_state: StateCodes,
+    },
+    ModbusRegisters {
+        registers: Vec<Register>,
+        #[serde(default)]
+        word_order: WordOrder,
+    },
+    Samples {
+        kind: SampleKind,
+        values: Vec<f64>,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: IntWidth,
+    },
+    MonotonicTime {
+        min_gap_ms: u64,
+        max_gap_ms: u64,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: IntWidth,
+        /// Last published timestamp, 0 before the first publish.
+        #[serde(skip)]
+        last_ms: Arc<AtomicU64>,
+    },
+    MovingAverage {
+        source: String,
+        window: usize,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: FloatWidth,
+    },
+    /// Number drifting by a normally distributed step on every publish, clamped to `min..=max`.
+    ///
+    /// Listed before random numbers, which would accept its range and ignore the walk.
+    RandomWalk {
+        start: f64,
+        step_stddev: f64,
+        min: f64,
+        max: f64,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: FloatWidth,
+        /// Last published value, `None` before the first publish.
+        #[serde(skip)]
+        current: Arc<Mutex<Option<f64>>>,
+    },
+    RandomInt {
+        min: i64,
+        max: i64,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: IntWidth,
+    },
+    RandomFloat {
+        min: f64,
+        max: f64,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: FloatWidth,
+    },
+    RandomBytes {
+        length: usize,
+        /// Seed of the bytes, derived from `--seed` and the topic without one.
+        #[serde(default)]
+        seed: Option<u64>,
+        /// Generator of the bytes, seeded on the first publish.
+        #[serde(skip)]
+        rng: Arc<Mutex<Option<StdRng>>>,
+    },
+    Counter {
+        start: u64,
+        #[serde(default = "Value::default_step")]
+        step: u64,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: IntWidth,
+        /// Offset of `start` per fleet index, so the devices of a fleet count in different ranges.
+        #[serde(default)]
+        index_step: u64,
+        /// Number of publishes so far.
+        #[serde(skip)]
+        publishes: Arc<AtomicU64>,
+    },
+    Waveform {
+        shape: Shape,
+        amplitude: f64,
+        #[serde(default)]
+        offset: f64,
+        period_ms: u64,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: FloatWidth,
+    },
+    /// Boolean that is true for `on_ms` and then false for `off_ms`, repeating.
+    DutyCycle {
+        on_ms: u64,
+        off_ms: u64,
+    },
+    Phased {
+        phases: Vec<Phase>,
+        /// Number of publishes so far.
+        #[serde(skip)]
+        publishes: Arc<AtomicUsize>,
+    },
+    Stats {
+        stats: StatsWindow,
+    },
+    /// Fresh UUID on every publish.
+    Uuid {
+        version: UuidVersion,
+        #[serde(default)]
+        format: UuidFormat,
+        /// Little endian bytes use the mixed-endian layout of Microsoft GUIDs.
+        #[serde(default)]
+        endian: Option<Endian>,
+    },
+    Timestamp {
+        format: TimestampFormat,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: IntWidth,
+    },
+    /// Current time formatted as a string with a strftime `format`, listed after timestamps with
+    /// their named formats.
+    DateTime {
+        format: String,
+        #[serde(default)]
+        timezone: TimeZone,
+        #[serde(default)]
+        encoding: StringEncoding,
+        #[serde(default)]
+        framing: Framing,
+    },
+    Flags {
+        active: Vec<String>,
+        /// Bit position of every flag name, counted from the end given by `bit_order`.
+        mapping: BTreeMap<String, u8>,
+        #[serde(default)]
+        bit_order: BitOrder,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: IntWidth,
+    },
+    /// Unsigned integer packed from bit fields.
+    Bits {
+        fields: Vec<BitField>,
+        #[serde(default)]
+        endian: Option<Endian>,
+        #[serde(default)]
+        width: IntWidth,
+    },
+    #[cfg(feature = "http")]
+    Http(crate::http::HttpSource),
+    SysFile(crate::sysfile::SysFile),
+    #[cfg(feature = "sqlite")]
+    Sqlite(crate::sqlite::SqliteSource),
+    /// Raw bytes of a file, read when the data file is loaded.
+    File {
+        path: PathBuf,
+        #[serde(skip)]
+        bytes: Arc<OnceLock<Vec<u8>>>,
+    },
+    Expr(crate::expr::Expr),
+    Frame {
+        fields: Vec<Value>,
+        checksum: Checksum,
+        /// Maximum alignment of the fields, 1 packs them without padding.
+        #[serde(default)]
+        align: Option<usize>,
+    },
+    PackedStruct {
+        size: usize,
+        fields: Vec<PackedField>,
+    },
+    Encrypted(crate::crypto::Encrypted),
+    /// Listed after encrypted values, which also have an `algorithm`, `key` and `inner` value.
+    Signed(crate::crypto::Signed),
+    Tail(crate::tail::Tail),
+    Anomalous(crate::anomaly::Anomalous),
+    /// Line of the InfluxDB line protocol, written as a string.
+    LineProtocol {
+        measurement: String,
+        #[serde(default)]
+        tags: BTreeMap<String, String>,
+        fields: BTreeMap<String, Value>,
+        /// Format of the current time appended to the line, the database assigns it if unset.
+        #[serde(default)]
+        timestamp: Option<TimestampFormat>,
+        #[serde(default)]
+        encoding: StringEncoding,
+        #[serde(default)]
+        framing: Framing,
+    },
+    /// JSON-shaped value published as CBOR.
+    Cbor {
+        cbor: serde_json::Value,
+    },
+    /// JSON-shaped value published as MessagePack.
+    MsgPack {
+        msgpack: serde_json::Value,
+    },
+    EncodedJSON {
+        json: serde_json::Value,
+        #[serde(default)]
+        encoding: StringEncoding,
+        #[serde(default)]
+        bom: Option<bool>,
+        #[serde(default)]
+        length_prefix: Option<LengthPrefix>,
+        /// Indent the JSON instead of writing it compactly.
+        #[serde(default)]
+        pretty: bool,
+    },
+    JSON(serde_json::Value),
+}
+
+impl Value {
+    fn default_step() -> u64 {
+        1
+    }
+
+    pub fn serialize<W>(
+        &self,
+        encoder: &dyn Encoder,
+        context: &Context,
+        writer: &mut W,
+    ) -> Result<(), io::Error>
+    where
+        W: Write,
+    {
+        encoder.encode(self, context, writer)
+    }
+
+    /// Get the natural alignment of a resolved value within a frame, its size for numbers.
+    pub fn alignment(&self) -> usize {
+        match self {
+            Value::UInt { width, .. } | Value::Int { width, .. } => width.bits() as usize / 8,
+            Value::Float {
+                width: FloatWidth::Thirtytwo,
+                ..
+            } => 4,
+            Value::Float {
+                width: FloatWidth::Sixtyfour,
+                ..
+            } => 8,
+            _ => 1,
+        }
+    }
+
+    /// Evaluate a computed value into the value that is published, other values are borrowed.
+    pub fn resolve(&self, context: &Context) -> Result<Cow<'_, Value>, io::Error> {
+        let value = match self {
+            #[cfg(feature = "http")]
+            Value::Http(source) => source.value()?,
+            Value::SysFile(file) => file.value()?,
+            #[cfg(feature = "sqlite")]
+            Value::Sqlite(source) => source.value()?,
+            Value::Expr(expr) => expr.value(context)?,
+            Value::MonotonicTime {
+                min_gap_ms,
+                max_gap_ms,
+                endian,
+                width,
+                last_ms,
+            } => {
+                let gap = context.random(|rng| rng.gen_range(*min_gap_ms..=*max_gap_ms));
+                let now = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64;
+                // the clock starts at the current time and only advances by the gaps after that
+                let previous = last_ms
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                        Some(if last == 0 { now } else { last + gap })
+                    })
+                    .unwrap();
+                Value::UInt {
+                    value: if previous == 0 { now } else { previous + gap }.into(),
+                    endian: *endian,
+                    width: *width,
+                }
+            }
+            Value::MovingAverage {
+                source,
+                window,
+                endian,
+                width,
+            } => Value::Float {
+                value: context.averages.mean(source, *window).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("No values of {} to average yet", source),
+                    )
+                })?,
+                endian: *endian,
+                width: *width,
+                rounding: Rounding::default(),
+                denormals: Denormals::default(),
+                require_finite: false,
+            },
+            Value::FixedPoint {
+                value,
+                scale,
+                endian,
+                width,
+            } => Value::Int {
+                value: fixed_point(*value, *scale, *width)?,
+                endian: *endian,
+                width: *width,
+            },
+            Value::Enum {
+                value,
+                variants,
+                endian,
+                width,
+            } => Value::Int {
+                value: (*variants.get(value).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unknown variant {}", value),
+                    )
+                })?)
+                .into(),
+                endian: *endian,
+                width: *width,
+            },
+            Value::RandomWalk {
+                start,
+                step_stddev,
+                min,
+                max,
+                endian,
+                width,
+                current,
+            } => {
+                let mut current = current.lock().unwrap();
+                let value = match *current {
+                    None => *start,
+                    Some(previous) => {
+                        (previous + context.random(normal) * step_stddev).clamp(*min, *max)
+                    }
+                };
+                *current = Some(value);
+                Value::Float {
+                    value,
+                    endian: *endian,
+                    width: *width,
+                    rounding: Rounding::default(),
+                    denormals: Denormals::default(),
+                    require_finite: false,
+                }
+            }
+            Value::RandomInt {
+                min,
+                max,
+                endian,
+                width,
+            } => Value::Int {
+                value: context.random(|rng| rng.gen_range(*min..=*max)).into(),
+                endian: *endian,
+                width: *width,
+            },
+            Value::RandomFloat {
+                min,
+                max,
+                endian,
+                width,
+            } => Value::Float {
+                value: context.random(|rng| rng.gen_range(*min..=*max)),
+                endian: *endian,
+                width: *width,
+                rounding: Rounding::default(),
+                denormals: Denormals::default(),
+                require_finite: false,
+            },
+            Value::Flags {
+                active,
+                mapping,
+                bit_order,
+                endian,
+                width,
+            } => Value::UInt {
+                value: active
+                    .iter()
+                    .filter_map(|flag| mapping.get(flag))
+                    .map(|bit| match bit_order {
+                        BitOrder::LsbFirst => *bit as u32,
+                        BitOrder::MsbFirst => width.bits() - 1 - *bit as u32,
+                    })
+                    .fold(0, |mask, bit| mask | 1 << bit),
+                endian: *endian,
+                width: *width,
+            },
+            Value::Bits {
+                fields,
+                endian,
+                width,
+            } => Value::UInt {
+                value: pack_bits(fields, *width)?,
+                endian: *endian,
+                width: *width,
+            },
+            Value::Uuid {
+                version,
+                format,
+                endian,
+            } => {
+                let random = context.random(|rng| rng.gen::<[u8; 16]>());
+                let uuid = match version {
+                    UuidVersion::V4 => uuid::Builder::from_random_bytes(random),
+                    UuidVersion::V7 => {
+                        let millis = SystemTime::now()
+                            .duration_since(SystemTime::UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_millis() as u64;
+                        let mut rest = [0; 10];
+                        rest.copy_from_slice(&random[..10]);
+                        uuid::Builder::from_unix_timestamp_millis(millis, &rest)
+                    }
+                }
+                .into_uuid();
+                let bytes = match (format, endian.unwrap_or_default()) {
+                    (UuidFormat::String, _) => {
+                        return Ok(Cow::Owned(Value::String {
+                            value: uuid.hyphenated().to_string(),
+                            encoding: StringEncoding::UTF8,
+                            framing: Framing::default(),
+                        }))
+                    }
+                    (UuidFormat::Bytes, Endian::BigEndian) => uuid.into_bytes(),
+                    (UuidFormat::Bytes, Endian::LittleEndian) => uuid.to_bytes_le(),
+                };
+                Value::Array(
+                    bytes
+                        .iter()
+                        .map(|b| Value::UInt {
+                            value: (*b).into(),
+                            endian: None,
+                            width: IntWidth::Eight,
+                        })
+                        .collect(),
+                )
+            }
+            Value::Timestamp {
+                format,
+                endian,
+                width,
+            } => {
+                let now = SystemTime::now();
+                let since_epoch = now
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default();
+                let value = match format {
+                    TimestampFormat::UnixSecs => since_epoch.as_secs().into(),
+                    TimestampFormat::UnixMillis => since_epoch.as_millis(),
+                    TimestampFormat::UnixNanos => since_epoch.as_nanos(),
+                    TimestampFormat::Rfc3339 => {
+                        return Ok(Cow::Owned(Value::String {
+                            value: humantime::format_rfc3339_millis(now).to_string(),
+                            encoding: StringEncoding::UTF8,
+                            framing: Framing::default(),
+                        }))
+                    }
+                };
+                Value::UInt {
+                    value,
+                    endian: *endian,
+                    width: *width,
+                }
+            }
+            Value::DateTime {
+                format,
+                timezone,
+                encoding,
+                framing,
+            } => {
+                let now = chrono::Utc::now();
+                let items = StrftimeItems::new(format);
+                let value = match timezone {
+                    TimeZone::Utc => now.format_with_items(items).to_string(),
+                    TimeZone::Local => now
+                        .with_timezone(&chrono::Local)
+                        .format_with_items(items)
+                        .to_string(),
+                };
+                Value::String {
+                    value,
+                    encoding: *encoding,
+                    framing: framing.clone(),
+                }
+            }
+            Value::Stats { stats } => {
+                let (min, max, mean) = context
+                    .averages
+                    .summary(&stats.source, stats.window)
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("No values of {} for stats yet", stats.source),
+                        )
+                    })?;
+                Value::JSON(serde_json::json!({ "min": min, "max": max, "mean": mean }))
+            }
+            Value::Counter {
+                start,
+                step,
+                index_step,
+                endian,
+                width,
+                publishes,
+            } => Value::UInt {
+                value: start
+                    .wrapping_add(index_step.wrapping_mul(context.index as u64))
+                    .wrapping_add(
+                        publishes
+                            .fetch_add(1, Ordering::Relaxed)
+                            .wrapping_mul(*step),
+                    )
+                    .into(),
+                endian: *endian,
+                width: *width,
+            },
+            Value::Waveform {
+                shape,
+                amplitude,
+                offset,
+                period_ms,
+                endian,
+                width,
+            } => {
+                let phase =
+                    (context.start.elapsed().as_secs_f64() * 1000. / *period_ms as f64).fract();
+                Value::Float {
+                    value: offset + amplitude * shape.at(phase),
+                    endian: *endian,
+                    width: *width,
+                    rounding: Rounding::default(),
+                    denormals: Denormals::default(),
+                    require_finite: false,
+                }
+            }
+            Value::DutyCycle { on_ms, off_ms } => {
+                let elapsed = context.start.elapsed().as_millis() as u64;
+                Value::Bool(elapsed % (on_ms + off_ms) < *on_ms)
+            }
+            Value::LineProtocol {
+                measurement,
+                tags,
+                fields,
+                timestamp,
+                encoding,
+                framing,
+            } => {
+                let mut line = escape_line(measurement, &[',', ' ']);
+                for (key, value) in tags {
+                    line.push(',');
+                    line.push_str(&escape_line(key, &[',', '=', ' ']));
+                    line.push('=');
+                    line.push_str(&escape_line(value, &[',', '=', ' ']));
+                }
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    line.push(if i == 0 { ' ' } else { ',' });
+                    line.push_str(&escape_line(key, &[',', '=', ' ']));
+                    line.push('=');
+                    line.push_str(&line_field(key, &*value.resolve(context)?)?);
+                }
+                if let Some(format) = timestamp {
+                    let since_epoch = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or_default();
+                    let time = match format {
+                        TimestampFormat::UnixSecs => since_epoch.as_secs().into(),
+                        TimestampFormat::UnixMillis => since_epoch.as_millis(),
+                        TimestampFormat::UnixNanos | TimestampFormat::Rfc3339 => {
+                            since_epoch.as_nanos()
+                        }
+                    };
+                    line.push_str(&format!(" {}", time));
+                }
+                Value::String {
+                    value: line,
+                    encoding: *encoding,
+                    framing: framing.clone(),
+                }
+            }
+            // nested in other values a dropout can't skip the publish
+            Value::Anomalous(anomalous) => {
+                return anomalous.apply(anomalous.draw(context), context)
+            }
+            Value::Phased { phases, publishes } => {
+                let mut remaining = publishes.fetch_add(1, Ordering::Relaxed);
+                // the last phase is held once the others are done
+                let phase = phases
+                    .iter()
+                    .find(|phase| {
+                        let current = remaining < phase.count;
+                        remaining = remaining.saturating_sub(phase.count);
+                        current
+                    })
+                    .or_else(|| phases.last())
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "phased value has no phases")
+                    })?;
+                return phase.value.resolve(context);
+            }
+            value => return Ok(Cow::Borrowed(value)),
+        };
+        Ok(Cow::Owned(value))
+    }
+
+    /// Evaluate the value into the values to publish on this tick.
+    ///
+    /// A tail yields a value per new line of its file, any other value yields itself resolved.
+    pub fn resolve_all(&self, context: &Context) -> Result<Vec<Cow<'_, Value>>, io::Error> {
+        match self {
+            Value::Tail(tail) => Ok(tail.read_lines()?.into_iter().map(Cow::Owned).collect()),
+            Value::Anomalous(anomalous) => match anomalous.draw(context) {
+                Anomaly::Dropout => Ok(Vec::new()),
+                anomaly => Ok(vec![anomalous.apply(anomaly, context)?]),
+            },
+            // a source that published all of its rows stops publishing
+            #[cfg(feature = "sqlite")]
+            Value::Sqlite(source) => Ok(source.next()?.map(Cow::Owned).into_iter().collect()),
+            value => Ok(vec![value.resolve(context)?]),
+        }
+    }
+
+    /// Get the number a resolved value represents, `None` if it isn't numeric.
+    ///
+    /// Booleans count as 0 and 1.
+    pub fn number(&self, context: &Context) -> Option<f64> {
+        let number = match self {
+            Value::Bool(b) => *b as u8 as f64,
+            Value::TextNumber { value, .. } | Value::Float { value, .. } => *value,
+            Value::UInt { value, .. } => *value as f64,
+            Value::Int { value, .. } => *value as f64,
+            Value::Uptime { unit, .. } => unit.elapsed(context.start) as f64,
+            Value::Index { .. } => context.index as f64,
+            Value::ReconnectCount { .. } => context.stats.reconnects() as f64,
+            Value::ReloadCount { .. } => context.stats.reloads() as f64,
+            Value::ConnectionState { connection_state } => {
+                connection_state.code(context.stats.connection()) as f64
+            }
+            _ => return None,
+        };
+        Some(number)
+    }
+
+    /// Take over the state of the generators in `previous` that have the same type at the same place.
+    pub fn adopt_state(&mut self, previous: &Value) {
+        match (self, previous) {
+            (Value::MonotonicTime { last_ms, .. }, Value::MonotonicTime { last_ms: old, .. }) => {
+                *last_ms = Arc::clone(old)
+            }
+            // a new seed starts a new sequence
+            (
+                Value::RandomBytes { seed, rng, .. },
+                Value::RandomBytes {
+                    seed: old_seed,
+                    rng: old,
+                    ..
+                },
+            ) if seed == old_seed => *rng = Arc::clone(old),
+            (Value::RandomWalk { current, .. }, Value::RandomWalk { current: old, .. }) => {
+                *current = Arc::clone(old)
+            }
+            (Value::Counter { publishes, .. }, Value::Counter { publishes: old, .. }) => {
+                *publishes = Arc::clone(old)
+            }
+            (
+                Value::Phased { phases, publishes },
+                Value::Phased {
+                    phases: old_phases,
+                    publishes: old,
+                },
+            ) => {
+                *publishes = Arc::clone(old);
+                for (phase, old) in phases.iter_mut().zip(old_phases) {
+                    phase.value.adopt_state(&old.value);
+                }
+            }
+            (Value::Array(values), Value::Array(old))
+            | (
+                Value::PrefixedArray { items: values, .. },
+                Value::PrefixedArray { items: old, .. },
+            )
+            | (Value::Frame { fields: values, .. }, Value::Frame { fields: old, .. }) => {
+                for (value, old) in values.iter_mut().zip(old) {
+                    value.adopt_state(old);
+                }
+            }
+            (Value::PackedStruct { fields, .. }, Value::PackedStruct { fields: old, .. }) => {
+                for (field, old) in fields.iter_mut().zip(old) {
+                    field.value.adopt_state(&old.value);
+                }
+            }
+            (Value::LineProtocol { fields, .. }, Value::LineProtocol { fields: old, .. }) => {
+                for (key, value) in fields {
+                    if let Some(old) = old.get(key) {
+                        value.adopt_state(old);
+                    }
+                }
+            }
+            (Value::Expr(expr), Value::Expr(old)) => expr.adopt_state(old),
+            #[cfg(feature = "sqlite")]
+            (Value::Sqlite(source), Value::Sqlite(old)) => source.adopt_state(old),
+            (Value::Tail(tail), Value::Tail(old)) => tail.adopt_state(old),
+            (Value::Anomalous(anomalous), Value::Anomalous(old)) => anomalous.adopt_state(old),
+            _ => (),
+        }
+    }
+
+    /// Fill in the endianness and frame alignment of this value and the values nested in it that
+    /// don't set their own.
+    pub fn inherit(&mut self, endian: Endian, align: usize) {
+        fn prefix(length_prefix: &mut Option<LengthPrefix>, endian: Endian) {
+            if let Some(prefix) = length_prefix {
+                prefix.endian.get_or_insert(endian);
+            }
+        }
+
+        match self {
+            Value::FixedPoint { endian: own, .. }
+            | Value::UInt { endian: own, .. }
+            | Value::Int { endian: own, .. }
+            | Value::Float { endian: own, .. }
+            | Value::Enum { endian: own, .. }
+            | Value::Uptime { endian: own, .. }
+            | Value::Samples { endian: own, .. }
+            | Value::MonotonicTime { endian: own, .. }
+            | Value::MovingAverage { endian: own, .. }
+            | Value::RandomWalk { endian: own, .. }
+            | Value::RandomInt { endian: own, .. }
+            | Value::RandomFloat { endian: own, .. }
+            | Value::Counter { endian: own, .. }
+            | Value::Waveform { endian: own, .. }
+            | Value::Uuid { endian: own, .. }
+            | Value::Timestamp { endian: own, .. }
+            | Value::Flags { endian: own, .. }
+            | Value::Bits { endian: own, .. }
+            | Value::Index {
+                index: IntFormat { endian: own, .. },
+            }
+            | Value::ReconnectCount {
+                reconnects: IntFormat { endian: own, .. },
+            }
+            | Value::ReloadCount {
+                reloads: IntFormat { endian: own, .. },
+            }
+            | Value::ConnectionState {
+                connection_state: StateCodes { endian: own, .. },
+            } => {
+                own.get_or_insert(endian);
+            }
+            Value::String { framing, .. } | Value::DateTime { framing, .. } => {
+                prefix(&mut framing.length_prefix, endian)
+            }
+            Value::EncodedJSON { length_prefix, .. } => prefix(length_prefix, endian),
+            Value::Array(values) => {
+                for value in values {
+                    value.inherit(endian, align);
+                }
+            }
+            Value::PrefixedArray {
+                items, endian: own, ..
+            } => {
+                own.get_or_insert(endian);
+                for value in items {
+                    value.inherit(endian, align);
+                }
+            }
+            Value::Frame {
+                fields,
+                checksum,
+                align: own,
+            } => {
+                checksum.endian.get_or_insert(endian);
+                own.get_or_insert(align);
+                for value in fields {
+                    value.inherit(endian, align);
+                }
+            }
+            Value::PackedStruct { fields, .. } => {
+                for field in fields {
+                    field.value.inherit(endian, align);
+                }
+            }
+            Value::Phased { phases, .. } => {
+                for phase in phases {
+                    phase.value.inherit(endian, align);
+                }
+            }
+            Value::LineProtocol {
+                fields, framing, ..
+            } => {
+                prefix(&mut framing.length_prefix, endian);
+                for value in fields.values_mut() {
+                    value.inherit(endian, align);
+                }
+            }
+            #[cfg(feature = "http")]
+            Value::Http(source) => source.inherit(endian),
+            Value::SysFile(file) => file.inherit(endian),
+            #[cfg(feature = "sqlite")]
+            Value::Sqlite(source) => source.inherit(endian),
+            Value::Expr(expr) => expr.inherit(endian),
+            Value::Encrypted(encrypted) => encrypted.inherit(endian, align),
+            Value::Signed(signed) => signed.inherit(endian, align),
+            Value::Anomalous(anomalous) => anomalous.inherit(endian, align),
+            _ => (),
+        }
+    }
+
+    /// Call `f` on this value and all values nested in it.
+    pub fn walk<'a>(&'a self, f: &mut dyn FnMut(&'a Value)) {
+        f(self);
+        match self {
+            Value::Array(values)
+            | Value::PrefixedArray { items: values, .. }
+            | Value::Frame { fields: values, .. } => {
+                for value in values {
+                    value.walk(f);
+                }
+            }
+            Value::PackedStruct { fields, .. } => {
+                for field in fields {
+                    field.value.walk(f);
+                }
+            }
+            Value::Encrypted(encrypted) => encrypted.inner().walk(f),
+            Value::Signed(signed) => signed.inner().walk(f),
+            Value::Anomalous(anomalous) => anomalous.base().walk(f),
+            Value::Phased { phases, .. } => {
+                for phase in phases {
+                    phase.value.walk(f);
+                }
+            }
+            Value::LineProtocol { fields, .. } => {
+                for value in fields.values() {
+                    value.walk(f);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Pack bit fields into an integer of `width` bits, rejecting overlaps and values that don't fit.
+fn pack_bits(fields: &[BitField], width: IntWidth) -> Result<u128, io::Error> {
+    let mut packed = 0;
+    let mut taken = 0u128;
+    for field in fields {
+        let (offset, bits) = (field.bit_offset as u32, field.bits as u32);
+        if bits == 0 || bits > 64 || offset + bits > width.bits() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "bit field of {} bits at offset {} doesn't fit {} bits",
+                    bits,
+                    offset,
+                    width.bits()
+                ),
+            ));
+        }
+        if u128::from(field.value) >> bits != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} exceeds a bit field of {} bits", field.value, bits),
+            ));
+        }
+        let mask = (u128::MAX >> (128 - bits)) << offset;
+        if taken & mask != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bit field at offset {} overlaps another field", offset),
+            ));
+        }
+        taken |= mask;
+        packed |= u128::from(field.value) << offset;
+    }
+    Ok(packed)
+}
+
+/// Scale `value` and round it half to even into a signed integer of `width` bits.
+fn fixed_point(value: f64, scale: f64, width: IntWidth) -> Result<i128, io::Error> {
+    let scaled = (value * scale).round_ties_even();
+    // the bounds are powers of two, exact as floats
+    let bound = 2f64.powi(width.bits() as i32 - 1);
+    if !(-bound..bound).contains(&scaled) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} scaled by {} overflows a {} bit integer",
+                value,
+                scale,
+                width.bits()
+            ),
+        ));
+    }
+    Ok(scaled as i128)
+}
+
+/// Escape the `special` characters of a line protocol name with backslashes.
+fn escape_line(text: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Format the resolved value of the line protocol field `key`.
+fn line_field(key: &str, value: &Value) -> Result<String, io::Error> {
+    let field = match value {
+        Value::Bool(value) => value.to_string(),
+        Value::Int { value, .. } => format!("{}i", value),
+        Value::UInt { value, .. } => format!("{}u", value),
+        Value::Float { value, .. } => value.to_string(),
+        Value::TextNumber {
+            value, decimals, ..
+        } => format!("{:.*}", decimals, value),
+        Value::String { value, .. } => format!("\"{}\"", escape_line(value, &['"', '\\'])),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "line protocol field {} is not a number, boolean or string",
+                    key
+                ),
+            ))
+        }
+    };
+    Ok(field)
+}
+
+/// Value published for a number of publishes of a phased value.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Phase {
+    pub count: usize,
+    pub value: Value,
+}
+
+/// Value placed at a fixed byte offset of a packed struct.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PackedField {
+    pub offset: usize,
+    pub value: Value,
+}
+
+/// Field of `bits` bits starting `bit_offset` bits above the least significant bit.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BitField {
+    pub bit_offset: u8,
+    pub bits: u8,
+    pub value: u64,
+}
+
+/// Compression of a serialized payload.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub enum Compression {
+    #[default]
+    #[serde(alias = "none")]
+    None,
+    /// Gzip stream as written by `gzip`, RFC 1952.
+    #[serde(alias = "gzip")]
+    Gzip,
+    /// Raw deflate stream without a header, RFC 1951.
+    #[serde(alias = "deflate")]
+    Deflate,
+    /// Deflate stream with the zlib header and checksum, RFC 1950.
+    #[serde(alias = "zlib")]
+    Zlib,
+}
+
+impl Compression {
+    /// Replace `payload` by its compressed form.
+    fn apply(self, payload: &mut Vec<u8>) -> Result<(), io::Error> {
+        let level = flate2::Compression::default();
+        *payload = match self {
+            Compression::None => return Ok(()),
+            Compression::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+                encoder.write_all(payload)?;
+                encoder.finish()?
+            }
+            Compression::Deflate => {
+                let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), level);
+                encoder.write_all(payload)?;
+                encoder.finish()?
+            }
+            Compression::Zlib => {
+                let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), level);
+                encoder.write_all(payload)?;
+                encoder.finish()?
+            }
+        };
+        Ok(())
+    }
+}
+
+/// Checksum appended to a frame.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    /// Bytes of the frame covered by the checksum, all of them by default.
+    #[serde(default)]
+    pub over: ByteRange,
+    #[serde(default)]
+    pub endian: Option<Endian>,
+}
+
+impl Checksum {
+    /// Write the checksum over the covered bytes of `frame`.
+    ///
+    /// Fails if the range exceeds the frame.
+    pub fn write(&self, frame: &[u8], writer: &mut dyn Write) -> Result<(), io::Error> {
+        let end = self.over.end.unwrap_or(frame.len());
+        let bytes = frame.get(self.over.start..end).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "checksum range {}..{} exceeds frame of {} bytes",
+                    self.over.start,
+                    end,
+                    frame.len()
+                ),
+            )
+        })?;
+        let (checksum, width) = self.algorithm.compute(bytes);
+        crate::encoder::write_uint(
+            checksum.into(),
+            self.endian.unwrap_or_default(),
+            width,
+            writer,
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub enum ChecksumAlgorithm {
+    /// Sum of all bytes modulo 256.
+    #[serde(alias = "sum8")]
+    Sum8,
+    /// XOR of all bytes.
+    #[serde(alias = "xor8")]
+    Xor8,
+    /// CRC-16 as used by Modbus RTU.
+    #[serde(alias = "crc16")]
+    Crc16Modbus,
+    /// CRC-32 as used by Ethernet and zlib.
+    #[serde(alias = "crc32")]
+    Crc32,
+}
+
+impl ChecksumAlgorithm {
+    /// Compute the checksum of `bytes` and get it with its width.
+    fn compute(self, bytes: &[u8]) -> (u64, IntWidth) {
+        match self {
+            ChecksumAlgorithm::Sum8 => (
+                bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) as u64,
+                IntWidth::Eight,
+            ),
+            ChecksumAlgorithm::Xor8 => (
+                bytes.iter().fold(0u8, |sum, b| sum ^ b) as u64,
+                IntWidth::Eight,
+            ),
+            ChecksumAlgorithm::Crc16Modbus => {
+                let mut crc = 0xFFFFu16;
+                for b in bytes {
+                    crc ^= *b as u16;
+                    for _ in 0..8 {
+                        crc = if crc & 1 == 1 {
+                            (crc >> 1) ^ 0xA001
+                        } else {
+                            crc >> 1
+                        };
+                    }
+                }
+                (crc as u64, IntWidth::Sixteen)
+            }
+            ChecksumAlgorithm::Crc32 => {
+                let mut crc = 0xFFFF_FFFFu32;
+                for b in bytes {
+                    crc ^= *b as u32;
+                    for _ in 0..8 {
+                        crc = if crc & 1 == 1 {
+                            (crc >> 1) ^ 0xEDB8_8320
+                        } else {
+                            crc >> 1
+                        };
+                    }
+                }
+                (!crc as u64, IntWidth::Thirtytwo)
+            }
+        }
+    }
+}
+
+/// Half-open byte range, `end` defaults to the end of the data.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ByteRange {
+    #[serde(default)]
+    pub start: usize,
+    #[serde(default)]
+    pub end: Option<usize>,
+}
+
+/// Byte length written in front of a payload.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub struct LengthPrefix {
+    #[serde(default = "LengthPrefix::default_width")]
+    pub width: IntWidth,
+    #[serde(default)]
+    pub endian: Option<Endian>,
+}
+
+impl LengthPrefix {
+    fn default_width() -> IntWidth {
+        IntWidth::Thirtytwo
+    }
+
+    /// Write the length of `payload` followed by `payload`.
+    ///
+    /// Fails if the length doesn't fit the prefix width.
+    pub fn write(&self, payload: &[u8], writer: &mut dyn Write) -> Result<(), io::Error> {
+        self.write_len(payload.len() as u64, writer)?;
+        writer.write_all(payload)
+    }
+
+    /// Write `len` on its own, failing if it doesn't fit the prefix width.
+    pub fn write_len(&self, len: u64, writer: &mut dyn Write) -> Result<(), io::Error> {
+        let max = match self.width {
+            IntWidth::Eight => u8::MAX as u64,
+            IntWidth::Sixteen => u16::MAX as u64,
+            IntWidth::Thirtytwo => u32::MAX as u64,
+            IntWidth::Sixtyfour | IntWidth::OneTwentyEight => u64::MAX,
+        };
+        if len > max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("length {} exceeds {:?} bit prefix", len, self.width),
+            ));
+        }
+        crate::encoder::write_uint(
+            len.into(),
+            self.endian.unwrap_or_default(),
+            self.width,
+            writer,
+        )
+    }
+}
+
+/// Bytes around the encoded text of a string.
+///
+/// The text follows the BOM and is followed by the terminator and then the padding up to
+/// `fixed_width`. The length prefix counts all of these bytes.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Framing {
+    #[serde(default)]
+    pub bom: Option<bool>,
+    /// Append a NUL character in the string's encoding.
+    #[serde(default)]
+    pub null_terminated: bool,
+    /// Number of bytes the string is padded to, longer strings fail to serialize.
+    #[serde(default)]
+    pub fixed_width: Option<usize>,
+    /// Byte the string is padded with.
+    #[serde(default)]
+    pub pad: u8,
+    #[serde(default)]
+    pub length_prefix: Option<LengthPrefix>,
+}
+
+impl Framing {
+    /// Write `value` in `encoding` with this framing, `utf16_bom` applies unless `bom` is set.
+    pub fn write(
+        &self,
+        value: &str,
+        encoding: StringEncoding,
+        utf16_bom: bool,
+        writer: &mut dyn Write,
+    ) -> Result<(), io::Error> {
+        let mut buf = Vec::new();
+        encoding.encode(value, self.bom.unwrap_or(utf16_bom), &mut buf)?;
+        if self.null_terminated {
+            buf.extend_from_slice(encoding.terminator());
+        }
+        if let Some(width) = self.fixed_width {
+            if buf.len() > width {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "string of {} bytes exceeds fixed_width {}",
+                        buf.len(),
+                        width
+                    ),
+                ));
+            }
+            buf.resize(width, self.pad);
+        }
+        match &self.length_prefix {
+            Some(prefix) => prefix.write(&buf, writer),
+            None => writer.write_all(&buf),
+        }
+    }
+}
+
+/// Size field written in front of a prefixed array.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub enum ArrayPrefix {
+    #[default]
+    #[serde(alias = "none")]
+    None,
+    /// Number of items as a 16 bit integer.
+    #[serde(alias = "count_u16")]
+    CountU16,
+    /// Number of items as a 32 bit integer.
+    #[serde(alias = "count_u32")]
+    CountU32,
+    /// Byte length of the serialized items as a 32 bit integer.
+    #[serde(alias = "bytelen_u32")]
+    BytelenU32,
+}
+
+/// Type that text from external sources is parsed as.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub enum ParseAs {
+    Bool,
+    Int,
+    UInt,
+    Float,
+    #[default]
+    String,
+}
+
+impl ParseAs {
+    /// Parse `text` into a value with the given endianness and width.
+    ///
+    /// Floats only support widths of 32 and 64 bits.
+    pub fn parse(
+        self,
+        text: &str,
+        endian: Option<Endian>,
+        width: IntWidth,
+    ) -> Result<Value, io::Error> {
+        fn invalid<E: std::fmt::Display>(e: E) -> io::Error {
+            io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+        }
+
+        let text = text.trim();
+        let value = match self {
+            ParseAs::Bool => Value::Bool(text.parse().map_err(invalid)?),
+            ParseAs::Int => Value::Int {
+                value: text.parse().map_err(invalid)?,
+                endian,
+                width,
+            },
+            ParseAs::UInt => Value::UInt {
+                value: text.parse().map_err(invalid)?,
+                endian,
+                width,
+            },
+            ParseAs::Float => Value::Float {
+                value: text.parse().map_err(invalid)?,
+                endian,
+                width: match width {
+                    IntWidth::Thirtytwo => FloatWidth::Thirtytwo,
+                    IntWidth::Sixtyfour => FloatWidth::Sixtyfour,
+                    width => return Err(invalid(format!("invalid float width {:?}", width))),
+                },
+                rounding: Rounding::default(),
+                denormals: Denormals::default(),
+                require_finite: false,
+            },
+            ParseAs::String => Value::String {
+                value: text.to_string(),
+                encoding: StringEncoding::default(),
+                framing: Framing::default(),
+            },
+        };
+        Ok(value)
+    }
+}
+
+/// Numeric type of the values in a sample buffer.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub enum SampleKind {
+    Int,
+    UInt,
+    Float,
+}
+
+impl SampleKind {
+    /// Check that all `values` are representable in this type with the given width.
+    ///
+    /// Floats only support widths of 32 and 64 bits.
+    pub fn check(self, values: &[f64], width: IntWidth) -> Result<(), io::Error> {
+        let bits = width.bits() as i32;
+        let fits = |value: f64| match self {
+            SampleKind::Int => {
+                value.fract() == 0.
+                    && value >= -(2f64.powi(bits - 1))
+                    && value < 2f64.powi(bits - 1)
+            }
+            SampleKind::UInt => value.fract() == 0. && value >= 0. && value < 2f64.powi(bits),
+            SampleKind::Float => bits == 64 || value.abs() <= f32::MAX as f64,
+        };
+        if matches!(self, SampleKind::Float) && !(32..=64).contains(&bits) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid float width {:?}", width),
+            ));
+        }
+        for (i, value) in values.iter().enumerate() {
+            if !fits(*value) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "sample {} ({}) doesn't fit a {} bit {:?}",
+                        i, value, bits, self
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Simulator state available during serialization.
+#[derive(Clone)]
+pub struct Context {
+    /// Startup time of the simulator.
+    pub start: Instant,
+    /// Counters of the simulator's activity.
+    pub stats: Arc<Stats>,
+    /// Whether UTF-16 strings start with a BOM unless the entry says otherwise.
+    pub utf16_bom: bool,
+    /// Fleet index of the entry being serialized.
+    pub index: usize,
+    /// Topic of the entry being serialized.
+    pub topic: Arc<str>,
+    /// Recent values of the sources of moving averages.
+    pub averages: Arc<Averages>,
+    /// Whether to warn about floats that don't round-trip through 32 bits.
+    pub warn_precision_loss: bool,
+    /// Seed of the random number generator, `None` if it's seeded from entropy.
+    pub seed: Option<u64>,
+    /// Generator shared by the random values of the entry being serialized.
+    pub entry_rng: Arc<Mutex<Option<StdRng>>>,
+    /// Constants of the device being serialized.
+    pub constants: Arc<BTreeMap<String, Constant>>,
+}
+
+impl Context {
+    /// Get a random number generator for the entry being serialized.
+    ///
+    /// `seed` takes precedence over the global seed, which is combined with the topic so entries
+    /// get different sequences. `seed` is offset by the fleet index for the same reason. It's
+    /// seeded from entropy without either.
+    pub fn rng(&self, seed: Option<u64>) -> StdRng {
+        // FNV-1a, unlike the std hasher it's stable across Rust versions
+        let topic = self
+            .topic
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, b| {
+                (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+            });
+        let seed = seed.map(|seed| seed.wrapping_add(self.index as u64));
+        match seed.or_else(|| self.seed.map(|seed| seed ^ topic)) {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    /// Draw from the generator of the entry being serialized, seeding it on first use.
+    pub fn random<T>(&self, f: impl FnOnce(&mut StdRng) -> T) -> T {
+        let mut rng = self.entry_rng.lock().unwrap();
+        f(rng.get_or_insert_with(|| self.rng(None)))
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub enum UuidVersion {
+    /// Random UUID.
+    #[serde(alias = "v4")]
+    V4,
+    /// UUID starting with the current Unix time in milliseconds, sortable by creation time.
+    #[serde(alias = "v7")]
+    V7,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub enum UuidFormat {
+    /// 16 raw bytes.
+    #[serde(alias = "bytes")]
+    Bytes,
+    /// Canonical hyphenated lowercase text.
+    #[default]
+    #[serde(alias = "string")]
+    String,
+}
+
+/// Representation of a published timestamp.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub enum TimestampFormat {
+    #[serde(alias = "unix_secs")]
+    UnixSecs,
+    #[serde(alias = "unix_millis")]
+    UnixMillis,
+    #[serde(alias = "unix_nanos")]
+    UnixNanos,
+    /// UTC date and time with milliseconds as a UTF-8 string.
+    #[serde(alias = "rfc3339")]
+    Rfc3339,
+}
+
+/// Time zone of formatted dates.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub enum TimeZone {
+    #[default]
+    #[serde(alias = "utc")]
+    Utc,
+    /// Time zone of the system the simulator runs on.
+    #[serde(alias = "local")]
+    Local,
+}
+
+/// Shape of a periodic waveform.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub enum Shape {
+    #[serde(alias = "sine")]
+    Sine,
+    /// Rising from -1 to 1 over every period.
+    #[serde(alias = "saw")]
+    Saw,
+    #[serde(alias = "triangle")]
+    Triangle,
+    /// 1 for the first and -1 for the second half of every period.
+    #[serde(alias = "square")]
+    Square,
+}
+
+impl Shape {
+    /// Get the value between -1 and 1 at `phase`, the fraction of the period that passed.
+    ///
+    /// Sine and triangle start at 0 and peak a quarter into the period.
+    pub fn at(self, phase: f64) -> f64 {
+        match self {
+            Shape::Sine => (phase * std::f64::consts::TAU).sin(),
+            Shape::Saw => 2. * phase - 1.,
+            Shape::Triangle => 4. * ((phase + 0.75).fract() - 0.5).abs() - 1.,
+            Shape::Square if phase < 0.5 => 1.,
+            Shape::Square => -1.,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub enum TimeUnit {
+    Seconds,
+    Millis,
+}
+
+impl TimeUnit {
+    /// Get the time elapsed since `since` in this unit.
+    pub fn elapsed(self, since: Instant) -> u64 {
+        let elapsed = since.elapsed();
+        match self {
+            TimeUnit::Seconds => elapsed.as_secs(),
+            TimeUnit::Millis => elapsed.as_millis() as u64,
+        }
+    }
+}
+
+/// Recent values of a topic that stats are computed over.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StatsWindow {
+    pub source: String,
+    pub window: usize,
+}
+
+/// Width and endianness of a generated integer.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct IntFormat {
+    #[serde(default)]
+    pub width: IntWidth,
+    #[serde(default)]
+    pub endian: Option<Endian>,
+}
+
+/// Format of a published connection state.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct StateCodes {
+    #[serde(default)]
+    pub width: IntWidth,
+    #[serde(default)]
+    pub endian: Option<Endian>,
+    #[serde(default)]
+    pub mapping: StateMapping,
+}
+
+impl StateCodes {
+    /// Get the code `state` is published as.
+    pub fn code(&self, state: ConnectionState) -> u64 {
+        match state {
+            ConnectionState::Connecting => self.mapping.connecting,
+            ConnectionState::Connected => self.mapping.connected,
+            ConnectionState::Reconnecting => self.mapping.reconnecting,
+        }
+    }
 }
 
-impl Data {
-    /// Get a reference to the data's data.
-    pub fn data(&self) -> &Value {
-        &self.data
-    }
+/// Code of every connection state.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StateMapping {
+    pub connecting: u64,
+    pub connected: u64,
+    pub reconnecting: u64,
+}
 
-    /// Get a reference to the data's topic.
-    pub fn topic(&self) -> &str {
-        &self.topic
+impl Default for StateMapping {
+    fn default() -> Self {
+        StateMapping {
+            connecting: 0,
+            connected: 1,
+            reconnecting: 2,
+        }
     }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+/// A Modbus register entry.
+///
+/// Plain numbers occupy a single 16 bit register, 32 bit values span two registers.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
 #[serde(untagged)]
-pub enum Value {
-    Bool(bool),
-    UInt {
-        value: u64,
-        #[serde(default)]
-        endian: Endian,
-        #[serde(default)]
-        width: IntWidth,
-    },
-    Int {
-        value: i64,
-        #[serde(default)]
-        endian: Endian,
-        #[serde(default)]
-        width: IntWidth,
-    },
-    Float {
-        value: f64,
-        #[serde(default)]
-        endian: Endian,
-        #[serde(default)]
-        width: FloatWidth,
-    },
-    String {
-        value: String,
-        #[serde(default)]
-        encoding: StringEncoding,
-    },
-    Array(Vec<Value>),
-    JSON(serde_json::Value)
+pub enum Register {
+    Word(u16),
+    UInt { value: u32 },
+    Int { value: i32 },
+    Float { value: f32 },
 }
 
-impl Value {
-    pub fn serialize<W>(&self, writer: &mut W) -> Result<(), io::Error>
-    where
-        W: Write,
-    {
-        match self {
-            Value::Bool(b) => writer.write_all(&(*b as u8).to_ne_bytes()),
-            Value::Int {
-                value,
-                endian,
-                width,
-            } => match (endian, width) {
-                (_, IntWidth::Eight) => writer.write_all(&(*value as i8).to_ne_bytes()),
-                (Endian::LittleEndian, IntWidth::Sixteen) => {
-                    writer.write_all(&(*value as i16).to_le_bytes())
-                }
-                (Endian::LittleEndian, IntWidth::Thirtytwo) => {
-                    writer.write_all(&(*value as i32).to_le_bytes())
-                }
-                (Endian::LittleEndian, IntWidth::Sixtyfour) => {
-                    writer.write_all(&value.to_le_bytes())
-                }
-                (Endian::BigEndian, IntWidth::Sixteen) => {
-                    writer.write_all(&(*value as i16).to_be_bytes())
-                }
-                (Endian::BigEndian, IntWidth::Thirtytwo) => {
-                    writer.write_all(&(*value as i32).to_be_bytes())
-                }
-                (Endian::BigEndian, IntWidth::Sixtyfour) => writer.write_all(&value.to_be_bytes()),
-            },
-            Value::UInt {
-                value,
-                endian,
-                width,
-            } => match (endian, width) {
-                (_, IntWidth::Eight) => writer.write_all(&(*value as u8).to_ne_bytes()),
-                (Endian::LittleEndian, IntWidth::Sixteen) => {
-                    writer.write_all(&(*value as u16).to_le_bytes())
-                }
-                (Endian::LittleEndian, IntWidth::Thirtytwo) => {
-                    writer.write_all(&(*value as u32).to_le_bytes())
-                }
-                (Endian::LittleEndian, IntWidth::Sixtyfour) => {
-                    writer.write_all(&value.to_le_bytes())
-                }
-                (Endian::BigEndian, IntWidth::Sixteen) => {
-                    writer.write_all(&(*value as u16).to_be_bytes())
-                }
-                (Endian::BigEndian, IntWidth::Thirtytwo) => {
-                    writer.write_all(&(*value as u32).to_be_bytes())
-                }
-                (Endian::BigEndian, IntWidth::Sixtyfour) => writer.write_all(&value.to_be_bytes()),
-            },
-            Value::Float {
-                value,
-                endian,
-                width,
-            } => match (endian, width) {
-                (Endian::LittleEndian, FloatWidth::Thirtytwo) => {
-                    writer.write_all(&(*value as f32).to_le_bytes())
-                }
-                (Endian::LittleEndian, FloatWidth::Sixtyfour) => {
-                    writer.write_all(&value.to_le_bytes())
-                }
-                (Endian::BigEndian, FloatWidth::Thirtytwo) => {
-                    writer.write_all(&(*value as f32).to_be_bytes())
-                }
-                (Endian::BigEndian, FloatWidth::Sixtyfour) => {
-                    writer.write_all(&value.to_be_bytes())
-                }
-            },
-            Value::String { value, encoding } => encoding.encode(value, writer),
-            Value::Array(array) => {
-                for value in array {
-                    value.serialize(writer)?;
-                }
-                Ok(())
-            }
-            Value::JSON(value) => {
-                serde_json::to_writer(writer, value)?;
-                Ok(())
+impl Register {
+    pub(crate) fn serialize(
+        &self,
+        word_order: WordOrder,
+        writer: &mut dyn Write,
+    ) -> Result<(), io::Error> {
+        let dword = match self {
+            Register::Word(word) => return writer.write_all(&word.to_be_bytes()),
+            Register::UInt { value } => value.to_be_bytes(),
+            Register::Int { value } => value.to_be_bytes(),
+            Register::Float { value } => value.to_be_bytes(),
+        };
+        match word_order {
+            WordOrder::HighWordFirst => writer.write_all(&dword),
+            WordOrder::LowWordFirst => {
+                writer.write_all(&dword[2..])?;
+                writer.write_all(&dword[..2])
             }
         }
     }
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+/// Order of the two registers holding a 32 bit value.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub enum WordOrder {
+    #[default]
+    HighWordFirst,
+    LowWordFirst,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
 pub enum StringEncoding {
+    #[default]
     UTF8,
     UTF16BE,
     UTF16LE,
+    /// Bytes written as hex digits, whitespace between them is ignored.
+    Hex,
+    /// Bytes written in standard Base64, whitespace is ignored.
+    Base64,
 }
 
 impl StringEncoding {
-    fn encode<W>(&self, value: &str, mut writer: W) -> Result<(), io::Error>
-    where
-        W: Write,
-    {
+    /// Write `value` in this encoding, UTF-16 text is preceded by a BOM if `bom` is set.
+    pub(crate) fn encode(
+        &self,
+        value: &str,
+        bom: bool,
+        writer: &mut dyn Write,
+    ) -> Result<(), io::Error> {
         match self {
             StringEncoding::UTF8 => writer.write_all(value.as_bytes()),
             StringEncoding::UTF16BE => {
-                writer.write_all(&0xFEFFu16.to_be_bytes())?;
+                if bom {
+                    writer.write_all(&0xFEFFu16.to_be_bytes())?;
+                }
                 for c in value.encode_utf16().map(u16::to_be_bytes) {
                     writer.write_all(&c)?;
                 }
                 Ok(())
             }
             StringEncoding::UTF16LE => {
-                writer.write_all(&0xFEFFu16.to_le_bytes())?;
+                if bom {
+                    writer.write_all(&0xFEFFu16.to_le_bytes())?;
+                }
                 for c in value.encode_utf16().map(u16::to_le_bytes) {
                     writer.write_all(&c)?;
                 }
                 Ok(())
             }
+            StringEncoding::Hex | StringEncoding::Base64 => writer.write_all(&self.decode(value)?),
         }
     }
-}
 
-impl Default for StringEncoding {
-    fn default() -> Self {
-        StringEncoding::UTF8
+    /// Get the bytes of a NUL character in this encoding.
+    pub(crate) fn terminator(&self) -> &'static [u8] {
+        match self {
+            StringEncoding::UTF16BE | StringEncoding::UTF16LE => &[0, 0],
+            _ => &[0],
+        }
+    }
+
+    /// Decode the bytes of a hex or Base64 string.
+    fn decode(&self, value: &str) -> Result<Vec<u8>, io::Error> {
+        use base64::Engine;
+
+        let text = value.split_whitespace().collect::<String>();
+        let decoded = match self {
+            StringEncoding::Hex => hex::decode(&text).map_err(|e| e.to_string()),
+            _ => base64::engine::general_purpose::STANDARD
+                .decode(&text)
+                .map_err(|e| e.to_string()),
+        };
+        decoded.map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid {:?} string {:?}: {}", self, value, e),
+            )
+        })
     }
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
 pub enum Endian {
     LittleEndian,
+    #[default]
     BigEndian,
 }
 
-impl Default for Endian {
-    fn default() -> Self {
-        Endian::BigEndian
+/// Fields of an integer configured either as a JSON number or as a decimal string.
+///
+/// JSON numbers can't hold integers beyond 64 bits, so wider values are written as strings. Only
+/// integers with a `width` of 128 take strings, other strings are published as strings.
+#[derive(Deserialize)]
+struct WideFields<T> {
+    value: Wide<T>,
+    #[serde(default)]
+    endian: Option<Endian>,
+    #[serde(default)]
+    width: IntWidth,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Wide<T> {
+    Number(T),
+    Text(String),
+}
+
+impl<T> WideFields<T> {
+    fn parse<'de, D, W>(self) -> Result<(W, Option<Endian>, IntWidth), D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        W: From<T> + std::str::FromStr,
+        W::Err: fmt::Display,
+    {
+        use serde::de::Error;
+
+        let value = match self.value {
+            Wide::Number(value) => value.into(),
+            Wide::Text(text) if matches!(self.width, IntWidth::OneTwentyEight) => {
+                text.parse().map_err(D::Error::custom)?
+            }
+            Wide::Text(_) => return Err(D::Error::custom("decimal strings need a width of 128")),
+        };
+        Ok((value, self.endian, self.width))
+    }
+}
+
+mod wide_uint {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::convert::TryFrom;
+
+    use super::{Endian, IntWidth, WideFields};
+
+    pub fn serialize<S: Serializer>(value: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        match u64::try_from(*value) {
+            Ok(value) => serializer.serialize_u64(value),
+            Err(_) => serializer.collect_str(value),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<(u128, Option<Endian>, IntWidth), D::Error> {
+        WideFields::<u64>::deserialize(deserializer)?.parse::<D, _>()
+    }
+}
+
+mod wide_int {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::convert::TryFrom;
+
+    use super::{Endian, IntWidth, WideFields};
+
+    pub fn serialize<S: Serializer>(value: &i128, serializer: S) -> Result<S::Ok, S::Error> {
+        match i64::try_from(*value) {
+            Ok(value) => serializer.serialize_i64(value),
+            Err(_) => serializer.collect_str(value),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<(i128, Option<Endian>, IntWidth), D::Error> {
+        WideFields::<i64>::deserialize(deserializer)?.parse::<D, _>()
     }
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
 pub enum IntWidth {
     #[serde(alias = "8")]
     Eight,
@@ -199,25 +3275,821 @@ pub enum IntWidth {
     #[serde(alias = "32")]
     Thirtytwo,
     #[serde(alias = "64")]
+    #[default]
     Sixtyfour,
+    #[serde(alias = "128")]
+    OneTwentyEight,
 }
 
-impl Default for IntWidth {
-    fn default() -> Self {
-        IntWidth::Sixtyfour
+impl IntWidth {
+    /// Get the number of bits of this width.
+    pub fn bits(self) -> u32 {
+        match self {
+            IntWidth::Eight => 8,
+            IntWidth::Sixteen => 16,
+            IntWidth::Thirtytwo => 32,
+            IntWidth::Sixtyfour => 64,
+            IntWidth::OneTwentyEight => 128,
+        }
     }
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+/// End of a word that bit positions are counted from.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub enum BitOrder {
+    /// Bit 0 is the least significant bit.
+    #[default]
+    LsbFirst,
+    /// Bit 0 is the most significant bit.
+    MsbFirst,
+}
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
 pub enum FloatWidth {
     #[serde(alias = "32")]
     Thirtytwo,
     #[serde(alias = "64")]
+    #[default]
     Sixtyfour,
 }
 
-impl Default for FloatWidth {
-    fn default() -> Self {
-        FloatWidth::Sixtyfour
+/// Rounding applied when narrowing a float to 32 bits.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub enum Rounding {
+    /// Round to the nearest representable value, ties to even.
+    #[default]
+    Nearest,
+    TowardZero,
+    TowardPositive,
+    TowardNegative,
+}
+
+impl Rounding {
+    /// Narrow `value` to 32 bits.
+    pub fn narrow(self, value: f64) -> f32 {
+        let nearest = value as f32;
+        if value.is_nan() || nearest as f64 == value {
+            return nearest;
+        }
+        match self {
+            Rounding::Nearest => nearest,
+            Rounding::TowardZero if (nearest as f64).abs() > value.abs() => {
+                if value > 0. {
+                    nearest.next_down()
+                } else {
+                    nearest.next_up()
+                }
+            }
+            Rounding::TowardPositive if (nearest as f64) < value => nearest.next_up(),
+            Rounding::TowardNegative if (nearest as f64) > value => nearest.next_down(),
+            _ => nearest,
+        }
+    }
+}
+
+/// Handling of subnormal floats.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub enum Denormals {
+    #[default]
+    Preserve,
+    /// Replace subnormal values by zero of the same sign.
+    FlushToZero,
+}
+
+impl Denormals {
+    pub fn apply_f32(self, value: f32) -> f32 {
+        match self {
+            Denormals::FlushToZero if value.is_subnormal() => 0f32.copysign(value),
+            _ => value,
+        }
+    }
+
+    pub fn apply_f64(self, value: f64) -> f64 {
+        match self {
+            Denormals::FlushToZero if value.is_subnormal() => 0f64.copysign(value),
+            _ => value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_json(text: &str) -> Result<Vec<Data>, io::Error> {
+        load(text, Format::Json, None)
+    }
+
+    fn context(seed: Option<u64>) -> Context {
+        Context {
+            start: Instant::now(),
+            stats: Default::default(),
+            utf16_bom: true,
+            index: 0,
+            topic: Arc::from(""),
+            averages: Default::default(),
+            warn_precision_loss: false,
+            seed,
+            entry_rng: Default::default(),
+            constants: Default::default(),
+        }
+    }
+
+    /// Serialize the values of `val` like the sender, one payload per value.
+    fn payloads(val: &Data, context: &Context) -> Vec<Vec<u8>> {
+        let encoder = val.encoder().unwrap_or_default().encoder();
+        let context = Context {
+            index: val.index(),
+            topic: Arc::from(val.topic()),
+            entry_rng: val.rng(),
+            constants: val.constants(),
+            ..context.clone()
+        };
+        let mut payloads = Vec::new();
+        for (_, value) in val.values() {
+            for value in value.resolve_all(&context).unwrap() {
+                let mut buf = Vec::new();
+                Value::serialize(&value, encoder, &context, &mut buf).unwrap();
+                val.finish_payload(&mut buf).unwrap();
+                payloads.push(buf);
+            }
+        }
+        payloads
+    }
+
+    /// Serialize the single value of the single entry of a JSON data file.
+    fn payload(text: &str) -> Vec<u8> {
+        let vals = load_json(text).unwrap();
+        payloads(&vals[0], &context(None)).remove(0)
+    }
+
+    #[test]
+    fn topic_with_wildcard_is_rejected() {
+        assert!(validate_topic("a/+/b", false).is_err());
+        assert!(validate_topic("a/#", false).is_err());
+        let err = load_json(r#"[{"topic": "a/+/b", "data": {"value": 1}}]"#).unwrap_err();
+        assert!(err.to_string().contains("a/+/b"), "{}", err);
+    }
+
+    #[test]
+    fn empty_topic_is_rejected() {
+        assert!(validate_topic("", false).is_err());
+        assert!(load_json(r#"[{"topic": "", "data": {"value": 1}}]"#).is_err());
+    }
+
+    #[test]
+    fn valid_topics_pass() {
+        assert!(validate_topic("sensors/temp", false).is_ok());
+        assert!(validate_topic("/sensors/temp", false).is_ok());
+        assert!(validate_topic("sensors//temp", false).is_ok());
+        assert!(validate_topic("$SYS/uptime", true).is_ok());
+        assert!(validate_topic("$SYS/uptime", false).is_err());
+        let vals = load_json(r#"[{"topic": "sensors/temp", "data": {"value": 1}}]"#).unwrap();
+        assert_eq!(vals[0].topic(), "sensors/temp");
+    }
+
+    #[test]
+    fn bytes_are_written_verbatim() {
+        let payload = payload(r#"[{"topic": "t", "data": {"bytes": [170, 85, 1]}}]"#);
+        assert_eq!(payload, [0xaa, 0x55, 0x01]);
+    }
+
+    #[test]
+    fn bytes_out_of_range_are_rejected() {
+        let err = load_json(r#"[{"topic": "t", "data": {"bytes": [170, 256]}}]"#).unwrap_err();
+        assert!(err.to_string().contains("raw bytes"), "{}", err);
+        assert!(load_json(r#"[{"topic": "t", "data": {"bytes": [-1]}}]"#).is_err());
+    }
+
+    #[test]
+    fn integer_arrays_stay_arrays() {
+        let vals = load_json(r#"[{"topic": "t", "data": [1, 2]}]"#).unwrap();
+        assert!(matches!(vals[0].values()[0].1, Value::Array(_)));
+    }
+
+    #[test]
+    fn hex_strings_are_decoded() {
+        let mut buf = Vec::new();
+        StringEncoding::Hex
+            .encode("deadbeef", false, &mut buf)
+            .unwrap();
+        assert_eq!(buf, [0xde, 0xad, 0xbe, 0xef]);
+        let payload = payload(r#"[{"topic": "t", "data": {"value": "de ad", "encoding": "Hex"}}]"#);
+        assert_eq!(payload, [0xde, 0xad]);
+    }
+
+    #[test]
+    fn base64_strings_are_decoded() {
+        let mut buf = Vec::new();
+        StringEncoding::Base64
+            .encode("3q2+7w==", false, &mut buf)
+            .unwrap();
+        assert_eq!(buf, [0xde, 0xad, 0xbe, 0xef]);
+    }
+
+    #[test]
+    fn malformed_hex_is_rejected_on_load() {
+        let text = r#"[{"topic": "t", "data": {"value": "xyz", "encoding": "Hex"}}]"#;
+        assert!(load_json(text).is_err());
+        let text = r#"[{"topic": "t", "data": {"value": "!!", "encoding": "Base64"}}]"#;
+        assert!(load_json(text).is_err());
+    }
+
+    #[test]
+    fn length_prefix_counts_the_utf16_bom() {
+        let text = r#"[{"topic": "t", "data": {
+            "value": "ab",
+            "encoding": "UTF16BE",
+            "framing": {"length_prefix": {"width": "16", "endian": "BigEndian"}}
+        }}]"#;
+        assert_eq!(payload(text), [0, 6, 0xfe, 0xff, 0, b'a', 0, b'b']);
+    }
+
+    #[test]
+    fn null_terminated_string() {
+        let text = r#"[{"topic": "t", "data": {
+            "value": "ab",
+            "framing": {"null_terminated": true}
+        }}]"#;
+        assert_eq!(payload(text), b"ab\0");
+    }
+
+    #[test]
+    fn length_prefix_counts_terminator_and_padding() {
+        let text = r#"[{"topic": "t", "data": {
+            "value": "ab",
+            "framing": {
+                "null_terminated": true,
+                "fixed_width": 5,
+                "pad": 32,
+                "length_prefix": {"width": "8"}
+            }
+        }}]"#;
+        assert_eq!(payload(text), [5, b'a', b'b', 0, b' ', b' ']);
+    }
+
+    #[test]
+    fn utf16_terminator_is_two_bytes() {
+        let text = r#"[{"topic": "t", "data": {
+            "value": "a",
+            "encoding": "UTF16LE",
+            "framing": {"bom": false, "null_terminated": true}
+        }}]"#;
+        assert_eq!(payload(text), [b'a', 0, 0, 0]);
+    }
+
+    #[test]
+    fn framing_bom_overrides_the_global_default() {
+        let text = r#"[{"topic": "t", "data": {
+            "value": "a",
+            "encoding": "UTF16BE",
+            "framing": {"bom": true}
+        }}]"#;
+        let vals = load_json(text).unwrap();
+        let context = Context {
+            utf16_bom: false,
+            ..context(None)
+        };
+        assert_eq!(payloads(&vals[0], &context)[0], [0xfe, 0xff, 0, b'a']);
+    }
+
+    #[test]
+    fn fixed_width_rejects_longer_strings() {
+        let text = r#"[{"topic": "t", "data": {
+            "value": "abc",
+            "framing": {"null_terminated": true, "fixed_width": 3}
+        }}]"#;
+        let err = load_json(text).unwrap_err();
+        assert!(err.to_string().contains("fixed_width"), "{}", err);
+    }
+
+    #[test]
+    fn uint_128_round_trip() {
+        let value = u64::MAX as u128 * 3 + 7;
+        let text = format!(
+            r#"[{{"topic": "t", "data": {{"value": "{}", "width": "128"}}}}]"#,
+            value
+        );
+        let bytes = payload(&text);
+        assert_eq!(
+            u128::from_be_bytes(<[u8; 16]>::try_from(&bytes[..]).unwrap()),
+            value
+        );
+        let vals = load_json(&text).unwrap();
+        let json = serde_json::to_string(&vals[0].values()[0].1).unwrap();
+        match serde_json::from_str(&json).unwrap() {
+            Value::UInt { value: parsed, .. } => assert_eq!(parsed, value),
+            other => panic!("parsed as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn int_128_little_endian() {
+        let value = i64::MIN as i128 * 5;
+        let text = format!(
+            r#"[{{"topic": "t", "data": {{"value": "{}", "width": "128", "endian": "LittleEndian"}}}}]"#,
+            value
+        );
+        let bytes = payload(&text);
+        assert_eq!(
+            i128::from_le_bytes(<[u8; 16]>::try_from(&bytes[..]).unwrap()),
+            value
+        );
+    }
+
+    #[test]
+    fn smaller_widths_stay_lossless() {
+        let text = r#"[{"topic": "t", "data": {"value": 18446744073709551615, "width": "64"}}]"#;
+        assert_eq!(payload(text), [0xff; 8]);
+    }
+
+    #[test]
+    fn cbor_decodes_to_the_configured_value() {
+        let json = serde_json::json!({"temperature": 21.5, "tags": ["a", "b"], "ok": true});
+        let text = serde_json::json!([{"topic": "t", "data": {"cbor": json}}]).to_string();
+        let decoded: serde_json::Value = ciborium::from_reader(&payload(&text)[..]).unwrap();
+        assert_eq!(decoded, json);
+    }
+
+    #[test]
+    fn msgpack_decodes_to_the_configured_value() {
+        let json = serde_json::json!({"temperature": 21.5, "tags": ["a", "b"], "ok": true});
+        let text = serde_json::json!([{"topic": "t", "data": {"msgpack": json}}]).to_string();
+        let decoded: serde_json::Value = rmp_serde::from_slice(&payload(&text)).unwrap();
+        assert_eq!(decoded, json);
+    }
+
+    #[test]
+    fn pretty_json_differs_from_compact_only_in_whitespace() {
+        let json = serde_json::json!({"a": [1, 2], "b": {"c": null}});
+        let entry = |pretty: bool| {
+            serde_json::json!([{"topic": "t", "data": {"json": json, "pretty": pretty}}])
+                .to_string()
+        };
+        let compact = payload(&entry(false));
+        let pretty = payload(&entry(true));
+        assert_eq!(compact, serde_json::to_vec(&json).unwrap());
+        assert_eq!(pretty, serde_json::to_vec_pretty(&json).unwrap());
+        assert_ne!(compact, pretty);
+        let stripped = pretty
+            .into_iter()
+            .filter(|b| !b.is_ascii_whitespace())
+            .collect::<Vec<_>>();
+        assert_eq!(stripped, compact);
+    }
+
+    #[test]
+    fn bit_fields_are_packed() {
+        let text = r#"[{"topic": "t", "data": {"width": "16", "fields": [
+            {"bit_offset": 0, "bits": 4, "value": 10},
+            {"bit_offset": 4, "bits": 1, "value": 1},
+            {"bit_offset": 8, "bits": 8, "value": 92}
+        ]}}]"#;
+        assert_eq!(payload(text), [0x5c, 0x1a]);
+        let text = text.replace(
+            r#""width": "16""#,
+            r#""width": "16", "endian": "LittleEndian""#,
+        );
+        assert_eq!(payload(&text), [0x1a, 0x5c]);
+    }
+
+    #[test]
+    fn overlapping_bit_fields_are_rejected() {
+        let text = r#"[{"topic": "t", "data": {"width": "8", "fields": [
+            {"bit_offset": 0, "bits": 4, "value": 1},
+            {"bit_offset": 3, "bits": 2, "value": 1}
+        ]}}]"#;
+        let err = load_json(text).unwrap_err();
+        assert!(err.to_string().contains("overlaps"), "{}", err);
+    }
+
+    #[test]
+    fn bit_field_values_have_to_fit() {
+        let text = r#"[{"topic": "t", "data": {"width": "8", "fields": [
+            {"bit_offset": 0, "bits": 3, "value": 8}
+        ]}}]"#;
+        let err = load_json(text).unwrap_err();
+        assert!(err.to_string().contains("exceeds"), "{}", err);
+    }
+
+    #[test]
+    fn env_placeholders_are_substituted() {
+        std::env::set_var("MQTT_SIMULATOR_TEST_DEVICE", "dev-1");
+        let text = substitute_env("id: ${MQTT_SIMULATOR_TEST_DEVICE}").unwrap();
+        assert_eq!(text, "id: dev-1");
+    }
+
+    #[test]
+    fn env_defaults_apply_to_unset_and_empty_variables() {
+        std::env::remove_var("MQTT_SIMULATOR_TEST_UNSET");
+        std::env::set_var("MQTT_SIMULATOR_TEST_EMPTY", "");
+        std::env::set_var("MQTT_SIMULATOR_TEST_SET", "set");
+        let text = "${MQTT_SIMULATOR_TEST_UNSET:-a} ${MQTT_SIMULATOR_TEST_EMPTY:-b} \
+            ${MQTT_SIMULATOR_TEST_SET:-c} ${MQTT_SIMULATOR_TEST_UNSET:-}";
+        assert_eq!(substitute_env(text).unwrap(), "a b set ");
+    }
+
+    #[test]
+    fn missing_env_variables_are_an_error() {
+        std::env::remove_var("MQTT_SIMULATOR_TEST_MISSING");
+        let err = substitute_env("${MQTT_SIMULATOR_TEST_MISSING}").unwrap_err();
+        assert!(
+            err.to_string().contains("MQTT_SIMULATOR_TEST_MISSING"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn other_dollars_pass_through() {
+        let text = "$price ${not a name} ${unclosed $$";
+        assert_eq!(substitute_env(text).unwrap(), text);
+        assert!(matches!(substitute_env("$SYS/x"), Ok(Cow::Borrowed(_))));
+    }
+
+    /// Publish all entries of a freshly loaded data file `ticks` times like a new simulator.
+    fn run(text: &str, seed: Option<u64>, ticks: usize) -> Vec<Vec<u8>> {
+        let vals = load_json(text).unwrap();
+        let context = context(seed);
+        (0..ticks)
+            .flat_map(|_| vals.iter().flat_map(|val| payloads(val, &context)))
+            .collect()
+    }
+
+    #[test]
+    fn seeded_runs_publish_identical_payloads() {
+        let text = r#"[
+            {"topic": "int", "data": {"min": -100, "max": 100}},
+            {"topic": "float", "data": {"min": 0.0, "max": 1.0}},
+            {"topic": "walk", "data": {"start": 0.0, "step_stddev": 1.0, "min": -10.0, "max": 10.0}},
+            {"topic": "bytes", "data": {"length": 8}},
+            {"topic": "uuid", "data": {"version": "V4"}}
+        ]"#;
+        let vals = load_json(text).unwrap();
+        assert!(vals
+            .iter()
+            .all(|val| !matches!(val.values()[0].1, Value::JSON(_))));
+        let first = run(text, Some(7), 20);
+        assert_eq!(first, run(text, Some(7), 20));
+        assert_ne!(first, run(text, Some(8), 20));
+    }
+
+    #[test]
+    fn entry_endian_applies_to_nested_values() {
+        let text = r#"[{"topic": "t", "endian": "LittleEndian", "data": [
+            {"value": 1, "width": "16"},
+            [
+                {"value": 2, "width": "16"},
+                {"value": 3, "width": "16", "endian": "BigEndian"}
+            ]
+        ]}]"#;
+        assert_eq!(payload(text), [1, 0, 2, 0, 0, 3]);
+    }
+
+    #[test]
+    fn entry_endian_defaults_to_big_endian() {
+        let text = r#"[
+            {"topic": "a", "endian": "LittleEndian", "data": {"value": 1, "width": "16"}},
+            {"topic": "b", "data": [{"value": 1, "width": "16"}]}
+        ]"#;
+        let vals = load_json(text).unwrap();
+        assert_eq!(payloads(&vals[0], &context(None)), [[1, 0]]);
+        assert_eq!(payloads(&vals[1], &context(None)), [[0, 1]]);
+    }
+
+    #[test]
+    fn date_time_is_formatted() {
+        let entry = |data: &str| format!(r#"[{{"topic": "t", "data": {}}}]"#, data);
+        let text = payload(&entry(r#"{"format": "%Y-%m-%dT%H:%M:%SZ"}"#));
+        let text = String::from_utf8(text).unwrap();
+        assert_eq!(text.len(), "2024-01-15T12:00:00Z".len());
+        assert!(chrono::NaiveDateTime::parse_from_str(&text, "%Y-%m-%dT%H:%M:%SZ").is_ok());
+        let text = payload(&entry(r#"{"format": "%H:%M", "timezone": "local"}"#));
+        assert_eq!(text.len(), 5);
+    }
+
+    #[test]
+    fn utf16_date_time_starts_with_a_bom() {
+        let text = r#"[{"topic": "t", "data": {"format": "%Y", "encoding": "UTF16BE"}}]"#;
+        let payload = payload(text);
+        assert_eq!(payload.len(), 2 + 4 * 2);
+        assert_eq!(payload[..2], [0xfe, 0xff]);
+    }
+
+    #[test]
+    fn invalid_date_time_format_is_rejected() {
+        let text = r#"[{"topic": "t", "data": {"format": "%Y-%Q"}}]"#;
+        let err = load_json(text).unwrap_err();
+        assert!(err.to_string().contains("date time format"), "{}", err);
+    }
+
+    fn decompress(compression: Compression, payload: &[u8]) -> Vec<u8> {
+        use std::io::Read;
+
+        let mut decoder: Box<dyn Read + '_> = match compression {
+            Compression::None => Box::new(payload),
+            Compression::Gzip => Box::new(flate2::read::GzDecoder::new(payload)),
+            Compression::Deflate => Box::new(flate2::read::DeflateDecoder::new(payload)),
+            Compression::Zlib => Box::new(flate2::read::ZlibDecoder::new(payload)),
+        };
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        decompressed
+    }
+
+    #[test]
+    fn compressed_payloads_decompress_to_the_original() {
+        for compression in ["gzip", "deflate", "zlib"] {
+            let entry = |compression: &str| {
+                format!(
+                    r#"[{{"topic": "t", "compression": "{}", "checksum": {{"algorithm": "sum8"}},
+                        "data": {{"value": "hello hello hello hello"}}}}]"#,
+                    compression
+                )
+            };
+            let plain = payload(&entry("none"));
+            let vals = load_json(&entry(compression)).unwrap();
+            let compressed = payloads(&vals[0], &context(None)).remove(0);
+            assert_ne!(compressed, plain);
+            assert_eq!(decompress(vals[0].compression, &compressed), plain);
+        }
+    }
+
+    #[test]
+    fn empty_payloads_compress_to_valid_streams() {
+        for compression in [Compression::Gzip, Compression::Deflate, Compression::Zlib] {
+            let mut payload = Vec::new();
+            compression.apply(&mut payload).unwrap();
+            assert!(!payload.is_empty());
+            assert!(decompress(compression, &payload).is_empty());
+        }
+    }
+
+    #[test]
+    fn overlay_replaces_entries_in_place_and_appends_new_ones() {
+        let base = load_json(
+            r#"[
+                {"topic": "a", "data": {"value": 1, "width": "8"}},
+                {"topic": "b", "data": {"value": 2, "width": "8"}},
+                {"topic": "c", "data": {"value": 3, "width": "8"}}
+            ]"#,
+        )
+        .unwrap();
+        let overlay = load(
+            "# only b differs in this environment\n\
+             - topic: b\n  data: {value: 20, width: \"8\"}\n\
+             - topic: d\n  data: {value: 4, width: \"8\"}\n",
+            Format::Yaml,
+            None,
+        );
+        let merged = merge(base, overlay.unwrap());
+        let topics = merged.iter().map(Data::topic).collect::<Vec<_>>();
+        assert_eq!(topics, ["a", "b", "c", "d"]);
+        let payloads = merged
+            .iter()
+            .flat_map(|val| payloads(val, &context(None)))
+            .collect::<Vec<_>>();
+        assert_eq!(payloads, [[1], [20], [3], [4]]);
+    }
+
+    #[test]
+    fn overlay_entries_replace_repeated_topics_in_order() {
+        let base = load_json(
+            r#"[
+                {"topic": "a", "data": {"value": 1, "width": "8"}},
+                {"topic": "a", "data": {"value": 2, "width": "8"}}
+            ]"#,
+        )
+        .unwrap();
+        let overlay = load_json(r#"[{"topic": "a", "data": {"value": 10, "width": "8"}}]"#);
+        let merged = merge(base, overlay.unwrap());
+        let payloads = merged
+            .iter()
+            .flat_map(|val| payloads(val, &context(None)))
+            .collect::<Vec<_>>();
+        assert_eq!(payloads, [[10], [2]]);
+    }
+
+    #[test]
+    fn entry_defaults_reach_frames_of_fleet_copies() {
+        let text = r#"[{"topic": "t/{i}", "repeat": 2, "endian": "LittleEndian", "align": 4, "data": {
+            "fields": [
+                {"value": 1, "width": "8"},
+                {"value": 2, "width": "32"},
+                {"value": 3, "width": "16", "endian": "BigEndian"},
+                {"value": 4, "width": "8"}
+            ],
+            "checksum": {"algorithm": "Sum8"}
+        }}]"#;
+        let vals = load_json(text).unwrap();
+        assert_eq!(vals.len(), 2);
+        for val in &vals {
+            assert_eq!(
+                payloads(val, &context(None)),
+                [[1, 0, 0, 0, 2, 0, 0, 0, 0, 3, 4, 0, 10]]
+            );
+        }
+    }
+
+    #[test]
+    fn fleet_constants_are_drawn_once_per_device() {
+        let text = r#"[{"topic": "sensors/{serial}", "repeat": 3, "constants": {
+            "serial": {"min": 1000, "max": 9999, "integer": true},
+            "offset": {"min": -1, "max": 1}
+        }, "data": {"expression": "offset"}}]"#;
+        let vals = load(text, Format::Json, Some(7)).unwrap();
+        let topics = vals.iter().map(Data::topic).collect::<Vec<_>>();
+        assert_eq!(
+            topics.iter().collect::<HashSet<_>>().len(),
+            3,
+            "{:?}",
+            topics
+        );
+        for (val, topic) in vals.iter().zip(&topics) {
+            let serial = topic
+                .strip_prefix("sensors/")
+                .unwrap()
+                .parse::<u64>()
+                .unwrap();
+            assert!((1000..=9999).contains(&serial), "{}", serial);
+            let first = payloads(val, &context(None));
+            let offset = f64::from_be_bytes(<[u8; 8]>::try_from(&first[0][..]).unwrap());
+            assert!((-1. ..1.).contains(&offset), "{}", offset);
+            assert_eq!(payloads(val, &context(None)), first);
+        }
+        let again = load(text, Format::Json, Some(7)).unwrap();
+        assert_eq!(again.iter().map(Data::topic).collect::<Vec<_>>(), topics);
+    }
+
+    #[test]
+    fn constant_shadowing_a_builtin_is_rejected() {
+        let text = r#"[{"topic": "t", "constants": {"i": {"min": 0, "max": 1}}, "data": true}]"#;
+        let err = load_json(text).unwrap_err();
+        assert!(err.to_string().contains("shadows"), "{}", err);
+    }
+
+    #[test]
+    fn fleet_intervals_are_drawn_from_the_range() {
+        let text = r#"[{"topic": "t/{i}", "repeat": 8, "interval_range_ms": {"min": 100, "max": 200},
+            "data": true}]"#;
+        let intervals = |seed| {
+            load(text, Format::Json, seed)
+                .unwrap()
+                .iter()
+                .map(|val| val.interval().unwrap().as_millis() as u64)
+                .collect::<Vec<_>>()
+        };
+        let drawn = intervals(Some(3));
+        assert!(
+            drawn.iter().all(|ms| (100..=200).contains(ms)),
+            "{:?}",
+            drawn
+        );
+        assert!(drawn.iter().any(|ms| *ms != drawn[0]), "{:?}", drawn);
+        assert_eq!(intervals(Some(3)), drawn);
+        let both = r#"[{"topic": "t", "interval_ms": 5, "interval_range_ms": {"min": 1, "max": 2},
+            "data": true}]"#;
+        assert!(load_json(both).is_err());
+    }
+
+    #[test]
+    fn decimal_strings_stay_strings_without_a_width_of_128() {
+        let texts = [
+            (r#"{"value": "2"}"#, "2"),
+            (r#"{"value": "-10"}"#, "-10"),
+            (r#"{"value": "2", "width": "64"}"#, "2"),
+        ];
+        for (text, expected) in texts {
+            match serde_json::from_str(text).unwrap() {
+                Value::String { value, .. } => assert_eq!(value, expected),
+                other => panic!("{} parsed as {:?}", text, other),
+            }
+        }
+        assert_eq!(payload(r#"[{"topic": "t", "data": {"value": "2"}}]"#), b"2");
+    }
+
+    #[test]
+    fn frame_checksums_match_known_vectors() {
+        // the CRC-32 check value and a Modbus request, whose CRC is sent low byte first
+        let crc32 = payload(
+            r#"[{"topic": "t", "data": {
+                "fields": [{"value": "123456789"}],
+                "checksum": {"algorithm": "Crc32"}
+            }}]"#,
+        );
+        assert_eq!(crc32, b"123456789\xcb\xf4\x39\x26");
+        let crc16 = payload(
+            r#"[{"topic": "t", "data": {
+                "fields": [{"bytes": [1, 3, 0, 0, 0, 10]}],
+                "checksum": {"algorithm": "Crc16Modbus", "endian": "LittleEndian"}
+            }}]"#,
+        );
+        assert_eq!(crc16, [1, 3, 0, 0, 0, 10, 0xc5, 0xcd]);
+        let sum8 = payload(
+            r#"[{"topic": "t", "data": {
+                "fields": [{"bytes": [170, 1, 2, 3]}],
+                "checksum": {"algorithm": "Sum8", "over": {"start": 1}}
+            }}]"#,
+        );
+        assert_eq!(sum8, [170, 1, 2, 3, 6]);
+    }
+
+    #[test]
+    fn packed_struct_fields_land_at_their_offsets() {
+        // the order of the fields doesn't matter and the bytes between them stay zero
+        let payload = payload(
+            r#"[{"topic": "t", "data": {
+                "size": 10,
+                "fields": [
+                    {"offset": 4, "value": {"value": 2.5, "width": "32", "endian": "LittleEndian"}},
+                    {"offset": 0, "value": {"value": 1, "width": "16", "endian": "LittleEndian"}},
+                    {"offset": 8, "value": {"value": 258, "width": "16", "endian": "BigEndian"}}
+                ]
+            }}]"#,
+        );
+        assert_eq!(payload, [1, 0, 0, 0, 0, 0, 0x20, 0x40, 1, 2]);
+    }
+
+    #[test]
+    fn expressions_follow_operator_precedence() {
+        let expr = |expression: &str| {
+            payload(&format!(
+                r#"[{{"topic": "t", "data": {{"expression": "{}", "output": "Int", "width": "8"}}}}]"#,
+                expression
+            ))
+        };
+        assert_eq!(expr("2 + 3 * 4 ^ 2"), [50]);
+        assert_eq!(expr("(2 + 3) * 4"), [20]);
+        // the exponent binds tighter than the sign
+        assert_eq!(expr("-2 ^ 2 + 10 % 4"), [-2i8 as u8]);
+        // malformed expressions are rejected on load, unknown variables on every publish
+        for expression in ["(2 + 3", "2 3"] {
+            let text = format!(
+                r#"[{{"topic": "t", "data": {{"expression": "{}"}}}}]"#,
+                expression
+            );
+            assert!(load_json(&text).is_err(), "{}", expression);
+        }
+        let vals = load_json(r#"[{"topic": "t", "data": {"expression": "x + 1"}}]"#).unwrap();
+        let err = vals[0].values()[0].1.resolve(&context(None)).unwrap_err();
+        assert!(err.to_string().contains("x + 1"), "{}", err);
+    }
+
+    #[test]
+    fn flags_set_their_bits_from_either_end() {
+        let flags = |bit_order: &str| {
+            payload(&format!(
+                r#"[{{"topic": "t", "data": {{
+                    "active": ["running", "alarm"],
+                    "mapping": {{"running": 0, "door_open": 1, "alarm": 3}},
+                    "bit_order": "{}",
+                    "width": "16"
+                }}}}]"#,
+                bit_order
+            ))
+        };
+        assert_eq!(flags("LsbFirst"), [0x00, 0x09]);
+        assert_eq!(flags("MsbFirst"), [0x90, 0x00]);
+        let unknown =
+            r#"[{"topic": "t", "data": {"active": ["alarm"], "mapping": {"running": 0}}}]"#;
+        assert!(load_json(unknown).is_err());
+    }
+
+    #[test]
+    fn line_protocol_escapes_special_characters() {
+        let payload = payload(
+            r#"[{"topic": "t", "data": {
+                "measurement": "cpu load,avg",
+                "tags": {"host name": "a=b,c"},
+                "fields": {"msg": {"value": "say \"hi\" \\o/"}, "ok": true}
+            }}]"#,
+        );
+        assert_eq!(
+            String::from_utf8(payload).unwrap(),
+            r#"cpu\ load\,avg,host\ name=a\=b\,c msg="say \"hi\" \\o/",ok=true"#
+        );
+    }
+
+    #[test]
+    fn signed_values_carry_the_hmac_of_the_inner_bytes() {
+        // test case 2 of RFC 4231
+        let signed = |placement: &str, truncate: usize| {
+            payload(&format!(
+                r#"[{{"topic": "t", "data": {{
+                    "algorithm": "HmacSha256",
+                    "key": "4a656665",
+                    "placement": "{}",
+                    "truncate": {},
+                    "inner": {{"value": "what do ya want for nothing?"}}
+                }}}}]"#,
+                placement, truncate
+            ))
+        };
+        let digest =
+            hex::decode("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")
+                .unwrap();
+        let data = b"what do ya want for nothing?";
+        assert_eq!(signed("Suffix", 32), [&data[..], &digest].concat());
+        assert_eq!(signed("Prefix", 16), [&digest[..16], &data[..]].concat());
     }
 }
@@ -0,0 +1,569 @@
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::data::{
+    ArrayPrefix, Context, Endian, FloatWidth, IntWidth, LengthPrefix, Rounding, SampleKind, Value,
+};
+
+/// Serialization format for `Value`s.
+pub trait Encoder: Sync {
+    /// Encode `value` into `writer`.
+    fn encode(
+        &self,
+        value: &Value,
+        context: &Context,
+        writer: &mut dyn Write,
+    ) -> Result<(), io::Error>;
+}
+
+/// Selection of the available encoders.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub enum EncoderKind {
+    #[default]
+    #[serde(alias = "binary")]
+    Binary,
+    #[serde(alias = "json")]
+    Json,
+}
+
+impl EncoderKind {
+    /// Get the encoder for this kind.
+    pub fn encoder(self) -> &'static dyn Encoder {
+        match self {
+            EncoderKind::Binary => &Binary,
+            EncoderKind::Json => &Json,
+        }
+    }
+}
+
+impl std::str::FromStr for EncoderKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(EncoderKind::Binary),
+            "json" => Ok(EncoderKind::Json),
+            _ => anyhow::bail!("Unknown encoder {}, expected binary or json", s),
+        }
+    }
+}
+
+/// Raw binary encoding.
+///
+/// Numbers are written with their configured width and endianness, strings in their configured
+/// encoding and arrays as the concatenation of their items.
+pub struct Binary;
+
+impl Encoder for Binary {
+    fn encode(
+        &self,
+        value: &Value,
+        context: &Context,
+        writer: &mut dyn Write,
+    ) -> Result<(), io::Error> {
+        match value {
+            Value::Bool(b) => writer.write_all(&(*b as u8).to_ne_bytes()),
+            Value::TextNumber {
+                value,
+                decimals,
+                pad,
+                sign,
+                encoding,
+                bom,
+            } => {
+                let text = format_number(*value, *decimals, *pad, *sign);
+                encoding.encode(&text, bom.unwrap_or(context.utf16_bom), writer)
+            }
+            Value::Int {
+                value,
+                endian,
+                width,
+            } => write_int(*value, endian.unwrap_or_default(), *width, writer),
+            Value::UInt {
+                value,
+                endian,
+                width,
+            } => write_uint(*value, endian.unwrap_or_default(), *width, writer),
+            Value::Uptime {
+                unit,
+                endian,
+                width,
+            } => write_uint(
+                unit.elapsed(context.start).into(),
+                endian.unwrap_or_default(),
+                *width,
+                writer,
+            ),
+            Value::Index { index } => write_uint(
+                context.index as u128,
+                index.endian.unwrap_or_default(),
+                index.width,
+                writer,
+            ),
+            Value::ReconnectCount { reconnects } => write_uint(
+                context.stats.reconnects().into(),
+                reconnects.endian.unwrap_or_default(),
+                reconnects.width,
+                writer,
+            ),
+            Value::ReloadCount { reloads } => write_uint(
+                context.stats.reloads().into(),
+                reloads.endian.unwrap_or_default(),
+                reloads.width,
+                writer,
+            ),
+            Value::ConnectionState { connection_state } => write_uint(
+                connection_state.code(context.stats.connection()).into(),
+                connection_state.endian.unwrap_or_default(),
+                connection_state.width,
+                writer,
+            ),
+            Value::Float {
+                value,
+                endian,
+                width,
+                rounding,
+                denormals,
+                require_finite,
+            } => match (endian.unwrap_or_default(), width) {
+                (Endian::LittleEndian, FloatWidth::Thirtytwo) => {
+                    let value = narrow(*value, *rounding, context);
+                    check_finite(value as f64, *require_finite)?;
+                    writer.write_all(&denormals.apply_f32(value).to_le_bytes())
+                }
+                (Endian::LittleEndian, FloatWidth::Sixtyfour) => {
+                    check_finite(*value, *require_finite)?;
+                    writer.write_all(&denormals.apply_f64(*value).to_le_bytes())
+                }
+                (Endian::BigEndian, FloatWidth::Thirtytwo) => {
+                    let value = narrow(*value, *rounding, context);
+                    check_finite(value as f64, *require_finite)?;
+                    writer.write_all(&denormals.apply_f32(value).to_be_bytes())
+                }
+                (Endian::BigEndian, FloatWidth::Sixtyfour) => {
+                    check_finite(*value, *require_finite)?;
+                    writer.write_all(&denormals.apply_f64(*value).to_be_bytes())
+                }
+            },
+            Value::String {
+                value,
+                encoding,
+                framing,
+            } => framing.write(value, *encoding, context.utf16_bom, writer),
+            Value::Bytes { bytes } => writer.write_all(bytes),
+            Value::Array(array) => {
+                for value in array {
+                    self.encode(value, context, writer)?;
+                }
+                Ok(())
+            }
+            Value::PrefixedArray {
+                items,
+                prefix,
+                endian,
+            } => {
+                let mut buf = Vec::new();
+                for value in items {
+                    self.encode(value, context, &mut buf)?;
+                }
+                let (len, width) = match prefix {
+                    ArrayPrefix::None => return writer.write_all(&buf),
+                    ArrayPrefix::CountU16 => (items.len(), IntWidth::Sixteen),
+                    ArrayPrefix::CountU32 => (items.len(), IntWidth::Thirtytwo),
+                    ArrayPrefix::BytelenU32 => (buf.len(), IntWidth::Thirtytwo),
+                };
+                let prefix = LengthPrefix {
+                    width,
+                    endian: *endian,
+                };
+                prefix.write_len(len as u64, writer)?;
+                writer.write_all(&buf)
+            }
+            Value::ModbusRegisters {
+                registers,
+                word_order,
+            } => {
+                for register in registers {
+                    register.serialize(*word_order, writer)?;
+                }
+                Ok(())
+            }
+            Value::Samples {
+                kind,
+                values,
+                endian,
+                width,
+            } => {
+                for value in values {
+                    match (kind, endian.unwrap_or_default(), width) {
+                        (SampleKind::Int, _, _) => {
+                            write_int(*value as i128, endian.unwrap_or_default(), *width, writer)?
+                        }
+                        (SampleKind::UInt, _, _) => {
+                            write_uint(*value as u128, endian.unwrap_or_default(), *width, writer)?
+                        }
+                        (SampleKind::Float, Endian::LittleEndian, IntWidth::Thirtytwo) => {
+                            writer.write_all(&(*value as f32).to_le_bytes())?
+                        }
+                        (SampleKind::Float, Endian::BigEndian, IntWidth::Thirtytwo) => {
+                            writer.write_all(&(*value as f32).to_be_bytes())?
+                        }
+                        (SampleKind::Float, Endian::LittleEndian, _) => {
+                            writer.write_all(&value.to_le_bytes())?
+                        }
+                        (SampleKind::Float, Endian::BigEndian, _) => {
+                            writer.write_all(&value.to_be_bytes())?
+                        }
+                    }
+                }
+                Ok(())
+            }
+            #[cfg(feature = "http")]
+            Value::Http(_) => self.encode(&*value.resolve(context)?, context, writer),
+            #[cfg(feature = "sqlite")]
+            Value::Sqlite(_) => self.encode(&*value.resolve(context)?, context, writer),
+            Value::SysFile(_)
+            | Value::Expr(_)
+            | Value::MonotonicTime { .. }
+            | Value::MovingAverage { .. }
+            | Value::FixedPoint { .. }
+            | Value::Enum { .. }
+            | Value::RandomWalk { .. }
+            | Value::RandomInt { .. }
+            | Value::RandomFloat { .. }
+            | Value::Uuid { .. }
+            | Value::Timestamp { .. }
+            | Value::DateTime { .. }
+            | Value::Stats { .. }
+            | Value::Counter { .. }
+            | Value::Waveform { .. }
+            | Value::DutyCycle { .. }
+            | Value::LineProtocol { .. }
+            | Value::Phased { .. }
+            | Value::Anomalous(_)
+            | Value::Flags { .. }
+            | Value::Bits { .. } => self.encode(&*value.resolve(context)?, context, writer),
+            Value::RandomBytes { length, seed, rng } => {
+                let mut rng = rng.lock().unwrap();
+                let rng = rng.get_or_insert_with(|| context.rng(*seed));
+                let mut bytes = vec![0; *length];
+                rng.fill_bytes(&mut bytes);
+                writer.write_all(&bytes)
+            }
+            Value::Frame {
+                fields,
+                checksum,
+                align,
+            } => {
+                let mut frame = Vec::new();
+                let mut largest = 1;
+                for field in fields {
+                    let field = field.resolve(context)?;
+                    let alignment = field.alignment().min(align.unwrap_or(1));
+                    largest = largest.max(alignment);
+                    pad(&mut frame, alignment);
+                    self.encode(&field, context, &mut frame)?;
+                }
+                // like a C struct, the frame ends on a multiple of its largest field alignment
+                pad(&mut frame, largest);
+                writer.write_all(&frame)?;
+                checksum.write(&frame, writer)
+            }
+            Value::PackedStruct { size, fields } => {
+                let mut packed = vec![0; *size];
+                let mut written = vec![false; *size];
+                for field in fields {
+                    let mut buf = Vec::new();
+                    self.encode(&field.value, context, &mut buf)?;
+                    let range = field.offset..field.offset + buf.len();
+                    let target = written.get_mut(range.clone()).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "field at {}..{} exceeds struct of {} bytes",
+                                range.start, range.end, size
+                            ),
+                        )
+                    })?;
+                    if target.iter().any(|written| *written) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "field at {}..{} overlaps another field",
+                                range.start, range.end
+                            ),
+                        ));
+                    }
+                    target.iter_mut().for_each(|written| *written = true);
+                    packed[range].copy_from_slice(&buf);
+                }
+                writer.write_all(&packed)
+            }
+            Value::Encrypted(encrypted) => {
+                let mut plaintext = Vec::new();
+                self.encode(encrypted.inner(), context, &mut plaintext)?;
+                writer.write_all(&encrypted.encrypt(&plaintext)?)
+            }
+            Value::Signed(signed) => {
+                let mut payload = Vec::new();
+                self.encode(signed.inner(), context, &mut payload)?;
+                writer.write_all(&signed.sign(&payload)?)
+            }
+            Value::File { path, bytes } => writer.write_all(bytes.get().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} wasn't read", path.display()),
+                )
+            })?),
+            Value::Tail(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "tail values are published line by line",
+            )),
+            Value::Cbor { cbor } => ciborium::into_writer(cbor, writer).map_err(|e| match e {
+                ciborium::ser::Error::Io(e) => e,
+                e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+            }),
+            Value::MsgPack { msgpack } => rmp_serde::encode::write(writer, msgpack)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            Value::EncodedJSON {
+                json,
+                encoding,
+                bom,
+                length_prefix,
+                pretty,
+            } => {
+                // errors other than I/O keep serde_json's message as InvalidData
+                let text = if *pretty {
+                    serde_json::to_string_pretty(json)?
+                } else {
+                    serde_json::to_string(json)?
+                };
+                let bom = bom.unwrap_or(context.utf16_bom);
+                match length_prefix {
+                    Some(prefix) => {
+                        let mut buf = Vec::new();
+                        encoding.encode(&text, bom, &mut buf)?;
+                        prefix.write(&buf, writer)
+                    }
+                    None => encoding.encode(&text, bom, writer),
+                }
+            }
+            Value::JSON(value) => {
+                serde_json::to_writer(writer, value)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// JSON encoding.
+///
+/// Scalars become JSON scalars and arrays JSON arrays, width, endianness and string encodings are
+/// ignored. Values without a natural JSON representation are written as arrays of their binary
+/// encoded bytes.
+pub struct Json;
+
+impl Json {
+    fn to_json(&self, value: &Value, context: &Context) -> Result<serde_json::Value, io::Error> {
+        let json = match value {
+            Value::Bool(b) => json!(b),
+            Value::TextNumber {
+                value,
+                decimals,
+                pad,
+                sign,
+                ..
+            } => json!(format_number(*value, *decimals, *pad, *sign)),
+            // JSON numbers can't hold integers beyond 64 bits
+            Value::UInt { value, .. } => u64::try_from(*value)
+                .map_or_else(|_| json!(value.to_string()), |value| json!(value)),
+            Value::Int { value, .. } => i64::try_from(*value)
+                .map_or_else(|_| json!(value.to_string()), |value| json!(value)),
+            Value::Float {
+                value,
+                require_finite,
+                ..
+            } => {
+                check_finite(*value, *require_finite)?;
+                json!(value)
+            }
+            Value::Uptime { unit, .. } => json!(unit.elapsed(context.start)),
+            Value::Index { .. } => json!(context.index),
+            Value::ReconnectCount { .. } => json!(context.stats.reconnects()),
+            Value::ReloadCount { .. } => json!(context.stats.reloads()),
+            Value::ConnectionState { connection_state } => {
+                json!(connection_state.code(context.stats.connection()))
+            }
+            Value::String { value, .. } => json!(value),
+            Value::Bytes { bytes } => json!(bytes),
+            Value::Array(array) | Value::PrefixedArray { items: array, .. } => array
+                .iter()
+                .map(|value| self.to_json(value, context))
+                .collect::<Result<_, _>>()?,
+            Value::Samples { kind, values, .. } => match kind {
+                SampleKind::Int => json!(values.iter().map(|v| *v as i64).collect::<Vec<_>>()),
+                SampleKind::UInt => json!(values.iter().map(|v| *v as u64).collect::<Vec<_>>()),
+                SampleKind::Float => json!(values),
+            },
+            #[cfg(feature = "http")]
+            Value::Http(_) => self.to_json(&*value.resolve(context)?, context)?,
+            #[cfg(feature = "sqlite")]
+            Value::Sqlite(_) => self.to_json(&*value.resolve(context)?, context)?,
+            Value::SysFile(_)
+            | Value::Expr(_)
+            | Value::MonotonicTime { .. }
+            | Value::MovingAverage { .. }
+            | Value::FixedPoint { .. }
+            | Value::Enum { .. }
+            | Value::RandomWalk { .. }
+            | Value::RandomInt { .. }
+            | Value::RandomFloat { .. }
+            | Value::Uuid { .. }
+            | Value::Timestamp { .. }
+            | Value::DateTime { .. }
+            | Value::Stats { .. }
+            | Value::Counter { .. }
+            | Value::Waveform { .. }
+            | Value::DutyCycle { .. }
+            | Value::LineProtocol { .. }
+            | Value::Phased { .. }
+            | Value::Anomalous(_)
+            | Value::Flags { .. }
+            | Value::Bits { .. } => self.to_json(&*value.resolve(context)?, context)?,
+            Value::Cbor { cbor: json }
+            | Value::MsgPack { msgpack: json }
+            | Value::EncodedJSON { json, .. } => json.clone(),
+            Value::JSON(value) => value.clone(),
+            value => {
+                let mut buf = Vec::new();
+                Binary.encode(value, context, &mut buf)?;
+                json!(buf)
+            }
+        };
+        Ok(json)
+    }
+}
+
+impl Encoder for Json {
+    fn encode(
+        &self,
+        value: &Value,
+        context: &Context,
+        writer: &mut dyn Write,
+    ) -> Result<(), io::Error> {
+        let json = self.to_json(value, context)?;
+        match value {
+            Value::EncodedJSON { pretty: true, .. } => serde_json::to_writer_pretty(writer, &json)?,
+            _ => serde_json::to_writer(writer, &json)?,
+        }
+        Ok(())
+    }
+}
+
+/// Fail on NaN and infinities if the value requires a finite float.
+fn check_finite(value: f64, require_finite: bool) -> Result<(), io::Error> {
+    if require_finite && !value.is_finite() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("float {} is not finite", value),
+        ));
+    }
+    Ok(())
+}
+
+/// Narrow a float to 32 bits, warning about lost precision if the context asks for it.
+///
+/// Finite values overflowing to an infinity are always warned about.
+fn narrow(value: f64, rounding: Rounding, context: &Context) -> f32 {
+    let narrowed = rounding.narrow(value);
+    if value.is_finite() && narrowed.is_infinite() {
+        log::warn!(
+            "{}: {} overflows a 32 bit float to {}",
+            context.topic,
+            value,
+            narrowed
+        );
+        return narrowed;
+    }
+    if context.warn_precision_loss && !value.is_nan() && narrowed as f64 != value {
+        log::warn!(
+            "{}: {} loses precision as a 32 bit float, published as {}",
+            context.topic,
+            value,
+            narrowed as f64
+        );
+    }
+    narrowed
+}
+
+/// Format a number with `decimals` digits after the point, zero padded to at least `pad`
+/// characters and with a leading `+` on non-negative numbers if `sign` is set.
+fn format_number(value: f64, decimals: usize, pad: usize, sign: bool) -> String {
+    if sign {
+        format!("{:+0pad$.decimals$}", value, pad = pad, decimals = decimals)
+    } else {
+        format!("{:0pad$.decimals$}", value, pad = pad, decimals = decimals)
+    }
+}
+
+/// Write a signed integer with the given endianness and width.
+pub(crate) fn write_int(
+    value: i128,
+    endian: Endian,
+    width: IntWidth,
+    writer: &mut dyn Write,
+) -> Result<(), io::Error> {
+    match (endian, width) {
+        (_, IntWidth::Eight) => writer.write_all(&(value as i8).to_ne_bytes()),
+        (Endian::LittleEndian, IntWidth::Sixteen) => {
+            writer.write_all(&(value as i16).to_le_bytes())
+        }
+        (Endian::LittleEndian, IntWidth::Thirtytwo) => {
+            writer.write_all(&(value as i32).to_le_bytes())
+        }
+        (Endian::LittleEndian, IntWidth::Sixtyfour) => {
+            writer.write_all(&(value as i64).to_le_bytes())
+        }
+        (Endian::LittleEndian, IntWidth::OneTwentyEight) => writer.write_all(&value.to_le_bytes()),
+        (Endian::BigEndian, IntWidth::Sixteen) => writer.write_all(&(value as i16).to_be_bytes()),
+        (Endian::BigEndian, IntWidth::Thirtytwo) => writer.write_all(&(value as i32).to_be_bytes()),
+        (Endian::BigEndian, IntWidth::Sixtyfour) => writer.write_all(&(value as i64).to_be_bytes()),
+        (Endian::BigEndian, IntWidth::OneTwentyEight) => writer.write_all(&value.to_be_bytes()),
+    }
+}
+
+/// Write an unsigned integer with the given endianness and width.
+pub(crate) fn write_uint(
+    value: u128,
+    endian: Endian,
+    width: IntWidth,
+    writer: &mut dyn Write,
+) -> Result<(), io::Error> {
+    match (endian, width) {
+        (_, IntWidth::Eight) => writer.write_all(&(value as u8).to_ne_bytes()),
+        (Endian::LittleEndian, IntWidth::Sixteen) => {
+            writer.write_all(&(value as u16).to_le_bytes())
+        }
+        (Endian::LittleEndian, IntWidth::Thirtytwo) => {
+            writer.write_all(&(value as u32).to_le_bytes())
+        }
+        (Endian::LittleEndian, IntWidth::Sixtyfour) => {
+            writer.write_all(&(value as u64).to_le_bytes())
+        }
+        (Endian::LittleEndian, IntWidth::OneTwentyEight) => writer.write_all(&value.to_le_bytes()),
+        (Endian::BigEndian, IntWidth::Sixteen) => writer.write_all(&(value as u16).to_be_bytes()),
+        (Endian::BigEndian, IntWidth::Thirtytwo) => writer.write_all(&(value as u32).to_be_bytes()),
+        (Endian::BigEndian, IntWidth::Sixtyfour) => writer.write_all(&(value as u64).to_be_bytes()),
+        (Endian::BigEndian, IntWidth::OneTwentyEight) => writer.write_all(&value.to_be_bytes()),
+    }
+}
+
+/// Pad `buf` with zeros to a multiple of `alignment` bytes.
+fn pad(buf: &mut Vec<u8>, alignment: usize) {
+    let len = buf.len().next_multiple_of(alignment);
+    buf.resize(len, 0);
+}
@@ -0,0 +1,1428 @@
+//! Tasks publishing the data and driving the MQTT connections.
+
+use std::collections::{HashMap, HashSet};
+use std::future;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+use rumqttc::{
+    ConnectionError, Event, EventLoop, MqttOptions, Outgoing, Packet, PubAck, PubComp, Publish,
+    QoS, Request, Sender, Subscribe, Transport, TrySendError,
+};
+use tokio::{
+    select,
+    sync::{mpsc, watch},
+    task,
+    time::{sleep, sleep_until, timeout, Interval},
+};
+
+use crate::backoff::Backoff;
+use crate::chaos::Chaos;
+use crate::data::{Context, Data, MissedTicks};
+use crate::encoder::EncoderKind;
+use crate::history::History;
+#[cfg(feature = "http")]
+use crate::http;
+use crate::ping::Ping;
+use crate::ratelimit::RateLimit;
+use crate::record::Recorder;
+use crate::schema::Schema;
+use crate::stats::{ConnectionState, Stats};
+
+/// Get a copy of `opts` connecting under `client_id`.
+fn with_client_id(opts: &MqttOptions, client_id: &str) -> MqttOptions {
+    let (host, port) = opts.broker_address();
+    copy_options(opts, client_id, host, port)
+}
+
+/// Get a copy of `opts` connecting to another broker.
+pub fn with_broker(opts: &MqttOptions, host: String, port: u16) -> MqttOptions {
+    copy_options(opts, &opts.client_id(), host, port)
+}
+
+fn copy_options(opts: &MqttOptions, client_id: &str, host: String, port: u16) -> MqttOptions {
+    let mut session = MqttOptions::new(client_id, host, port);
+    session
+        .set_transport(opts.transport())
+        .set_keep_alive(opts.keep_alive().as_secs() as u16)
+        .set_clean_session(opts.clean_session())
+        .set_request_channel_capacity(opts.request_channel_capacity())
+        .set_pending_throttle(opts.pending_throttle())
+        .set_inflight(opts.inflight())
+        .set_connection_timeout(opts.connection_timeout());
+    if let Some((username, password)) = opts.credentials() {
+        session.set_credentials(username, password);
+    }
+    if let Some(will) = opts.last_will() {
+        session.set_last_will(will);
+    }
+    session
+}
+
+/// MQTT sessions of entries publishing under their own client id.
+pub struct Sessions {
+    opts: MqttOptions,
+    /// Capacity of the request queue of every session.
+    capacity: usize,
+    backoff: Backoff,
+    stats: Arc<Stats>,
+    shutdown: Shutdown,
+    /// Transports with rotated TLS certificates the sessions reconnect with.
+    transports: Option<watch::Receiver<Transport>>,
+    /// Request queue of each session and the signal closing it.
+    sessions: HashMap<String, (Queue, watch::Sender<bool>)>,
+}
+
+impl Sessions {
+    pub fn new(
+        opts: MqttOptions,
+        capacity: usize,
+        backoff: Backoff,
+        stats: Arc<Stats>,
+        shutdown: Shutdown,
+    ) -> Self {
+        Sessions {
+            opts,
+            capacity,
+            backoff,
+            stats,
+            shutdown,
+            transports: None,
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// Reconnect the sessions with every transport sent to `transports`.
+    pub fn reload_transport(mut self, transports: watch::Receiver<Transport>) -> Self {
+        self.transports = Some(transports);
+        self
+    }
+
+    /// Get the request queue of the session for `client_id`, connecting it if necessary.
+    fn queue(&mut self, client_id: &str) -> Queue {
+        let Sessions {
+            opts,
+            capacity,
+            backoff,
+            stats,
+            shutdown,
+            transports,
+            sessions,
+        } = self;
+        let (queue, _) = sessions.entry(client_id.to_string()).or_insert_with(|| {
+            log::info!("Opening session for {}", client_id);
+            let eventloop = EventLoop::new(with_client_id(opts, client_id), *capacity);
+            let queue = Queue::of(&eventloop);
+            // closing a session drains it like a shutdown, which also closes it
+            let (close, signal) = watch::channel(false);
+            let mut simulator = shutdown.clone();
+            let forward = close.clone();
+            task::spawn(async move {
+                select! {
+                    _ = simulator.requested() => {
+                        forward.send_replace(true);
+                    }
+                    _ = forward.closed() => {}
+                }
+            });
+            let handlers = Handlers {
+                transports: transports.clone(),
+                ..Handlers::default()
+            };
+            task::spawn(eventloop_task(
+                eventloop,
+                handlers,
+                *backoff,
+                None,
+                Arc::clone(stats),
+                Shutdown {
+                    signal,
+                    drain_timeout: shutdown.drain_timeout,
+                },
+            ));
+            (queue, close)
+        });
+        queue.clone()
+    }
+
+    /// Close the sessions of client ids not used by any entry in `vals`.
+    ///
+    /// Requests already queued for a closed session are still sent.
+    fn retain(&mut self, vals: &[Data]) {
+        self.sessions.retain(|client_id, (_, close)| {
+            let used = vals.iter().any(|val| val.client_id() == Some(client_id));
+            if !used {
+                log::info!("Closing session for {}", client_id);
+                close.send_replace(true);
+            }
+            used
+        });
+    }
+}
+
+/// Request queue of an event loop, the receiving end lets queued publishes be shed.
+#[derive(Clone)]
+pub struct Queue {
+    pub sink: Sender<Request>,
+    pub requests: async_channel::Receiver<Request>,
+}
+
+impl Queue {
+    /// Get the request queue of `eventloop`.
+    pub fn of(eventloop: &EventLoop) -> Self {
+        Queue {
+            sink: eventloop.handle(),
+            requests: eventloop.requests_rx.clone(),
+        }
+    }
+}
+
+/// Handling of publishes that don't fit into the full request queue of an event loop.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait until the event loop takes a request, holding up all other entries.
+    #[default]
+    Block,
+    /// Drop the oldest queued publish to make room.
+    DropOldest,
+    /// Drop the publish that doesn't fit.
+    DropNewest,
+}
+
+impl FromStr for Backpressure {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(Backpressure::Block),
+            "drop-oldest" => Ok(Backpressure::DropOldest),
+            "drop-newest" => Ok(Backpressure::DropNewest),
+            _ => anyhow::bail!(
+                "Unknown backpressure policy {}, expected block, drop-oldest or drop-newest",
+                s
+            ),
+        }
+    }
+}
+
+/// Error of a request queue whose event loop is gone.
+fn closed<T>(_: T) -> anyhow::Error {
+    anyhow::anyhow!("The event loop stopped taking requests")
+}
+
+/// Queue a publish without waiting, shedding it or the oldest queued one if the queue is full.
+///
+/// Gets whether the publish was queued.
+async fn try_queue(
+    msg: Publish,
+    queue: &Queue,
+    backpressure: Backpressure,
+    stats: &Stats,
+) -> Result<bool> {
+    let mut request = Request::Publish(msg);
+    // other requests taken from the front are queued again, at most one queue length of them
+    let mut requeued = 0;
+    loop {
+        request = match queue.sink.try_send(request) {
+            Ok(()) => return Ok(true),
+            Err(TrySendError::Closed(_)) => return Err(closed(())),
+            Err(TrySendError::Full(request)) => request,
+        };
+        if backpressure == Backpressure::DropNewest {
+            if let Request::Publish(msg) = &request {
+                log::warn!(
+                    "Dropping publish on {}, the request queue is full",
+                    msg.topic
+                );
+            }
+            stats.record_shed();
+            return Ok(false);
+        }
+        match queue.requests.try_recv() {
+            Ok(Request::Publish(old)) => {
+                log::warn!(
+                    "Dropping queued publish on {}, the request queue is full",
+                    old.topic
+                );
+                stats.record_shed();
+            }
+            Ok(other) if requeued < queue.sink.capacity().unwrap_or(0) => {
+                requeued += 1;
+                queue.sink.send(other).await.map_err(closed)?;
+            }
+            // nothing but other requests is queued, so the publish waits for its turn
+            Ok(other) => {
+                queue.sink.send(other).await.map_err(closed)?;
+                queue.sink.send(request).await.map_err(closed)?;
+                return Ok(true);
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Send a publish unless its value goes stale at `deadline` before the request is accepted.
+async fn send_fresh(
+    queue: &Queue,
+    msg: Publish,
+    deadline: Option<tokio::time::Instant>,
+    backpressure: Backpressure,
+    stats: &Stats,
+) -> Result<()> {
+    let len = msg.payload.len();
+    let topic = msg.topic.clone();
+    let sent = match deadline {
+        Some(deadline) if tokio::time::Instant::now() >= deadline => None,
+        _ if backpressure != Backpressure::Block => {
+            Some(try_queue(msg, queue, backpressure, stats).await?)
+        }
+        Some(deadline) => {
+            let send = queue.sink.send(Request::Publish(msg));
+            match tokio::time::timeout_at(deadline, send).await {
+                Ok(sent) => Some(sent.map(|()| true).map_err(closed)?),
+                Err(_) => None,
+            }
+        }
+        None => Some(
+            queue
+                .sink
+                .send(Request::Publish(msg))
+                .await
+                .map(|()| true)
+                .map_err(closed)?,
+        ),
+    };
+    match sent {
+        Some(true) => stats.record_publish(&topic, len),
+        // shed publishes are counted by try_queue
+        Some(false) => {}
+        None => {
+            log::debug!("Skipping stale publish on {}", topic);
+            stats.record_stale();
+        }
+    }
+    Ok(())
+}
+
+/// Default number of requests queued for an event loop before publishes are held up or shed.
+pub const REQUEST_CAPACITY: usize = 10;
+
+/// Largest payload an MQTT packet can carry, its remaining length is limited to 256 MB.
+pub const MAX_PAYLOAD_BYTES: usize = 268_435_455;
+
+/// Settings of the sender task.
+pub struct SenderConfig {
+    /// Time between publishes of entries without their own interval.
+    pub interval: Duration,
+    /// Maximum random deviation from the interval for entries without their own jitter.
+    pub jitter: Duration,
+    /// Time publishes of entries without their own delay are held before they are sent.
+    pub send_delay: Duration,
+    /// Maximum age of a computed value, older values are skipped instead of sent.
+    pub value_ttl: Option<Duration>,
+    pub encoder: EncoderKind,
+    pub drop_rate: f64,
+    pub shuffle: bool,
+    /// Spread the first publishes of entries without a phase evenly over their interval.
+    pub stagger: bool,
+    pub dedup: bool,
+    pub max_packet_size: Option<usize>,
+    /// Payload size beyond which publishes are warned about, or skipped if `strict_payload_size`.
+    pub max_payload_bytes: usize,
+    pub strict_payload_size: bool,
+    pub rng: StdRng,
+    pub history: Option<Arc<History>>,
+    pub recording: Option<Recorder>,
+    pub sessions: Sessions,
+    /// Request queues of the brokers by name, for the entries published to a single broker.
+    pub brokers: HashMap<String, Queue>,
+    /// Maximum age of the data file and its modification time.
+    pub freshness: Option<(Duration, watch::Receiver<SystemTime>)>,
+    /// Whether the simulator is active rather than on standby.
+    pub active: watch::Receiver<bool>,
+    /// Revisions of the data to publish in order instead of only the latest one.
+    pub revisions: Option<mpsc::UnboundedReceiver<Vec<Data>>>,
+    /// Print the payloads instead of publishing them.
+    pub dry_run: bool,
+    /// Stop after the first tick once its publishes are handed to the event loop.
+    pub once: bool,
+    /// Faults injected into the publishes, drops are part of `drop_rate`.
+    pub chaos: Chaos,
+    /// Ceiling of the publishes per second across all entries.
+    pub rate_limit: Option<RateLimit>,
+    /// Handling of publishes when the request queue is full.
+    pub backpressure: Backpressure,
+    pub context: Context,
+}
+
+pub async fn sender(
+    mut rx: watch::Receiver<Vec<Data>>,
+    queue: Queue,
+    config: SenderConfig,
+) -> Result<()> {
+    let SenderConfig {
+        interval,
+        jitter,
+        send_delay,
+        value_ttl,
+        encoder,
+        drop_rate,
+        shuffle,
+        stagger,
+        dedup,
+        max_packet_size,
+        max_payload_bytes,
+        strict_payload_size,
+        mut rng,
+        history,
+        recording,
+        mut sessions,
+        brokers,
+        freshness,
+        active,
+        mut revisions,
+        dry_run,
+        once,
+        chaos,
+        mut rate_limit,
+        backpressure,
+        context,
+    } = config;
+    let sink = &queue.sink;
+    #[cfg(feature = "http")]
+    let client = reqwest::Client::new();
+    let mut dropped = 0u64;
+    // delayed publishes of a single tick, awaited before finishing
+    let mut held = Vec::new();
+    // last payload per topic and component of entries with deduplication
+    let mut last_payloads = HashMap::<(String, Option<String>), Vec<u8>>::new();
+    let mut fresh = true;
+    // next publish of each entry, identified by its position and topic in the data file
+    let mut deadlines = HashMap::<(usize, String), tokio::time::Instant>::new();
+    // client id and topics of each entry holding a retained message, cleared once it's removed
+    let mut retained = HashMap::<(usize, String), (Option<String>, HashSet<String>)>::new();
+    let mut current = rx.borrow().clone();
+    loop {
+        if !*active.borrow() {
+            sleep(interval).await;
+            continue;
+        }
+        if let Some((timeout, mtime)) = &freshness {
+            let age = mtime.borrow().elapsed().unwrap_or_default();
+            if fresh != (age <= *timeout) {
+                fresh = !fresh;
+                if fresh {
+                    log::info!("Data file was refreshed, resuming publishes");
+                } else {
+                    log::warn!("Data file not refreshed for {:?}, pausing publishes", age);
+                }
+            }
+            if !fresh {
+                sleep(interval).await;
+                continue;
+            }
+        }
+        context.stats.record_sender_progress();
+        rx.borrow_and_update();
+        let vals = match &mut revisions {
+            // the next revision replaces the current one only after it was published once
+            Some(revisions) => {
+                if let Ok(next) = revisions.try_recv() {
+                    current = next;
+                    deadlines.clear();
+                }
+                current.clone()
+            }
+            None => rx.borrow().clone(),
+        };
+        context.averages.track(&vals);
+        let present = |(position, topic): &(usize, String)| {
+            vals.get(*position).map(|val| val.topic()) == Some(topic)
+        };
+        deadlines.retain(|key, _| present(key));
+        let removed = retained.keys().filter(|key| !present(key)).cloned();
+        // cleared before unused sessions are closed, so they go out through the entry's session
+        for key in removed.collect::<Vec<_>>() {
+            let (client_id, topics) = retained.remove(&key).unwrap_or_default();
+            let queue = match &client_id {
+                Some(client_id) => sessions.queue(client_id),
+                None => queue.clone(),
+            };
+            for topic in topics {
+                log::info!("Clearing retained message on {}", topic);
+                let mut msg = Publish::new(topic, QoS::AtLeastOnce, Vec::new());
+                msg.retain = true;
+                send_fresh(&queue, msg, None, backpressure, &context.stats).await?;
+            }
+        }
+        sessions.retain(&vals);
+        let now = tokio::time::Instant::now();
+        let mut due = Vec::new();
+        let entries = vals.len() as u32;
+        for (position, val) in vals.into_iter().enumerate() {
+            // a single tick has to publish every entry, so it can't wait for their phases
+            let phase = match val.phase() {
+                _ if once => Duration::ZERO,
+                Some(phase) => phase,
+                None if stagger => val.interval().unwrap_or(interval) * position as u32 / entries,
+                None => Duration::ZERO,
+            };
+            let deadline = deadlines
+                .entry((position, val.topic().to_string()))
+                .or_insert(now + phase);
+            if *deadline <= now {
+                let period = val.interval().unwrap_or(interval);
+                let period = jittered(period, val.jitter().unwrap_or(jitter), &mut rng);
+                *deadline += period;
+                if *deadline <= now {
+                    match val.missed_ticks() {
+                        // the deadline stays in the past, so the entry is due again right away
+                        MissedTicks::Burst => {}
+                        MissedTicks::Skip if !period.is_zero() => {
+                            let missed = (now - *deadline).as_nanos() / period.as_nanos() + 1;
+                            *deadline += period * missed as u32;
+                        }
+                        MissedTicks::Delay | MissedTicks::Skip => *deadline = now + period,
+                    }
+                }
+                due.push((position, val));
+            }
+        }
+        if shuffle {
+            due.shuffle(&mut rng);
+        }
+        // the broker closes the connection after the disconnect, and the event loop reconnects
+        if !due.is_empty() && chaos.flaps(&mut rng) {
+            log::info!("Chaos: disconnecting from the broker");
+            sink.send(Request::Disconnect).await.map_err(closed)?;
+        }
+        for (position, val) in due {
+            if !val.is_active(SystemTime::now()) {
+                log::debug!(
+                    "Skipped publish on {} outside its active window",
+                    val.topic()
+                );
+                continue;
+            }
+            if rng.gen::<f64>() < val.drop_rate().unwrap_or(drop_rate) {
+                dropped += 1;
+                log::debug!(
+                    "Dropped publish on {}, {} dropped so far",
+                    val.topic(),
+                    dropped
+                );
+                continue;
+            }
+            let probability = val.publish_probability(SystemTime::now());
+            if probability < 1. && rng.gen::<f64>() >= probability {
+                log::debug!(
+                    "Skipped publish on {} with probability {:.3}",
+                    val.topic(),
+                    probability
+                );
+                continue;
+            }
+            let values = val.values();
+            if val.is_group() {
+                let components = values.iter().filter_map(|(component, _)| *component);
+                let components = components.collect::<Vec<_>>().join(", ");
+                log::debug!("Publishing group {}: {}", val.topic(), components);
+            }
+            if val.topics().len() > 1 {
+                log::debug!("Publishing to {}", val.topics().join(", "));
+            }
+            let encoder = val.encoder().unwrap_or(encoder).encoder();
+            let context = Context {
+                index: val.index(),
+                topic: Arc::from(val.topic()),
+                entry_rng: val.rng(),
+                constants: val.constants(),
+                ..context.clone()
+            };
+            let queue = match (val.client_id(), val.broker()) {
+                _ if dry_run => queue.clone(),
+                (Some(client_id), _) => sessions.queue(client_id),
+                (None, Some(broker)) => match brokers.get(broker) {
+                    Some(queue) => queue.clone(),
+                    None => {
+                        log::error!(
+                            "{} is published to the unknown broker {}",
+                            val.topic(),
+                            broker
+                        );
+                        continue;
+                    }
+                },
+                (None, None) => queue.clone(),
+            };
+            for (component, value) in values {
+                #[cfg(feature = "http")]
+                http::refresh(value, &client).await;
+                let values = match value.resolve_all(&context) {
+                    Ok(values) => values,
+                    Err(e) => {
+                        log::error!("Failed to serialize {}: {}", val.topic(), e);
+                        continue;
+                    }
+                };
+                let computed = tokio::time::Instant::now();
+                for value in values {
+                    let mut buf = Vec::new();
+                    if let Err(e) = value
+                        .serialize(encoder, &context, &mut buf)
+                        .and_then(|()| val.finish_payload(&mut buf))
+                    {
+                        log::error!("Failed to serialize {}: {}", val.topic(), e);
+                        continue;
+                    }
+                    if buf.len() > max_payload_bytes {
+                        if strict_payload_size {
+                            log::error!(
+                                "Skipping publish on {}, payload of {} bytes exceeds the maximum of {}",
+                                val.topic(),
+                                buf.len(),
+                                max_payload_bytes
+                            );
+                            continue;
+                        }
+                        log::warn!(
+                            "Payload of {} bytes on {} exceeds the maximum of {}",
+                            buf.len(),
+                            val.topic(),
+                            max_payload_bytes
+                        );
+                    }
+                    if let Some(number) = value.number(&context) {
+                        context
+                            .averages
+                            .record(&val.component_topic(component), number);
+                    }
+                    if val.dedup().unwrap_or(dedup) {
+                        let key = (val.topic().to_string(), component.map(ToOwned::to_owned));
+                        if last_payloads.get(&key) == Some(&buf) {
+                            log::debug!("Skipping unchanged payload on {}", val.topic());
+                            context.stats.record_dedup();
+                            continue;
+                        }
+                        last_payloads.insert(key, buf.clone());
+                    }
+                    let topics = val.publish_topics(component, &buf, &mut rng);
+                    for (topic, qos) in val.publish_targets(topics) {
+                        let size = packet_size(&topic, qos, buf.len());
+                        if let Some(max_packet_size) = max_packet_size.filter(|max| size > *max) {
+                            log::warn!(
+                                "Skipping publish on {}, packet of {} bytes exceeds the maximum of {}",
+                                topic,
+                                size,
+                                max_packet_size
+                            );
+                            continue;
+                        }
+                        let mut payload = buf.clone();
+                        if chaos.corrupt(&mut payload, &mut rng) {
+                            log::debug!("Chaos: corrupted the payload on {}", topic);
+                        }
+                        let duplicate = chaos.duplicates(&mut rng);
+                        if duplicate {
+                            log::debug!("Chaos: duplicating the publish on {}", topic);
+                        }
+                        if dry_run {
+                            print_payload(&topic, &payload);
+                            if duplicate {
+                                print_payload(&topic, &payload);
+                            }
+                            continue;
+                        }
+                        if let Some(history) = &history {
+                            history.record(&topic, &payload);
+                        }
+                        if let Some(recording) = &recording {
+                            if let Err(e) = recording.record(&topic, qos, val.retain(), &payload) {
+                                log::error!("Failed to record publish on {}: {}", topic, e);
+                            }
+                        }
+                        if val.retain() {
+                            retained
+                                .entry((position, val.topic().to_string()))
+                                .or_insert_with(|| {
+                                    (val.client_id().map(ToOwned::to_owned), HashSet::new())
+                                })
+                                .1
+                                .insert(topic.clone());
+                        }
+                        let mut msg = Publish::new(topic, qos, payload);
+                        msg.retain = val.retain();
+                        let msgs = if duplicate {
+                            vec![msg.clone(), msg]
+                        } else {
+                            vec![msg]
+                        };
+                        for msg in msgs {
+                            if let Some(rate_limit) = &mut rate_limit {
+                                context.stats.record_sender_idle();
+                                rate_limit.acquire().await;
+                                context.stats.record_sender_progress();
+                            }
+                            let delay = val.send_delay().unwrap_or(send_delay);
+                            let deadline = value_ttl.map(|ttl| computed + ttl);
+                            if delay.is_zero() {
+                                send_fresh(&queue, msg, deadline, backpressure, &context.stats)
+                                    .await?;
+                                context.stats.record_sender_progress();
+                            } else {
+                                // held in its own task so other topics aren't delayed
+                                let queue = queue.clone();
+                                let stats = Arc::clone(&context.stats);
+                                let send = task::spawn(async move {
+                                    sleep(delay).await;
+                                    let sent =
+                                        send_fresh(&queue, msg, deadline, backpressure, &stats)
+                                            .await;
+                                    if let Err(e) = &sent {
+                                        log::error!("Failed to send a delayed publish: {}", e);
+                                    }
+                                    sent
+                                });
+                                if once {
+                                    held.push(send);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if once {
+            // delayed publishes wait on purpose
+            context.stats.record_sender_idle();
+            for send in held {
+                send.await??;
+            }
+            return Ok(());
+        }
+        let next = deadlines.values().min().copied();
+        let next = next.unwrap_or_else(|| tokio::time::Instant::now() + interval);
+        if revisions
+            .as_ref()
+            .is_some_and(|revisions| !revisions.is_empty())
+        {
+            continue;
+        }
+        context.stats.record_sender_idle();
+        // reloaded data is scheduled right away, new entries publish without waiting
+        select! {
+            _ = sleep_until(next) => {}
+            Ok(()) = rx.changed() => {}
+        }
+    }
+}
+
+/// Print the topic, length and a hex dump of a payload to stdout.
+fn print_payload(topic: &str, payload: &[u8]) {
+    let mut dump = format!("{} ({} bytes)\n", topic, payload.len());
+    for (line, chunk) in payload.chunks(16).enumerate() {
+        let hex = chunk
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>();
+        let text = chunk
+            .iter()
+            .map(|b| match b {
+                0x20..=0x7e => *b as char,
+                _ => '.',
+            })
+            .collect::<String>();
+        dump.push_str(&format!(
+            "{:08x}  {:<47}  |{}|\n",
+            line * 16,
+            hex.join(" "),
+            text
+        ));
+    }
+    println!("{}", dump);
+}
+
+/// Randomize `period` by up to `jitter` in either direction, without going below zero.
+fn jittered(period: Duration, jitter: Duration, rng: &mut StdRng) -> Duration {
+    // no random number is drawn without jitter, which would change the sequences of --seed
+    if jitter.is_zero() {
+        return period;
+    }
+    let offset = rng.gen_range(-jitter.as_secs_f64()..=jitter.as_secs_f64());
+    Duration::from_secs_f64((period.as_secs_f64() + offset).max(0.))
+}
+
+/// Get the size of a publish packet including its fixed header.
+fn packet_size(topic: &str, qos: QoS, payload_len: usize) -> usize {
+    let packet_id = if qos == QoS::AtMostOnce { 0 } else { 2 };
+    let remaining = 2 + topic.len() + packet_id + payload_len;
+    let length_bytes = match remaining {
+        0..=127 => 1,
+        128..=16_383 => 2,
+        16_384..=2_097_151 => 3,
+        _ => 4,
+    };
+    1 + length_bytes + remaining
+}
+
+pub async fn pinger(ping: Arc<Ping>, sink: Sender<Request>, mut interval: Interval) -> Result<()> {
+    loop {
+        interval.tick().await;
+        let msg = Publish::new(ping.topic(), QoS::AtMostOnce, ping.payload());
+        sink.send(Request::Publish(msg))
+            .await
+            .expect("Eventloop rx seems to be dead.");
+    }
+}
+
+/// Publish the schema message after every connect.
+///
+/// The schema is derived from the current data unless a fixed `payload` is given, it is retained
+/// so consumers subscribing later still receive it.
+pub async fn schema_publisher(
+    topic: String,
+    payload: Option<String>,
+    encoder: EncoderKind,
+    mut data: watch::Receiver<Vec<Data>>,
+    mut connects: watch::Receiver<u64>,
+    sink: Sender<Request>,
+) -> Result<()> {
+    loop {
+        connects.changed().await?;
+        let payload = match &payload {
+            Some(payload) => payload.clone().into_bytes(),
+            None => {
+                let data = data.wait_for(|data| !data.is_empty()).await?;
+                serde_json::to_vec(&Schema::new(&data, encoder))?
+            }
+        };
+        log::debug!("Publishing schema to {}", topic);
+        let mut msg = Publish::new(&topic, QoS::AtLeastOnce, payload);
+        msg.retain = true;
+        sink.send(Request::Publish(msg))
+            .await
+            .expect("Eventloop rx seems to be dead.");
+    }
+}
+
+/// Forward every request to the first of `sinks` and copies of it to the others.
+///
+/// The first broker sets the pace like without fan-out, the others drop requests while their
+/// queue is full, e.g. as long as they're disconnected, so they can't hold back the rest.
+pub async fn fan_out(
+    requests: async_channel::Receiver<Request>,
+    sinks: Vec<(String, Sender<Request>)>,
+) {
+    let mut behind = vec![false; sinks.len()];
+    while let Ok(request) = requests.recv().await {
+        for (i, (broker, sink)) in sinks.iter().enumerate() {
+            let sent = if i == 0 {
+                sink.send(request.clone()).await.map_err(|_| ())
+            } else {
+                match sink.try_send(request.clone()) {
+                    Err(async_channel::TrySendError::Full(_)) => {
+                        if !behind[i] {
+                            log::warn!("{} isn't keeping up, dropping its publishes", broker);
+                        }
+                        behind[i] = true;
+                        continue;
+                    }
+                    sent => sent.map_err(drop),
+                }
+            };
+            sent.expect("Eventloop rx seems to be dead.");
+            if behind[i] {
+                log::info!("{} caught up, publishing again", broker);
+                behind[i] = false;
+            }
+        }
+    }
+}
+
+/// Enqueue a request from within the eventloop task.
+///
+/// Awaiting the send directly could deadlock on a full request channel since the eventloop is not
+/// polled in the meantime.
+fn send_detached(sink: &Sender<Request>, request: Request) {
+    let sink = sink.clone();
+    task::spawn(async move {
+        if sink.send(request).await.is_err() {
+            log::error!("Eventloop rx seems to be dead.");
+        }
+    });
+}
+
+/// Graceful shutdown request shared with the eventloop tasks.
+#[derive(Clone)]
+pub struct Shutdown {
+    pub signal: watch::Receiver<bool>,
+    /// Maximum time to wait for pending acknowledgements.
+    pub drain_timeout: Duration,
+}
+
+impl Shutdown {
+    /// Wait until a shutdown is requested.
+    async fn requested(&mut self) {
+        while !*self.signal.borrow() {
+            if self.signal.changed().await.is_err() {
+                future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// Drop the requests of a dry run until a shutdown is requested.
+///
+/// Only data payloads are printed, other messages such as pings or status updates go nowhere.
+pub async fn discard_requests(
+    requests: async_channel::Receiver<Request>,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    loop {
+        select! {
+            Ok(request) = requests.recv() => log::debug!("Dry run, not sending {:?}", request),
+            _ = shutdown.requested() => return Ok(()),
+        }
+    }
+}
+
+/// Check whether a network error of a TLS connect is caused by TLS rather than the connection.
+fn tls_failure(e: &(dyn std::error::Error + 'static)) -> bool {
+    match e
+        .source()
+        .and_then(|source| source.downcast_ref::<io::Error>())
+    {
+        // rustls reports handshake failures such as unknown issuers as invalid data
+        Some(io) => io.kind() == io::ErrorKind::InvalidData,
+        None => true,
+    }
+}
+
+/// Features of the main session served by its event loop.
+#[derive(Default)]
+pub struct Handlers {
+    pub history: Option<(String, Arc<History>)>,
+    pub ping: Option<Arc<Ping>>,
+    pub control: Option<(String, watch::Sender<bool>)>,
+    /// Number of connects, counted for the schema publisher and benchmarks.
+    pub connects: Option<watch::Sender<u64>>,
+    /// Topic filters whose messages are republished, and the prefix of their new topics.
+    pub echo: Option<(Vec<String>, String)>,
+    /// Whether the session's connection state is the one published by connection state values.
+    pub reports_state: bool,
+    /// Transports with rotated TLS certificates to reconnect with.
+    pub transports: Option<watch::Receiver<Transport>>,
+}
+
+/// Wait for a new transport, forever without any.
+async fn rotated(transports: &mut Option<watch::Receiver<Transport>>) -> Transport {
+    if let Some(transports) = transports {
+        if transports.changed().await.is_ok() {
+            return transports.borrow_and_update().clone();
+        }
+    }
+    future::pending().await
+}
+
+pub async fn eventloop_task(
+    mut eventloop: EventLoop,
+    handlers: Handlers,
+    mut backoff: Backoff,
+    max_reconnect_attempts: Option<usize>,
+    stats: Arc<Stats>,
+    mut shutdown: Shutdown,
+) -> Result<()> {
+    let Handlers {
+        history,
+        ping,
+        control,
+        connects,
+        echo,
+        reports_state,
+        mut transports,
+    } = handlers;
+    // sessions opened after a rotation start out with the current transport
+    if let Some(transports) = &mut transports {
+        eventloop
+            .options
+            .set_transport(transports.borrow_and_update().clone());
+    }
+    // whether the connection is closed to reconnect with a new transport
+    let mut rotating = false;
+    let sink = eventloop.handle();
+    let mut connected = false;
+    // whether the current connection is established, connected tells if it ever was
+    let mut up = false;
+    // connection attempts that failed since the last successful connect
+    let mut failures = 0;
+    // packet ids of QoS 1 and 2 publishes awaiting their acknowledgement
+    let mut pending = HashSet::new();
+    let mut deadline = None;
+    loop {
+        // requests still queued, e.g. clearing retained messages, are sent before finishing
+        if deadline.is_some() && pending.is_empty() && sink.is_empty() {
+            break;
+        }
+        let drained = async {
+            match deadline {
+                Some(deadline) => sleep_until(deadline).await,
+                None => future::pending().await,
+            }
+        };
+        let event = select! {
+            event = eventloop.poll() => event,
+            _ = shutdown.requested(), if deadline.is_none() => {
+                if !pending.is_empty() || !sink.is_empty() {
+                    log::info!(
+                        "Waiting for {} pending acknowledgements and {} queued requests",
+                        pending.len(),
+                        sink.len()
+                    );
+                }
+                deadline = Some(tokio::time::Instant::now() + shutdown.drain_timeout);
+                continue;
+            }
+            transport = rotated(&mut transports), if deadline.is_none() => {
+                eventloop.options.set_transport(transport);
+                // a connection attempt in progress already picks up the new transport
+                if up {
+                    log::info!("Reconnecting to the MQTT Broker with the new TLS certificates");
+                    rotating = true;
+                    send_detached(&sink, Request::Disconnect);
+                }
+                continue;
+            }
+            _ = drained => {
+                log::warn!(
+                    "{} publishes still unacknowledged and {} requests unsent after {:?}",
+                    pending.len(),
+                    sink.len(),
+                    shutdown.drain_timeout
+                );
+                break;
+            }
+        };
+        match &event {
+            Ok(_) => {
+                backoff.reset();
+                stats.record_poll();
+            }
+            Err(_) if rotating => {
+                if up {
+                    stats.record_disconnect();
+                    up = false;
+                }
+            }
+            Err(e) => {
+                if up {
+                    stats.record_disconnect();
+                    up = false;
+                }
+                if reports_state {
+                    stats.set_connection(if connected {
+                        ConnectionState::Reconnecting
+                    } else {
+                        ConnectionState::Connecting
+                    });
+                }
+                failures += 1;
+                if let Some(max) = max_reconnect_attempts {
+                    if failures > max {
+                        anyhow::bail!(
+                            "Giving up after {} failed connection attempts: {:?}",
+                            failures,
+                            e
+                        );
+                    }
+                }
+            }
+        }
+        match event {
+            // reconnect right away, the broker closed the connection as asked to
+            Err(e) if rotating => {
+                log::debug!(
+                    "Closed the connection for the new TLS certificates: {:?}",
+                    e
+                );
+                rotating = false;
+            }
+            Err(ConnectionError::Network(e)) if tls_failure(&e) => {
+                let delay = backoff.next_delay();
+                log::error!(
+                    "TLS connection to MQTT Broker failed, check its certificate and \
+                     --ca-cert/--client-cert/--client-key: {:?}, retrying in {:.1?}",
+                    e,
+                    delay
+                );
+                sleep(delay).await;
+            }
+            Err(e) => {
+                let delay = backoff.next_delay();
+                log::error!(
+                    "Lost connection to MQTT Broker {:?}, retrying in {:.1?}",
+                    e,
+                    delay
+                );
+                sleep(delay).await;
+            }
+            Ok(Event::Incoming(Packet::ConnAck(ack))) => {
+                log::debug!("MQTT Event: {:?}", ack);
+                failures = 0;
+                if connected {
+                    stats.record_reconnect();
+                }
+                stats.record_connect();
+                up = true;
+                if reports_state {
+                    stats.set_connection(ConnectionState::Connected);
+                }
+                connected = true;
+                if let Some(connects) = &connects {
+                    connects.send_modify(|connects| *connects += 1);
+                }
+                if let Some((topic, _)) = &history {
+                    let subscribe = Subscribe::new(topic, QoS::AtLeastOnce);
+                    send_detached(&sink, Request::Subscribe(subscribe));
+                }
+                if let Some(ping) = &ping {
+                    let subscribe = Subscribe::new(ping.topic(), QoS::AtMostOnce);
+                    send_detached(&sink, Request::Subscribe(subscribe));
+                }
+                if let Some((topic, _)) = &control {
+                    let subscribe = Subscribe::new(topic, QoS::AtLeastOnce);
+                    send_detached(&sink, Request::Subscribe(subscribe));
+                }
+                for filter in echo.iter().flat_map(|(filters, _)| filters) {
+                    let subscribe = Subscribe::new(filter, QoS::AtLeastOnce);
+                    send_detached(&sink, Request::Subscribe(subscribe));
+                }
+            }
+            Ok(Event::Outgoing(Outgoing::Publish(pkid))) => {
+                // QoS 0 publishes have no packet id
+                if pkid != 0 {
+                    pending.insert(pkid);
+                }
+            }
+            Ok(Event::Incoming(Packet::PubAck(PubAck { pkid })))
+            | Ok(Event::Incoming(Packet::PubComp(PubComp { pkid }))) => {
+                pending.remove(&pkid);
+            }
+            Ok(Event::Incoming(Packet::Publish(pong)))
+                if ping.as_ref().map(|ping| ping.topic()) == Some(&pong.topic) =>
+            {
+                ping.as_ref().unwrap().record(&pong.payload);
+            }
+            Ok(Event::Incoming(Packet::Publish(command)))
+                if control.as_ref().map(|(topic, _)| topic) == Some(&command.topic) =>
+            {
+                let (_, active) = control.as_ref().unwrap();
+                match std::str::from_utf8(&command.payload).map(str::trim) {
+                    Ok("promote") => {
+                        if !active.send_replace(true) {
+                            log::info!("Promoted from standby, starting to publish");
+                        }
+                    }
+                    _ => log::warn!(
+                        "Unknown control command {:?}",
+                        String::from_utf8_lossy(&command.payload)
+                    ),
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(query)))
+                if history.as_ref().map(|(topic, _)| topic) == Some(&query.topic) =>
+            {
+                let (topic, history) = history.as_ref().unwrap();
+                let filter = std::str::from_utf8(&query.payload)
+                    .ok()
+                    .map(str::trim)
+                    .filter(|filter| !filter.is_empty());
+                log::debug!("History query for {:?}", filter);
+                let response = serde_json::to_vec(&history.query(filter))?;
+                let msg = Publish::new(format!("{}/response", topic), QoS::AtLeastOnce, response);
+                send_detached(&sink, Request::Publish(msg));
+            }
+            // echoes matching a filter again aren't echoed a second time
+            Ok(Event::Incoming(Packet::Publish(msg)))
+                if echo.as_ref().is_some_and(|(filters, prefix)| {
+                    !msg.topic.starts_with(prefix.as_str())
+                        && filters
+                            .iter()
+                            .any(|filter| rumqttc::matches(&msg.topic, filter))
+                }) =>
+            {
+                let (_, prefix) = echo.as_ref().unwrap();
+                let topic = format!("{}{}", prefix, msg.topic);
+                log::debug!("Echoing {} to {}", msg.topic, topic);
+                let echoed = Publish::new(topic, msg.qos, msg.payload.to_vec());
+                send_detached(&sink, Request::Publish(echoed));
+            }
+            Ok(p) => {
+                log::debug!("MQTT Event: {:?}", p)
+            }
+        }
+    }
+    send_detached(&sink, Request::Disconnect);
+    while let Ok(Ok(event)) = timeout(Duration::from_secs(1), eventloop.poll()).await {
+        if event == Event::Outgoing(Outgoing::Disconnect) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use rand::SeedableRng;
+    use tokio::runtime;
+
+    use super::*;
+    use crate::data::{self, Format};
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// Get an event loop that isn't polled, so its queue fills up, and its queue.
+    fn queue(capacity: usize) -> (EventLoop, Queue) {
+        let eventloop = EventLoop::new(MqttOptions::new("test", "localhost", 1883), capacity);
+        let queue = Queue::of(&eventloop);
+        (eventloop, queue)
+    }
+
+    /// Get a sender config publishing every `interval` through an unconnected session.
+    pub(crate) fn config(stats: &Arc<Stats>, interval: Duration) -> SenderConfig {
+        let opts = MqttOptions::new("test", "localhost", 1883);
+        let (_, signal) = watch::channel(false);
+        let shutdown = Shutdown {
+            signal,
+            drain_timeout: Duration::ZERO,
+        };
+        let backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(1));
+        let (_, active) = watch::channel(true);
+        SenderConfig {
+            interval,
+            jitter: Duration::ZERO,
+            send_delay: Duration::ZERO,
+            value_ttl: None,
+            encoder: EncoderKind::Binary,
+            drop_rate: 0.,
+            shuffle: false,
+            stagger: false,
+            dedup: false,
+            max_packet_size: None,
+            max_payload_bytes: MAX_PAYLOAD_BYTES,
+            strict_payload_size: false,
+            rng: StdRng::seed_from_u64(0),
+            history: None,
+            recording: None,
+            sessions: Sessions::new(opts, 1, backoff, Arc::clone(stats), shutdown),
+            brokers: HashMap::new(),
+            freshness: None,
+            active,
+            revisions: None,
+            dry_run: false,
+            once: false,
+            chaos: Chaos::default(),
+            rate_limit: None,
+            backpressure: Backpressure::Block,
+            context: Context {
+                start: std::time::Instant::now(),
+                stats: Arc::clone(stats),
+                utf16_bom: true,
+                index: 0,
+                topic: Arc::from(""),
+                averages: Default::default(),
+                warn_precision_loss: false,
+                seed: None,
+                entry_rng: Default::default(),
+                constants: Default::default(),
+            },
+        }
+    }
+
+    fn publish(topic: &str) -> Publish {
+        Publish::new(topic, QoS::AtMostOnce, vec![0])
+    }
+
+    /// Send publishes to `topics` with `backpressure` and get the topics that were queued.
+    fn fill(
+        topics: &[&str],
+        capacity: usize,
+        backpressure: Backpressure,
+        stats: &Stats,
+    ) -> Vec<String> {
+        let (_eventloop, queue) = queue(capacity);
+        block_on(async {
+            for topic in topics {
+                send_fresh(&queue, publish(topic), None, backpressure, stats)
+                    .await
+                    .unwrap();
+            }
+        });
+        std::iter::from_fn(|| queue.requests.try_recv().ok())
+            .map(|request| match request {
+                Request::Publish(msg) => msg.topic,
+                other => panic!("unexpected request {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn drop_newest_sheds_publishes_that_dont_fit() {
+        let stats = Stats::default();
+        let queued = fill(&["a", "b", "c", "d"], 2, Backpressure::DropNewest, &stats);
+        assert_eq!(queued, ["a", "b"]);
+        assert_eq!(stats.shed(), 2);
+        assert_eq!(stats.messages(), 2);
+    }
+
+    #[test]
+    fn drop_oldest_sheds_the_front_of_the_queue() {
+        let stats = Stats::default();
+        let queued = fill(&["a", "b", "c", "d"], 2, Backpressure::DropOldest, &stats);
+        assert_eq!(queued, ["c", "d"]);
+        assert_eq!(stats.shed(), 2);
+        assert_eq!(stats.messages(), 4);
+    }
+
+    #[test]
+    fn blocked_publishes_go_stale() {
+        let stats = Stats::default();
+        let (_eventloop, queue) = queue(1);
+        block_on(async {
+            let deadline = tokio::time::Instant::now() + Duration::from_millis(20);
+            for topic in ["a", "b"] {
+                send_fresh(
+                    &queue,
+                    publish(topic),
+                    Some(deadline),
+                    Backpressure::Block,
+                    &stats,
+                )
+                .await
+                .unwrap();
+            }
+        });
+        assert_eq!(queue.requests.len(), 1);
+        assert_eq!((stats.messages(), stats.stale()), (1, 1));
+    }
+
+    #[test]
+    fn drop_policies_skip_stale_publishes() {
+        for backpressure in [Backpressure::DropOldest, Backpressure::DropNewest] {
+            let stats = Stats::default();
+            let (_eventloop, queue) = queue(1);
+            block_on(async {
+                let deadline = tokio::time::Instant::now();
+                send_fresh(&queue, publish("a"), Some(deadline), backpressure, &stats)
+                    .await
+                    .unwrap();
+            });
+            assert!(queue.requests.is_empty());
+            assert_eq!((stats.messages(), stats.stale()), (0, 1));
+        }
+    }
+
+    #[test]
+    fn backpressure_policies_parse() {
+        assert_eq!(
+            "block".parse::<Backpressure>().unwrap(),
+            Backpressure::Block
+        );
+        assert_eq!(
+            "drop-oldest".parse::<Backpressure>().unwrap(),
+            Backpressure::DropOldest
+        );
+        assert!("drop-all".parse::<Backpressure>().is_err());
+    }
+
+    #[test]
+    fn once_ignores_phases() {
+        let vals = data::load(
+            r#"[
+                {"topic": "a", "data": {"value": 1}},
+                {"topic": "b", "phase_ms": 60000, "data": {"value": 2}}
+            ]"#,
+            Format::Json,
+            None,
+        )
+        .unwrap();
+        let stats = Arc::new(Stats::default());
+        let (_eventloop, queue) = queue(10);
+        let config = SenderConfig {
+            once: true,
+            stagger: true,
+            ..config(&stats, Duration::from_secs(60))
+        };
+        let (_data, rx) = watch::channel(vals);
+        let finished = block_on(async {
+            timeout(Duration::from_secs(5), sender(rx, queue.clone(), config)).await
+        });
+        finished.expect("sender waited for the phase").unwrap();
+        assert_eq!(queue.requests.len(), 2);
+    }
+
+    #[test]
+    fn entries_naming_a_broker_only_go_to_it() {
+        let vals = data::load(
+            r#"[
+                {"topic": "a", "broker": "primary", "data": {"value": 1}},
+                {"topic": "b", "broker": "monitoring", "data": {"value": 2}},
+                {"topic": "c", "data": {"value": 3}}
+            ]"#,
+            Format::Json,
+            None,
+        )
+        .unwrap();
+        let stats = Arc::new(Stats::default());
+        let (_primary, primary) = queue(10);
+        let (_monitoring, monitoring) = queue(10);
+        let (_all, queue) = queue(10);
+        let brokers = [("primary", &primary), ("monitoring", &monitoring)];
+        let config = SenderConfig {
+            once: true,
+            brokers: brokers
+                .iter()
+                .map(|(name, queue)| (name.to_string(), (*queue).clone()))
+                .collect(),
+            ..config(&stats, Duration::from_secs(60))
+        };
+        let (_data, rx) = watch::channel(vals);
+        block_on(sender(rx, queue.clone(), config)).unwrap();
+        let topics = |queue: &Queue| {
+            std::iter::from_fn(|| queue.requests.try_recv().ok())
+                .map(|request| match request {
+                    Request::Publish(publish) => publish.topic,
+                    request => panic!("unexpected request {:?}", request),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(topics(&queue), ["c"]);
+        assert_eq!(topics(&primary), ["a"]);
+        assert_eq!(topics(&monitoring), ["b"]);
+    }
+
+    #[test]
+    fn rotated_transports_are_taken_once() {
+        let (transports, receiver) = watch::channel(Transport::Tcp);
+        let mut receiver = Some(receiver);
+        let wait = Duration::from_millis(10);
+        block_on(async {
+            transports.send_replace(Transport::tls(Vec::new(), None, None));
+            assert!(matches!(rotated(&mut receiver).await, Transport::Tls(_)));
+            let again = tokio::time::timeout(wait, rotated(&mut receiver)).await;
+            assert!(again.is_err());
+            // without watched certificates there's never a new transport
+            let unwatched = tokio::time::timeout(wait, rotated(&mut None)).await;
+            assert!(unwatched.is_err());
+        });
+    }
+}
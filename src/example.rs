@@ -0,0 +1,98 @@
+use serde::Serialize;
+use serde_json::json;
+
+use crate::data::{Endian, FloatWidth, Framing, IntWidth, StringEncoding, Value};
+
+#[derive(Serialize)]
+pub struct Example {
+    #[serde(rename = "_comment")]
+    comment: &'static str,
+    topic: &'static str,
+    data: Value,
+}
+
+/// Build an example data file with an entry for each basic value type.
+///
+/// The values are constructed from the actual types and serialized, so the example always
+/// matches the accepted format. JSON has no comments, entries explain themselves in an
+/// `_comment` field which is ignored when loading the file.
+pub fn generate() -> Vec<Example> {
+    let examples = vec![
+        (
+            "example/bool",
+            "Booleans are a plain true or false.",
+            Value::Bool(true),
+        ),
+        (
+            "example/uint",
+            "Integers take a width of 8, 16, 32, 64 or 128 bits and an endianness.",
+            Value::UInt {
+                value: 10,
+                endian: Some(Endian::LittleEndian),
+                width: IntWidth::Thirtytwo,
+            },
+        ),
+        (
+            "example/int",
+            "Negative integers are published as signed integers.",
+            Value::Int {
+                value: -10,
+                endian: Some(Endian::BigEndian),
+                width: IntWidth::Sixteen,
+            },
+        ),
+        (
+            "example/float",
+            "Floats are 32 or 64 bits wide.",
+            Value::Float {
+                value: 2.3,
+                endian: Some(Endian::BigEndian),
+                width: FloatWidth::Thirtytwo,
+                rounding: Default::default(),
+                denormals: Default::default(),
+                require_finite: false,
+            },
+        ),
+        (
+            "example/string",
+            "Strings are encoded as UTF8, UTF16LE or UTF16BE.",
+            Value::String {
+                value: "hello world".to_string(),
+                encoding: StringEncoding::UTF16LE,
+                framing: Framing::default(),
+            },
+        ),
+        (
+            "example/array",
+            "Arrays concatenate the payloads of their values.",
+            Value::Array(vec![
+                Value::Bool(false),
+                Value::UInt {
+                    value: 1,
+                    endian: Some(Endian::BigEndian),
+                    width: IntWidth::Eight,
+                },
+            ]),
+        ),
+        (
+            "example/bytes",
+            "Raw bytes are published as they are.",
+            Value::Bytes {
+                bytes: vec![0xaa, 0x55, 0x01],
+            },
+        ),
+        (
+            "example/json",
+            "Any other JSON is published as serialized JSON.",
+            Value::JSON(json!({ "mapping": { "nested": true }, "n_fields": 2 })),
+        ),
+    ];
+    examples
+        .into_iter()
+        .map(|(topic, comment, data)| Example {
+            comment,
+            topic,
+            data,
+        })
+        .collect()
+}
@@ -0,0 +1,160 @@
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use evalexpr::{
+    ContextWithMutableFunctions, ContextWithMutableVariables, DefaultNumericTypes, Function,
+    HashMapContext, Node,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::data::{Constant, Context, Endian, IntWidth, ParseAs, Value};
+
+type ExprValue = evalexpr::Value<DefaultNumericTypes>;
+
+/// Value computed from a math expression on every publish.
+///
+/// The expression can refer to the seconds since startup `t`, the fleet index `i`, the number
+/// of previous evaluations `seq` and the constants of the device by name, and draw random
+/// numbers through `rand(min, max)`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Expr {
+    expression: String,
+    #[serde(default = "Expr::default_output")]
+    output: ParseAs,
+    #[serde(default)]
+    endian: Option<Endian>,
+    #[serde(default)]
+    width: IntWidth,
+    /// Fail the publish if a float result is NaN or an infinity.
+    #[serde(default)]
+    require_finite: bool,
+    #[serde(skip)]
+    node: Arc<OnceLock<Node<DefaultNumericTypes>>>,
+    #[serde(skip)]
+    seq: Arc<AtomicU64>,
+}
+
+impl Expr {
+    fn default_output() -> ParseAs {
+        ParseAs::Float
+    }
+
+    /// Get the type the result is converted to.
+    pub fn output(&self) -> ParseAs {
+        self.output
+    }
+
+    /// Continue the sequence number `n` of `previous`.
+    pub fn adopt_state(&mut self, previous: &Expr) {
+        self.seq = Arc::clone(&previous.seq);
+    }
+
+    /// Use the endianness of the entry unless the expression sets its own.
+    pub fn inherit(&mut self, endian: Endian) {
+        self.endian.get_or_insert(endian);
+    }
+
+    /// Parse the expression, subsequent calls reuse the parsed expression.
+    pub fn compile(&self) -> Result<&Node<DefaultNumericTypes>, io::Error> {
+        if let Some(node) = self.node.get() {
+            return Ok(node);
+        }
+        let node = evalexpr::build_operator_tree(&self.expression)
+            .map_err(|e| invalid(format!("invalid expression {:?}: {}", self.expression, e)))?;
+        Ok(self.node.get_or_init(|| node))
+    }
+
+    /// Evaluate the expression and convert the result to the output type.
+    pub fn value(&self, context: &Context) -> Result<Value, io::Error> {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        let mut variables = HashMapContext::<DefaultNumericTypes>::new();
+        let scope = vec![
+            (
+                "t",
+                ExprValue::from_float(context.start.elapsed().as_secs_f64()),
+            ),
+            ("i", ExprValue::from_int(context.index as i64)),
+            ("seq", ExprValue::from_int(seq as i64)),
+        ];
+        for (name, value) in scope {
+            variables
+                .set_value(name.to_string(), value)
+                .map_err(|e| invalid(e.to_string()))?;
+        }
+        for (name, constant) in context.constants.iter() {
+            let value = match *constant {
+                Constant::Int(value) => ExprValue::from_int(value),
+                Constant::Float(value) => ExprValue::from_float(value),
+            };
+            variables
+                .set_value(name.clone(), value)
+                .map_err(|e| invalid(e.to_string()))?;
+        }
+        for (name, f) in FUNCTIONS {
+            let f = *f;
+            let function = Function::new(move |arg| Ok(ExprValue::from_float(f(arg.as_number()?))));
+            variables
+                .set_function(name.to_string(), function)
+                .map_err(|e| invalid(e.to_string()))?;
+        }
+        let random = context.clone();
+        let rand = Function::new(move |arg| {
+            let bounds = arg.as_fixed_len_tuple(2)?;
+            let (min, max) = (bounds[0].as_number()?, bounds[1].as_number()?);
+            if min >= max {
+                return Err(evalexpr::EvalexprError::CustomMessage(format!(
+                    "rand({}, {}) needs min < max",
+                    min, max
+                )));
+            }
+            Ok(ExprValue::from_float(
+                random.random(|rng| rng.gen_range(min..max)),
+            ))
+        });
+        variables
+            .set_function("rand".to_string(), rand)
+            .map_err(|e| invalid(e.to_string()))?;
+
+        let result = self
+            .compile()?
+            .eval_with_context(&variables)
+            .map_err(|e| invalid(format!("failed to evaluate {:?}: {}", self.expression, e)))?;
+        let number = || result.as_number().map_err(|e| invalid(e.to_string()));
+        let text = match self.output {
+            ParseAs::Bool => result
+                .as_boolean()
+                .map_err(|e| invalid(e.to_string()))?
+                .to_string(),
+            ParseAs::Int | ParseAs::UInt => number()?.round().to_string(),
+            ParseAs::Float => number()?.to_string(),
+            ParseAs::String => match &result {
+                ExprValue::String(s) => s.clone(),
+                result => result.to_string(),
+            },
+        };
+        let mut value = self.output.parse(&text, self.endian, self.width)?;
+        if let Value::Float { require_finite, .. } = &mut value {
+            *require_finite = self.require_finite;
+        }
+        Ok(value)
+    }
+}
+
+type MathFunction = fn(f64) -> f64;
+
+/// Math functions available without the `math::` prefix.
+const FUNCTIONS: &[(&str, MathFunction)] = &[
+    ("sin", f64::sin),
+    ("cos", f64::cos),
+    ("tan", f64::tan),
+    ("sqrt", f64::sqrt),
+    ("exp", f64::exp),
+    ("ln", f64::ln),
+    ("abs", f64::abs),
+];
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,66 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+
+/// Bounded buffer of the most recently published messages.
+pub struct History {
+    size: usize,
+    entries: Mutex<VecDeque<Entry>>,
+}
+
+struct Entry {
+    topic: String,
+    payload: Vec<u8>,
+    timestamp: SystemTime,
+}
+
+impl History {
+    /// Construct a new history holding at most `size` messages.
+    pub fn new(size: usize) -> Self {
+        History {
+            size,
+            entries: Mutex::new(VecDeque::with_capacity(size)),
+        }
+    }
+
+    /// Record a published message, evicting the oldest one if the buffer is full.
+    pub fn record(&self, topic: &str, payload: &[u8]) {
+        if self.size == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.size {
+            entries.pop_front();
+        }
+        entries.push_back(Entry {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            timestamp: SystemTime::now(),
+        });
+    }
+
+    /// Get the recorded messages as a JSON array, oldest first.
+    ///
+    /// If `topic` is given, only messages published on that topic are returned.
+    pub fn query(&self, topic: Option<&str>) -> serde_json::Value {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .filter(|entry| topic.map(|topic| topic == entry.topic).unwrap_or(true))
+            .map(|entry| {
+                let timestamp = entry
+                    .timestamp
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64;
+                json!({
+                    "topic": entry.topic,
+                    "timestamp_ms": timestamp,
+                    "payload_hex": hex::encode(&entry.payload),
+                })
+            })
+            .collect()
+    }
+}
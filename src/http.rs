@@ -0,0 +1,82 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::data::{Endian, IntWidth, ParseAs, Value};
+
+/// Value fetched from an HTTP endpoint before every publish.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HttpSource {
+    url: String,
+    #[serde(default)]
+    parse_as: ParseAs,
+    #[serde(default)]
+    endian: Option<Endian>,
+    #[serde(default)]
+    width: IntWidth,
+    #[serde(default = "default_timeout_ms")]
+    timeout_ms: u64,
+    /// Body of the last successful response.
+    #[serde(skip)]
+    body: Arc<Mutex<Option<String>>>,
+}
+
+fn default_timeout_ms() -> u64 {
+    1000
+}
+
+impl HttpSource {
+    /// Get the type the response body is parsed as.
+    pub fn parse_as(&self) -> ParseAs {
+        self.parse_as
+    }
+
+    /// Use the endianness of the entry unless the source sets its own.
+    pub fn inherit(&mut self, endian: Endian) {
+        self.endian.get_or_insert(endian);
+    }
+
+    /// Get the value parsed from the last successful response.
+    pub fn value(&self) -> Result<Value, io::Error> {
+        let body = self.body.lock().unwrap();
+        let body = body.as_deref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No response from {} yet", self.url),
+            )
+        })?;
+        self.parse_as.parse(body, self.endian, self.width)
+    }
+
+    async fn refresh(&self, client: &Client) {
+        let response = client
+            .get(&self.url)
+            .timeout(Duration::from_millis(self.timeout_ms))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match response {
+            Ok(response) => match response.text().await {
+                Ok(body) => *self.body.lock().unwrap() = Some(body),
+                Err(e) => log::warn!("Failed to read response from {}: {}", self.url, e),
+            },
+            Err(e) => log::warn!("Failed to fetch {}, keeping last value: {}", self.url, e),
+        }
+    }
+}
+
+/// Refresh all HTTP sources contained in `value`.
+pub async fn refresh(value: &Value, client: &Client) {
+    let mut sources = Vec::new();
+    value.walk(&mut |value| {
+        if let Value::Http(source) = value {
+            sources.push(source);
+        }
+    });
+    for source in sources {
+        source.refresh(client).await;
+    }
+}
@@ -0,0 +1,56 @@
+//! Publishing engine of the MQTT simulator.
+//!
+//! [`Simulator`] publishes a list of [`Data`](data::Data) entries to a broker on an interval,
+//! e.g. to embed the simulator in a test binary instead of running the command line tool.
+//! The entries are usually deserialized from the same JSON as the simulator's data files
+//! through [`data::load`].
+
+mod admin;
+pub mod anomaly;
+pub mod average;
+mod backoff;
+mod bench;
+mod chaos;
+pub mod crypto;
+pub mod data;
+pub mod encoder;
+mod engine;
+pub mod expr;
+mod history;
+#[cfg(feature = "http")]
+pub mod http;
+mod metrics;
+mod ping;
+mod prometheus;
+mod ratelimit;
+mod record;
+#[cfg(feature = "http")]
+mod remote;
+mod schema;
+mod simulator;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stats;
+pub mod sysfile;
+pub mod tail;
+mod watchdog;
+mod watcher;
+
+// connection setup of the command line tool, not part of the library's API
+#[doc(hidden)]
+pub mod bind;
+#[doc(hidden)]
+pub mod example;
+#[doc(hidden)]
+pub mod proxy;
+#[cfg(feature = "quic")]
+#[doc(hidden)]
+pub mod quic;
+#[doc(hidden)]
+pub mod tls;
+
+pub use chaos::Chaos;
+pub use engine::Backpressure;
+pub use rumqttc::MqttOptions;
+pub use simulator::{Simulator, SimulatorHandle};
+pub use watcher::Source;
@@ -1,148 +1,1289 @@
-mod data;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use std::time::{Duration, SystemTime};
-
-use anyhow::{bail, Result};
-use clap::{App, Arg};
+use anyhow::{anyhow, Context as _, Result};
+use clap::{App, Arg, ArgMatches};
 use env_logger::Env;
-use rumqttc::{EventLoop, MqttOptions, Publish, QoS, Request, Sender};
-use tokio::{
-    fs, select, task,
-    time::{interval, Interval},
-};
-use tokio::{sync::watch, time::sleep};
-
-use data::Data;
-
-async fn data_watcher(path: String, tx: watch::Sender<Vec<Data>>) -> Result<()> {
-    let mut interval = interval(Duration::from_millis(100));
-    let mut modified = SystemTime::UNIX_EPOCH;
-    loop {
-        let meta = fs::metadata(&path).await?;
-        let last_mod = meta.modified().unwrap();
-        if modified < last_mod {
-            let values = if let Ok(s) = fs::read_to_string(&path).await {
-                s
-            } else {
-                continue;
-            };
-            match serde_json::from_str::<Vec<Data>>(&values) {
-                Ok(vals) => {
-                    log::info!("Replacing values with:\n{:#?}", vals);
-                    tx.send(vals).map_err(|_| "").expect("Watchers died");
-                    modified = last_mod;
-                }
-                Err(e) => {
-                    log::debug!("Failed to read values: {:?}\n{}", e, values);
-                }
-            }
-        }
-        interval.tick().await;
+use rand::Rng;
+use rumqttc::{LastWill, MqttOptions, Transport};
+use tokio::{runtime, signal, task};
+
+use mqtt_simulator::bind::{Forwarder, Route};
+use mqtt_simulator::data::{self, Context, Qos};
+use mqtt_simulator::proxy::HttpProxy;
+#[cfg(feature = "quic")]
+use mqtt_simulator::quic;
+use mqtt_simulator::{example, tls, Backpressure, Chaos, Simulator, Source};
+
+/// Data file loaded when neither the command line nor the environment name one.
+const DEFAULT_CONFIG: &str = "/etc/mqtt-simulator/config.json";
+
+/// Check whether a flag is passed on the command line or set in the environment.
+///
+/// Options read `MQTT_SIMULATOR_*` variables through clap, flags like `--dedup` are enabled by
+/// setting `MQTT_SIMULATOR_DEDUP` to `1` or `true`.
+fn flag(matches: &ArgMatches, name: &str) -> bool {
+    if matches.is_present(name) {
+        return true;
     }
+    let var = format!("MQTT_SIMULATOR_{}", name.to_uppercase().replace('-', "_"));
+    std::env::var(var).is_ok_and(|value| value == "1" || value.eq_ignore_ascii_case("true"))
 }
 
-async fn sender(
-    rx: watch::Receiver<Vec<Data>>,
-    sink: Sender<Request>,
-    mut interval: Interval,
-) -> Result<()> {
-    loop {
-        let vals = rx.borrow().clone();
-        for val in vals {
-            let mut buf = Vec::new();
-            val.data().serialize(&mut buf)?;
-            let msg = Publish::new(val.topic(), QoS::AtLeastOnce, buf);
-            sink.send(Request::Publish(msg))
-                .await
-                .expect("Eventloop rx seems to be dead.");
-        }
-        interval.tick().await;
-    }
+/// Load and serialize the data file once and print the resolved entries.
+fn check(matches: &ArgMatches) -> Result<()> {
+    let paths = matches.values_of("config").unwrap().collect::<Vec<_>>();
+    let encoder = matches.value_of("encoder").unwrap().parse()?;
+    let seed = matches.value_of("seed").map(str::parse).transpose()?;
+    let mut source = Source::new(&paths, Duration::ZERO)?.seed(seed);
+    let mut vals = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(source.load())?;
+    let context = Context {
+        start: Instant::now(),
+        stats: Default::default(),
+        utf16_bom: !flag(matches, "no-utf16-bom"),
+        index: 0,
+        topic: Arc::from(""),
+        averages: Default::default(),
+        warn_precision_loss: false,
+        seed,
+        entry_rng: Default::default(),
+        constants: Default::default(),
+    };
+    data::check(&mut vals, encoder, &context)?;
+    println!("{}", serde_json::to_string_pretty(&vals)?);
+    Ok(())
 }
 
-async fn eventloop_task(mut eventloop: EventLoop) -> Result<()> {
-    loop {
-        match eventloop.poll().await {
-            Err(e) => {
-                log::error!("Lost connection to MQTT Broker {:?}, retrying in 3s", e);
-                sleep(Duration::from_secs(3)).await;
-            }
-            Ok(p) => {
-                log::debug!("MQTT Event: {:?}", p)
-            }
+/// Parse a local IP address with an optional port, any free port is used without one.
+fn parse_bind_address(addr: &str) -> Result<SocketAddr> {
+    addr.parse()
+        .or_else(|_| addr.parse().map(|ip: IpAddr| SocketAddr::new(ip, 0)))
+        .with_context(|| format!("Invalid bind address {}", addr))
+}
+
+/// Forward broker connections over QUIC and get the loopback address to connect to instead.
+#[cfg(feature = "quic")]
+async fn forward_quic(
+    broker: (String, u16),
+    ca_cert: Option<&str>,
+    client_auth: Option<(&str, &str)>,
+) -> Result<SocketAddr> {
+    let config = tls::quic_configuration(ca_cert, client_auth)?;
+    let forwarder = quic::QuicForwarder::bind(broker, config).await?;
+    let addr = forwarder.addr()?;
+    task::spawn(async move {
+        if let Err(e) = forwarder.run().await {
+            log::error!("Forwarding broker connections over QUIC failed: {}", e);
         }
+    });
+    Ok(addr)
+}
+
+#[cfg(not(feature = "quic"))]
+async fn forward_quic(
+    _: (String, u16),
+    _: Option<&str>,
+    _: Option<(&str, &str)>,
+) -> Result<SocketAddr> {
+    unreachable!("QUIC is rejected without the quic feature")
+}
+
+/// Get the WebSocket transport, secured with TLS for `wss`.
+#[cfg(feature = "websocket")]
+fn websocket_transport(
+    secure: bool,
+    ca_cert: Option<&str>,
+    client_auth: Option<(&str, &str)>,
+) -> Result<Transport> {
+    Ok(if secure {
+        Transport::wss_with_config(tls::configuration(ca_cert, client_auth)?)
+    } else {
+        Transport::Ws
+    })
+}
+
+#[cfg(not(feature = "websocket"))]
+fn websocket_transport(_: bool, _: Option<&str>, _: Option<(&str, &str)>) -> Result<Transport> {
+    unreachable!("WebSockets are rejected without the websocket feature")
+}
+
+/// Get the broker password from the command line or the environment.
+///
+/// `MQTT_PASSWORD` is read if `MQTT_SIMULATOR_PASSWORD` isn't set.
+fn password(matches: &ArgMatches) -> Option<String> {
+    matches
+        .value_of("password")
+        .map(ToOwned::to_owned)
+        .or_else(|| std::env::var("MQTT_PASSWORD").ok())
+}
+
+/// The names of the arguments defined on the App, collected while building it.
+struct ArgNames {
+    options: Vec<&'static str>,
+    flags: Vec<&'static str>,
+}
+
+/// Log the value of every argument, including defaults.
+fn log_effective_config(names: &ArgNames, matches: &ArgMatches) {
+    let mut config = String::new();
+    // the password and the credentials of the proxy are logged below
+    for option in names
+        .options
+        .iter()
+        .filter(|option| !matches!(**option, "password" | "http-proxy"))
+    {
+        let value = matches.values_of(option).map_or_else(
+            || "unset".to_string(),
+            |values| values.collect::<Vec<_>>().join(","),
+        );
+        config.push_str(&format!("\n  {}: {}", option, value));
     }
+    for name in &names.flags {
+        config.push_str(&format!("\n  {}: {}", name, flag(matches, name)));
+    }
+    // the password itself is never logged
+    let password = if password(matches).is_some() {
+        "set"
+    } else {
+        "unset"
+    };
+    config.push_str(&format!("\n  password: {}", password));
+    // nor are the credentials of the proxy
+    let proxy = matches.value_of("http-proxy").map_or("unset", |proxy| {
+        proxy.rsplit_once('@').map_or(proxy, |(_, addr)| addr)
+    });
+    config.push_str(&format!("\n  http-proxy: {}", proxy));
+    log::info!("Effective configuration:{}", config);
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+/// Define the command line arguments, along with their names for the effective configuration.
+fn app() -> (App<'static, 'static>, ArgNames) {
+    let (mut options, mut flags) = (Vec::new(), Vec::new());
+    let mut option = |name| {
+        options.push(name);
+        Arg::with_name(name)
+    };
+    let mut switch = |name| {
+        flags.push(name);
+        Arg::with_name(name)
+    };
     let app = App::new("mqtt-simulator")
-        .arg(Arg::with_name("config"))
         .arg(
-            Arg::with_name("host")
+            option("config")
+                .env("MQTT_SIMULATOR_CONFIG")
+                .help("Data files, later files replace the entries of earlier ones with the same topics")
+                .multiple(true)
+                .default_value(DEFAULT_CONFIG),
+        )
+        .arg(
+            option("host")
                 .long("host")
+                .env("MQTT_SIMULATOR_HOST")
                 .short("h")
+                .help("Broker to publish to, repeated or comma-separated to publish to several brokers, named for entries as name=host")
+                .multiple(true)
+                .number_of_values(1)
+                .use_delimiter(true)
                 .default_value("localhost"),
         )
         .arg(
-            Arg::with_name("port")
+            option("port")
                 .long("port")
+                .env("MQTT_SIMULATOR_PORT")
                 .short("p")
-                .default_value("1883"),
+                .help("Port of the broker, 1883 or 8080 and 443 for WebSockets by default, repeated for every --host or once for all")
+                .multiple(true)
+                .number_of_values(1)
+                .use_delimiter(true)
+                .takes_value(true),
         )
         .arg(
-            Arg::with_name("client-id")
+            option("bind-address")
+                .long("bind-address")
+                .env("MQTT_SIMULATOR_BIND_ADDRESS")
+                .help("Local IP address, optionally with a port, to connect to the broker from")
+                .takes_value(true),
+        )
+        .arg(
+            option("http-proxy")
+                .long("http-proxy")
+                .env("MQTT_SIMULATOR_HTTP_PROXY")
+                .help("HTTP proxy as [user:password@]host:port to tunnel the broker connection through with CONNECT")
+                .conflicts_with("bind-address")
+                .takes_value(true),
+        )
+        .arg(
+            option("transport")
+                .long("transport")
+                .env("MQTT_SIMULATOR_TRANSPORT")
+                .help("Transport to the broker, tcp, quic, ws or wss")
+                .default_value("tcp"),
+        )
+        .arg(
+            option("ws-path")
+                .long("ws-path")
+                .env("MQTT_SIMULATOR_WS_PATH")
+                .help("Path of the broker's WebSocket endpoint")
+                .default_value("/mqtt"),
+        )
+        .arg(
+            option("max-reconnect-attempts")
+                .long("max-reconnect-attempts")
+                .env("MQTT_SIMULATOR_MAX_RECONNECT_ATTEMPTS")
+                .takes_value(true)
+                .help("Exit with an error after this many consecutive failed reconnects"),
+        )
+        .arg(
+            option("reconnect-min")
+                .long("reconnect-min")
+                .env("MQTT_SIMULATOR_RECONNECT_MIN")
+                .help("Milliseconds to wait before the first reconnect attempt")
+                .default_value("1000"),
+        )
+        .arg(
+            option("reconnect-max")
+                .long("reconnect-max")
+                .env("MQTT_SIMULATOR_RECONNECT_MAX")
+                .help("Milliseconds the wait between reconnect attempts grows to at most")
+                .default_value("60000"),
+        )
+        .arg(
+            switch("tls")
+                .long("tls")
+                .help("Connect to the broker over TLS")
+                .conflicts_with_all(&["bind-address", "http-proxy"]),
+        )
+        .arg(
+            option("tls-server-name")
+                .long("tls-server-name")
+                .env("MQTT_SIMULATOR_TLS_SERVER_NAME")
+                .help("DNS name to present in the TLS handshake and verify the broker for instead of --host")
+                .requires("tls")
+                .takes_value(true),
+        )
+        .arg(
+            option("ca-cert")
+                .long("ca-cert")
+                .env("MQTT_SIMULATOR_CA_CERT")
+                .help("PEM file of the CA certificates to verify the broker with instead of the system roots")
+                .takes_value(true),
+        )
+        .arg(
+            option("client-cert")
+                .long("client-cert")
+                .env("MQTT_SIMULATOR_CLIENT_CERT")
+                .help("PEM file of the client certificate chain to authenticate with over TLS")
+                .requires("client-key")
+                .takes_value(true),
+        )
+        .arg(
+            option("client-key")
+                .long("client-key")
+                .env("MQTT_SIMULATOR_CLIENT_KEY")
+                .help("PEM file of the private key of --client-cert")
+                .requires("client-cert")
+                .takes_value(true),
+        )
+        .arg(
+            option("client-id")
                 .long("client-id")
+                .env("MQTT_SIMULATOR_CLIENT_ID")
                 .short("i")
                 .default_value("mqtt-simulator"),
         )
         .arg(
-            Arg::with_name("send-interval")
+            switch("unique-client-id")
+                .long("unique-client-id")
+                .help("Append a random suffix to the client id, so simulators started alike don't disconnect each other"),
+        )
+        .arg(
+            option("keep-alive")
+                .long("keep-alive")
+                .env("MQTT_SIMULATOR_KEEP_ALIVE")
+                .help("Seconds between keep-alive pings, at least 5")
+                .default_value("60"),
+        )
+        .arg(
+            switch("clean-session")
+                .long("clean-session")
+                .help("Start every connection with a clean session, the default")
+                .conflicts_with("persistent-session"),
+        )
+        .arg(
+            switch("persistent-session")
+                .long("persistent-session")
+                .help("Keep the session and its subscriptions at the broker across connections"),
+        )
+        .arg(
+            option("username")
+                .long("username")
+                .env("MQTT_SIMULATOR_USERNAME")
+                .help("Username to authenticate with at the broker")
+                .takes_value(true),
+        )
+        .arg(
+            option("password")
+                .long("password")
+                .env("MQTT_SIMULATOR_PASSWORD")
+                .hide_env_values(true)
+                .help("Password to authenticate with at the broker, also read from MQTT_PASSWORD")
+                .takes_value(true),
+        )
+        .arg(
+            option("will-topic")
+                .long("will-topic")
+                .env("MQTT_SIMULATOR_WILL_TOPIC")
+                .help("Topic the broker publishes the last will to if the simulator disconnects ungracefully")
+                .takes_value(true),
+        )
+        .arg(
+            option("will-payload")
+                .long("will-payload")
+                .env("MQTT_SIMULATOR_WILL_PAYLOAD")
+                .help("UTF-8 payload of the last will, empty by default")
+                .requires("will-topic")
+                .takes_value(true),
+        )
+        .arg(
+            option("will-qos")
+                .long("will-qos")
+                .env("MQTT_SIMULATOR_WILL_QOS")
+                .help("QoS of the last will, 0, 1 or 2, 0 by default")
+                .requires("will-topic")
+                .takes_value(true),
+        )
+        .arg(
+            switch("will-retain")
+                .long("will-retain")
+                .help("Retain the last will")
+                .requires("will-topic"),
+        )
+        .arg(
+            option("send-interval")
                 .long("send-interval")
+                .env("MQTT_SIMULATOR_SEND_INTERVAL")
                 .short("t")
                 .help("Send interval in milliseconds")
                 .default_value("1000"),
+        )
+        .arg(
+            option("jitter")
+                .long("jitter")
+                .env("MQTT_SIMULATOR_JITTER")
+                .help("Maximum milliseconds each publish deviates from the send interval at random")
+                .default_value("0"),
+        )
+        .arg(
+            option("send-delay")
+                .long("send-delay")
+                .env("MQTT_SIMULATOR_SEND_DELAY")
+                .help("Milliseconds each publish is held before it is sent")
+                .default_value("0"),
+        )
+        .arg(
+            option("value-ttl")
+                .long("value-ttl")
+                .env("MQTT_SIMULATOR_VALUE_TTL")
+                .help("Milliseconds after computing a value it is skipped instead of sent")
+                .takes_value(true),
+        )
+        .arg(
+            option("worker-threads")
+                .long("worker-threads")
+                .env("MQTT_SIMULATOR_WORKER_THREADS")
+                .help("Number of runtime worker threads, defaults to the number of CPU cores")
+                .takes_value(true),
+        )
+        .arg(
+            option("config-freshness-timeout")
+                .long("config-freshness-timeout")
+                .env("MQTT_SIMULATOR_CONFIG_FRESHNESS_TIMEOUT")
+                .help("Pause publishing while the data file is older than this many milliseconds")
+                .takes_value(true),
+        )
+        .arg(
+            option("config-poll-interval")
+                .long("config-poll-interval")
+                .env("MQTT_SIMULATOR_CONFIG_POLL_INTERVAL")
+                .help("Milliseconds between polls of a data file loaded from an http(s) URL")
+                .default_value("10000"),
+        )
+        .arg(
+            option("error-topic")
+                .long("error-topic")
+                .env("MQTT_SIMULATOR_ERROR_TOPIC")
+                .help("Topic to publish config parse errors to")
+                .takes_value(true),
+        )
+        .arg(
+            option("config-error-threshold")
+                .long("config-error-threshold")
+                .env("MQTT_SIMULATOR_CONFIG_ERROR_THRESHOLD")
+                .help("Failed attempts to load a changed data file before reporting the error")
+                .default_value("10"),
+        )
+        .arg(
+            option("config-status-topic")
+                .long("config-status-topic")
+                .env("MQTT_SIMULATOR_CONFIG_STATUS_TOPIC")
+                .help("Topic to publish the time and entry count of every config reload to")
+                .takes_value(true),
+        )
+        .arg(
+            option("schema-topic")
+                .long("schema-topic")
+                .env("MQTT_SIMULATOR_SCHEMA_TOPIC")
+                .help("Topic to publish a description of the data's topics and types to on connect")
+                .takes_value(true),
+        )
+        .arg(
+            option("schema-payload")
+                .long("schema-payload")
+                .env("MQTT_SIMULATOR_SCHEMA_PAYLOAD")
+                .help("Fixed payload of the schema message instead of the derived description")
+                .requires("schema-topic")
+                .takes_value(true),
+        )
+        .arg(
+            option("history-topic")
+                .long("history-topic")
+                .env("MQTT_SIMULATOR_HISTORY_TOPIC")
+                .help("Control topic to query recently published messages on")
+                .takes_value(true),
+        )
+        .arg(
+            option("history-size")
+                .long("history-size")
+                .env("MQTT_SIMULATOR_HISTORY_SIZE")
+                .help("Number of recently published messages to keep for --history-topic")
+                .default_value("100"),
+        )
+        .arg(
+            option("subscribe")
+                .long("subscribe")
+                .env("MQTT_SIMULATOR_SUBSCRIBE")
+                .help("Topic filter whose messages are republished under --echo-prefix, can be repeated")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .use_delimiter(true),
+        )
+        .arg(
+            option("echo-prefix")
+                .long("echo-prefix")
+                .env("MQTT_SIMULATOR_ECHO_PREFIX")
+                .help("Prefix of the topics that subscribed messages are republished to")
+                .default_value("echo/"),
+        )
+        .arg(
+            option("control-topic")
+                .long("control-topic")
+                .env("MQTT_SIMULATOR_CONTROL_TOPIC")
+                .help("Topic to receive control commands on")
+                .takes_value(true),
+        )
+        .arg(
+            switch("standby")
+                .long("standby")
+                .help("Connect without publishing until promoted on the control topic")
+                .requires("control-topic"),
+        )
+        .arg(
+            option("ping-topic")
+                .long("ping-topic")
+                .env("MQTT_SIMULATOR_PING_TOPIC")
+                .help("Topic to measure the broker round trip time on")
+                .takes_value(true),
+        )
+        .arg(
+            option("ping-interval")
+                .long("ping-interval")
+                .env("MQTT_SIMULATOR_PING_INTERVAL")
+                .help("Ping interval in milliseconds")
+                .default_value("1000"),
+        )
+        .arg(
+            option("max-packet-size")
+                .long("max-packet-size")
+                .env("MQTT_SIMULATOR_MAX_PACKET_SIZE")
+                .help("Skip publishes whose packets exceed this size in bytes")
+                .takes_value(true),
+        )
+        .arg(
+            option("max-payload-bytes")
+                .long("max-payload-bytes")
+                .env("MQTT_SIMULATOR_MAX_PAYLOAD_BYTES")
+                .help("Warn about payloads larger than this many bytes")
+                .default_value("268435455"),
+        )
+        .arg(
+            switch("strict-payload-size")
+                .long("strict-payload-size")
+                .help("Skip payloads beyond --max-payload-bytes instead of warning about them"),
+        )
+        .arg(
+            option("request-capacity")
+                .long("request-capacity")
+                .env("MQTT_SIMULATOR_REQUEST_CAPACITY")
+                .help("Number of requests queued for the event loop before --backpressure applies")
+                .default_value("10"),
+        )
+        .arg(
+            option("backpressure")
+                .long("backpressure")
+                .env("MQTT_SIMULATOR_BACKPRESSURE")
+                .help("Handling of publishes when the request queue is full, block, drop-oldest or drop-newest")
+                .default_value("block"),
+        )
+        .arg(
+            option("max-msgs-per-sec")
+                .long("max-msgs-per-sec")
+                .env("MQTT_SIMULATOR_MAX_MSGS_PER_SEC")
+                .help("Limit the publishes across all topics to this many messages per second")
+                .takes_value(true),
+        )
+        .arg(
+            option("encoder")
+                .long("encoder")
+                .env("MQTT_SIMULATOR_ENCODER")
+                .help("Default payload encoding, either binary or json")
+                .default_value("binary"),
+        )
+        .arg(
+            switch("no-utf16-bom")
+                .long("no-utf16-bom")
+                .help("Omit the BOM of UTF-16 strings unless an entry sets bom"),
+        )
+        .arg(
+            switch("warn-precision-loss")
+                .long("warn-precision-loss")
+                .help("Warn about floats that lose precision when published with 32 bits"),
+        )
+        .arg(
+            option("drop-rate")
+                .long("drop-rate")
+                .env("MQTT_SIMULATOR_DROP_RATE")
+                .help("Fraction of publishes to randomly drop")
+                .default_value("0"),
+        )
+        .arg(
+            option("chaos")
+                .long("chaos")
+                .env("MQTT_SIMULATOR_CHAOS")
+                .help("Inject faults with the given probabilities, e.g. drop=0.1,duplicate=0.05,corrupt=0.01,flap=0.001")
+                .takes_value(true),
+        )
+        .arg(
+            switch("shuffle")
+                .long("shuffle")
+                .help("Publish the entries in random order every tick"),
+        )
+        .arg(
+            switch("stagger")
+                .long("stagger")
+                .help("Spread the first publishes of the entries evenly over their interval"),
+        )
+        .arg(
+            switch("publish-every-revision")
+                .long("publish-every-revision")
+                .help("Publish every revision of the data file at least once, even if reloaded quickly"),
+        )
+        .arg(
+            switch("dedup")
+                .long("dedup")
+                .help("Skip publishes whose payload is identical to the previous one of the topic"),
+        )
+        .arg(
+            option("seed")
+                .long("seed")
+                .env("MQTT_SIMULATOR_SEED")
+                .help("Seed for the random number generator, seeded from entropy if unset")
+                .takes_value(true),
+        )
+        .arg(
+            switch("summary")
+                .long("summary")
+                .help("Log a throughput summary on exit"),
+        )
+        .arg(
+            option("metrics-csv")
+                .long("metrics-csv")
+                .env("MQTT_SIMULATOR_METRICS_CSV")
+                .help("CSV file to append the counters to every metrics interval")
+                .takes_value(true),
+        )
+        .arg(
+            option("metrics-interval")
+                .long("metrics-interval")
+                .env("MQTT_SIMULATOR_METRICS_INTERVAL")
+                .help("Interval of the rows of --metrics-csv in milliseconds")
+                .default_value("1000"),
+        )
+        .arg(
+            option("metrics-addr")
+                .long("metrics-addr")
+                .env("MQTT_SIMULATOR_METRICS_ADDR")
+                .help("Address to serve Prometheus metrics on at /metrics, e.g. 0.0.0.0:9100")
+                .takes_value(true),
+        )
+        .arg(
+            option("drain-timeout")
+                .long("drain-timeout")
+                .env("MQTT_SIMULATOR_DRAIN_TIMEOUT")
+                .help("Milliseconds to wait for pending acknowledgements on shutdown")
+                .default_value("5000"),
+        )
+        .arg(
+            option("stall-timeout")
+                .long("stall-timeout")
+                .env("MQTT_SIMULATOR_STALL_TIMEOUT")
+                .help(
+                    "Log an error when the sender or the event loop made no progress for this \
+                     many milliseconds",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            switch("exit-on-stall")
+                .long("exit-on-stall")
+                .help("Exit with an error once --stall-timeout detects a stall")
+                .requires("stall-timeout"),
+        )
+        .arg(
+            option("pid-file")
+                .long("pid-file")
+                .env("MQTT_SIMULATOR_PID_FILE")
+                .help("File to write the process id to, removed on exit")
+                .takes_value(true),
+        )
+        .arg(
+            option("record")
+                .long("record")
+                .env("MQTT_SIMULATOR_RECORD")
+                .help("File to record all published data messages to")
+                .takes_value(true),
+        )
+        .arg(
+            option("replay")
+                .long("replay")
+                .env("MQTT_SIMULATOR_REPLAY")
+                .help("Republish a recording with its original timing instead of the data file")
+                .conflicts_with("record")
+                .takes_value(true),
+        )
+        .arg(
+            option("replay-speed")
+                .long("replay-speed")
+                .env("MQTT_SIMULATOR_REPLAY_SPEED")
+                .help("Speed up the replay by this factor, 2 halves the delays between the messages")
+                .default_value("1"),
+        )
+        .arg(
+            option("admin-port")
+                .long("admin-port")
+                .env("MQTT_SIMULATOR_ADMIN_PORT")
+                .help("Port to serve the HTTP admin API on")
+                .takes_value(true),
+        )
+        .arg(
+            option("bench")
+                .long("bench")
+                .env("MQTT_SIMULATOR_BENCH")
+                .help("Publish this many messages as fast as possible, report the throughput and exit")
+                .conflicts_with_all(&["replay", "dry-run", "once"])
+                .takes_value(true),
+        )
+        .arg(
+            switch("dry-run")
+                .long("dry-run")
+                .help("Print the payloads to stdout instead of connecting to the broker"),
+        )
+        .arg(
+            switch("once")
+                .long("once")
+                .help("Publish every entry once and exit after the broker acknowledged them"),
+        )
+        .arg(
+            switch("check")
+                .long("check")
+                .help("Print the resolved data and exit"),
+        )
+        .arg(
+            switch("generate-example")
+                .long("generate-example")
+                .help("Print an example data file and exit"),
         );
+    (app, ArgNames { options, flags })
+}
+
+fn main() -> Result<()> {
+    let (app, arg_names) = app();
     let matches = app.get_matches();
+    if matches.is_present("generate-example") {
+        println!("{}", serde_json::to_string_pretty(&example::generate())?);
+        return Ok(());
+    }
+    if matches.is_present("check") {
+        return check(&matches);
+    }
 
-    let path = matches.value_of("config").unwrap().to_string();
-    let host = matches.value_of("host").unwrap();
-    let port = matches.value_of("port").unwrap().parse()?;
+    let mut runtime = runtime::Builder::new_multi_thread();
+    if let Some(worker_threads) = matches.value_of("worker-threads") {
+        let worker_threads = worker_threads.parse().context("Invalid --worker-threads")?;
+        if worker_threads == 0 {
+            anyhow::bail!("--worker-threads must be at least 1");
+        }
+        runtime.worker_threads(worker_threads);
+    }
+    runtime
+        .enable_all()
+        .build()?
+        .block_on(run(&arg_names, matches))
+}
+
+async fn run(arg_names: &ArgNames, matches: ArgMatches<'_>) -> Result<()> {
+    let seed = matches.value_of("seed").map(str::parse).transpose()?;
+    let max_reconnect_attempts = matches
+        .value_of("max-reconnect-attempts")
+        .map(str::parse)
+        .transpose()
+        .context("Invalid --max-reconnect-attempts")?;
+    let reconnect_min = matches
+        .value_of("reconnect-min")
+        .unwrap()
+        .parse()
+        .context("Invalid --reconnect-min")?;
+    let reconnect_max = matches
+        .value_of("reconnect-max")
+        .unwrap()
+        .parse()
+        .context("Invalid --reconnect-max")?;
+    if reconnect_min > reconnect_max {
+        anyhow::bail!("--reconnect-min must not exceed --reconnect-max");
+    }
+    let config_poll_interval = Duration::from_millis(
+        matches
+            .value_of("config-poll-interval")
+            .unwrap()
+            .parse()
+            .context("Invalid --config-poll-interval")?,
+    );
+    let paths = matches.values_of("config").unwrap().collect::<Vec<_>>();
+    let mut source = Source::new(&paths, config_poll_interval)?.seed(seed);
+    let path = source.label().to_string();
+    // brokers are named after their host for the `broker` of entries unless given as name=host
+    let (mut names, hosts): (Vec<_>, Vec<_>) = matches
+        .values_of("host")
+        .unwrap()
+        .map(|host| host.split_once('=').unwrap_or((host, host)))
+        .unzip();
+    if names.iter().chain(&hosts).any(|name| name.is_empty()) {
+        anyhow::bail!("Invalid --host, expected a host or name=host");
+    }
+    let bind_address = matches
+        .value_of("bind-address")
+        .map(parse_bind_address)
+        .transpose()?;
+    let http_proxy = matches
+        .value_of("http-proxy")
+        .map(str::parse::<HttpProxy>)
+        .transpose()
+        .map_err(|e| anyhow!("Invalid --http-proxy: {}", e))?;
+    let tls = flag(&matches, "tls");
+    // the scheme of WebSocket transports, `wss` is secured with TLS
+    let (quic, websocket) = match matches.value_of("transport").unwrap() {
+        "tcp" => (false, None),
+        "quic" if cfg!(feature = "quic") => (true, None),
+        "quic" => anyhow::bail!("--transport quic requires building with the quic feature"),
+        scheme @ ("ws" | "wss") if cfg!(feature = "websocket") => (false, Some(scheme)),
+        scheme @ ("ws" | "wss") => anyhow::bail!(
+            "--transport {} requires building with the websocket feature",
+            scheme
+        ),
+        transport => anyhow::bail!(
+            "Unknown transport {}, expected tcp, quic, ws or wss",
+            transport
+        ),
+    };
+    let secure = websocket == Some("wss");
+    if (quic || secure) && (tls || matches.is_present("bind-address") || http_proxy.is_some()) {
+        anyhow::bail!(
+            "--transport quic and wss always use TLS and can't be combined with --tls, \
+             --bind-address or --http-proxy"
+        );
+    }
+    if websocket.is_some() && tls {
+        anyhow::bail!("--tls doesn't apply to WebSockets, use --transport wss instead");
+    }
+    let ports = match matches.values_of("port") {
+        Some(ports) => ports
+            .map(str::parse)
+            .collect::<Result<Vec<u16>, _>>()
+            .context("Invalid --port")?,
+        None if secure => vec![443],
+        None if websocket.is_some() => vec![8080],
+        None => vec![1883],
+    };
+    let brokers = match ports.as_slice() {
+        [port] => hosts.iter().map(|host| (*host, *port)).collect::<Vec<_>>(),
+        ports if ports.len() == hosts.len() => {
+            hosts.into_iter().zip(ports.iter().copied()).collect()
+        }
+        _ => anyhow::bail!("Expected a single --port or one for every --host"),
+    };
+    let ws_path = matches.value_of("ws-path").unwrap();
+    if !ws_path.starts_with('/') {
+        anyhow::bail!(
+            "Invalid --ws-path {}, expected a path starting with /",
+            ws_path
+        );
+    }
+    let ca_cert = matches.value_of("ca-cert");
+    let client_auth = matches
+        .value_of("client-cert")
+        .zip(matches.value_of("client-key"));
+    if !tls && !quic && !secure && (ca_cert.is_some() || client_auth.is_some()) {
+        return Err(anyhow!(
+            "--ca-cert, --client-cert and --client-key require --tls or --transport quic or wss"
+        ));
+    }
+    let credentials = match (matches.value_of("username"), password(&matches)) {
+        (Some(username), Some(password)) => Some((username, password)),
+        (None, None) => None,
+        _ => anyhow::bail!("Credentials require both --username and --password"),
+    };
+    let will = match matches.value_of("will-topic") {
+        Some(topic) => {
+            let qos = matches
+                .value_of("will-qos")
+                .unwrap_or("0")
+                .parse::<Qos>()
+                .map_err(|e| anyhow!("Invalid --will-qos: {}", e))?;
+            let payload = matches.value_of("will-payload").unwrap_or_default();
+            Some(LastWill::new(
+                topic,
+                payload,
+                qos.0,
+                flag(&matches, "will-retain"),
+            ))
+        }
+        None => None,
+    };
     let send_interval = matches.value_of("send-interval").unwrap().parse()?;
-    let client_id = matches.value_of("client-id").unwrap();
+    let jitter = Duration::from_millis(matches.value_of("jitter").unwrap().parse()?);
+    let send_delay = Duration::from_millis(matches.value_of("send-delay").unwrap().parse()?);
+    let value_ttl = matches
+        .value_of("value-ttl")
+        .map(str::parse)
+        .transpose()
+        .context("Invalid --value-ttl")?
+        .map(Duration::from_millis);
+    let unique_client_id = flag(&matches, "unique-client-id");
+    // the suffix is drawn once, so reconnects resume under the same id
+    let client_id = match matches.value_of("client-id").unwrap() {
+        client_id if unique_client_id => {
+            format!(
+                "{}-{:06x}",
+                client_id,
+                rand::thread_rng().gen_range(0..1 << 24)
+            )
+        }
+        client_id => client_id.to_string(),
+    };
+    let keep_alive = matches
+        .value_of("keep-alive")
+        .unwrap()
+        .parse::<u16>()
+        .context("Invalid --keep-alive")?;
+    if keep_alive < 5 {
+        anyhow::bail!("--keep-alive must be at least 5 seconds");
+    }
+    let clean_session = !flag(&matches, "persistent-session");
+    let error_topic = matches.value_of("error-topic").map(ToOwned::to_owned);
+    let error_threshold = matches
+        .value_of("config-error-threshold")
+        .unwrap()
+        .parse()?;
+    let status_topic = matches
+        .value_of("config-status-topic")
+        .map(ToOwned::to_owned);
+    let schema_topic = matches.value_of("schema-topic").map(ToOwned::to_owned);
+    let schema_payload = matches.value_of("schema-payload").map(ToOwned::to_owned);
+    let freshness_timeout = matches
+        .value_of("config-freshness-timeout")
+        .map(str::parse)
+        .transpose()?
+        .map(Duration::from_millis);
+    let encoder = matches.value_of("encoder").unwrap().parse()?;
+    let summary = flag(&matches, "summary");
+    let chaos = matches
+        .value_of("chaos")
+        .map(str::parse::<Chaos>)
+        .transpose()
+        .map_err(|e| anyhow!("Invalid --chaos: {}", e))?
+        .unwrap_or_default();
+    let drop_rate = matches.value_of("drop-rate").unwrap().parse()?;
+    let shuffle = flag(&matches, "shuffle");
+    let stagger = flag(&matches, "stagger");
+    let dedup = flag(&matches, "dedup");
+    let max_packet_size = matches
+        .value_of("max-packet-size")
+        .map(str::parse)
+        .transpose()?;
+    let max_payload_bytes = matches
+        .value_of("max-payload-bytes")
+        .unwrap()
+        .parse()
+        .context("Invalid --max-payload-bytes")?;
+    let strict_payload_size = flag(&matches, "strict-payload-size");
+    let rate_limit = matches
+        .value_of("max-msgs-per-sec")
+        .map(|rate| {
+            rate.parse::<f64>()
+                .ok()
+                .filter(|rate| rate.is_finite() && *rate > 0.)
+                .context("Invalid --max-msgs-per-sec, expected a positive rate")
+        })
+        .transpose()?;
+    let request_capacity = matches
+        .value_of("request-capacity")
+        .unwrap()
+        .parse::<usize>()
+        .ok()
+        .filter(|&capacity| capacity > 0)
+        .context("Invalid --request-capacity, expected a positive number")?;
+    let backpressure: Backpressure = matches.value_of("backpressure").unwrap().parse()?;
+    let history_size = matches.value_of("history-size").unwrap().parse()?;
+    let echo = matches.values_of("subscribe").map(|filters| {
+        let prefix = matches.value_of("echo-prefix").unwrap().to_string();
+        (filters.map(ToOwned::to_owned).collect(), prefix)
+    });
+    let ping_interval = Duration::from_millis(matches.value_of("ping-interval").unwrap().parse()?);
 
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
-    log::info!(
-        "Sending data from {} to MQTT Broker at {}:{} as {}",
-        path,
-        host,
-        port,
-        client_id
+    let metrics_interval = Duration::from_millis(
+        matches
+            .value_of("metrics-interval")
+            .unwrap()
+            .parse()
+            .context("Invalid --metrics-interval")?,
     );
-    let opts = MqttOptions::new(client_id, host, port);
+    let drain_timeout = Duration::from_millis(matches.value_of("drain-timeout").unwrap().parse()?);
+    let stall_timeout = matches
+        .value_of("stall-timeout")
+        .map(str::parse)
+        .transpose()
+        .context("Invalid --stall-timeout")?
+        .filter(|&ms| ms > 0)
+        .map(Duration::from_millis);
+    if matches.is_present("stall-timeout") && stall_timeout.is_none() {
+        anyhow::bail!("--stall-timeout must be positive");
+    }
+    // an idle connection only returns an event for every keep-alive ping
+    if stall_timeout.is_some_and(|timeout| timeout <= Duration::from_secs(keep_alive.into())) {
+        anyhow::bail!("--stall-timeout has to exceed the --keep-alive interval");
+    }
+    let pid_file = matches.value_of("pid-file");
+    let replay = matches.value_of("replay").map(ToOwned::to_owned);
+    let replay_speed = matches
+        .value_of("replay-speed")
+        .unwrap()
+        .parse::<f64>()
+        .ok()
+        .filter(|speed| speed.is_finite() && *speed > 0.)
+        .context("Invalid --replay-speed, expected a positive factor")?;
+    let admin_port = matches
+        .value_of("admin-port")
+        .map(str::parse::<u16>)
+        .transpose()?;
+    let metrics_addr = matches
+        .value_of("metrics-addr")
+        .map(str::parse::<SocketAddr>)
+        .transpose()
+        .context("Invalid --metrics-addr")?;
+    let bench = matches
+        .value_of("bench")
+        .map(str::parse)
+        .transpose()
+        .context("Invalid --bench")?;
+    let dry_run = flag(&matches, "dry-run");
+    let once = flag(&matches, "once");
 
-    let eventloop = EventLoop::new(opts, 10);
-    let requests_tx = eventloop.handle();
-    let (data_tx, data_rx) = watch::channel(vec![]);
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    log_effective_config(arg_names, &matches);
+    if !clean_session && unique_client_id {
+        log::warn!(
+            "Persistent session with --unique-client-id, the session can't be resumed after a restart"
+        );
+    } else if !clean_session
+        && matches.occurrences_of("client-id") == 0
+        && std::env::var_os("MQTT_SIMULATOR_CLIENT_ID").is_none()
+    {
+        log::warn!(
+            "Persistent session with the default client id {}, set a stable --client-id unique to this simulator",
+            client_id
+        );
+    }
+    // connections to the same broker under one client id take each other over
+    if let Some((host, port)) = brokers
+        .iter()
+        .enumerate()
+        .find_map(|(i, broker)| brokers[..i].contains(broker).then_some(broker))
+    {
+        log::warn!(
+            "Broker {}:{} is listed more than once, its connections share the client id {} and \
+             disconnect each other",
+            host,
+            port,
+            client_id
+        );
+    }
+    // a broken data file fails right away, later edits are reloaded by the watcher
+    let data = match replay {
+        Some(_) => Vec::new(),
+        None => source.load().await?,
+    };
+    if let Some(pid_file) = pid_file {
+        std::fs::write(pid_file, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Failed to write pid file {}", pid_file))?;
+    }
+    if dry_run {
+        log::info!("Printing data from {} instead of sending it", path);
+    } else {
+        let addresses = brokers
+            .iter()
+            .map(|(host, port)| format!("{}:{}", host, port))
+            .collect::<Vec<_>>();
+        log::info!(
+            "Sending data from {} to MQTT Broker at {} as {}",
+            path,
+            addresses.join(", "),
+            client_id
+        );
+    }
+    let server_name = matches.value_of("tls-server-name");
+    let route = match (bind_address, http_proxy) {
+        (Some(local), _) => Some(Route::Bind(local)),
+        (None, Some(proxy)) => Some(Route::HttpProxy(proxy)),
+        // rumqttc presents the host it connects to, so a forwarder secures the connection
+        (None, None) if server_name.is_some() => Some(Route::Direct),
+        (None, None) => None,
+    };
+    // the address the client connects to for every broker, a local one if it's forwarded
+    let mut addresses = Vec::new();
+    for (host, port) in brokers {
+        let (host, port) = match &route {
+            // a dry run never connects, so there's nothing to forward
+            _ if dry_run => (host.to_string(), port),
+            Some(route) => {
+                log::info!("Connecting to the broker {}", route);
+                let mut forwarder =
+                    Forwarder::bind(route.clone(), (host.to_string(), port)).await?;
+                if let Some(name) = server_name {
+                    log::info!("Verifying the broker {} as {}", host, name);
+                    forwarder = forwarder.tls(tls::client_config(ca_cert, client_auth)?, name)?;
+                }
+                let addr = forwarder.addr()?;
+                task::spawn(async move {
+                    if let Err(e) = forwarder.run().await {
+                        log::error!("Forwarding broker connections failed: {}", e);
+                    }
+                });
+                (addr.ip().to_string(), addr.port())
+            }
+            None if quic => {
+                let addr = forward_quic((host.to_string(), port), ca_cert, client_auth).await?;
+                log::info!("Connecting to the broker over QUIC");
+                (addr.ip().to_string(), addr.port())
+            }
+            None => (host.to_string(), port),
+        };
+        addresses.push(match websocket {
+            // WebSocket clients connect to the URL given as the host
+            Some(scheme) => (format!("{}://{}:{}{}", scheme, host, port, ws_path), port),
+            None => (host, port),
+        });
+    }
+    let (host, port) = addresses.remove(0);
+    let broker_name = names.remove(0);
+    let mut opts = MqttOptions::new(client_id, host, port);
+    opts.set_keep_alive(keep_alive)
+        .set_clean_session(clean_session);
+    if let Some((username, password)) = credentials {
+        opts.set_credentials(username, &password);
+    }
+    if let Some(will) = will {
+        opts.set_last_will(will);
+    }
+    // forwarded connections are already secured
+    if tls && server_name.is_none() {
+        let config = tls::configuration(ca_cert, client_auth)?;
+        opts.set_transport(Transport::tls_with_config(config));
+    }
+    if websocket.is_some() {
+        opts.set_transport(websocket_transport(secure, ca_cert, client_auth)?);
+    }
 
-    let watcher = task::spawn(data_watcher(path, data_tx));
+    let mut simulator = Simulator::new(opts, data)
+        .interval(Duration::from_millis(send_interval))
+        .jitter(jitter)
+        .send_delay(send_delay)
+        .encoder(encoder)
+        .utf16_bom(!flag(&matches, "no-utf16-bom"))
+        .warn_precision_loss(flag(&matches, "warn-precision-loss"))
+        .drop_rate(drop_rate)
+        .shuffle(shuffle)
+        .stagger(stagger)
+        .dedup(dedup)
+        .max_payload_bytes(max_payload_bytes)
+        .strict_payload_size(strict_payload_size)
+        .backpressure(backpressure)
+        .request_capacity(request_capacity)
+        .reconnect_backoff(
+            Duration::from_millis(reconnect_min),
+            Duration::from_millis(reconnect_max),
+        )
+        .drain_timeout(drain_timeout)
+        .chaos(chaos)
+        .standby(flag(&matches, "standby"))
+        .watch(source)
+        .error_threshold(error_threshold)
+        .publish_every_revision(flag(&matches, "publish-every-revision"))
+        .dry_run(dry_run)
+        .once(once)
+        .summary(summary)
+        .broker_name(broker_name.to_string());
+    for ((host, port), name) in addresses.into_iter().zip(names) {
+        simulator = simulator.named_mirror(name.to_string(), host, port);
+    }
+    if let Some(seed) = seed {
+        simulator = simulator.seed(seed);
+    }
+    if let Some(value_ttl) = value_ttl {
+        simulator = simulator.value_ttl(value_ttl);
+    }
+    if let Some(max_packet_size) = max_packet_size {
+        simulator = simulator.max_packet_size(max_packet_size);
+    }
+    if let Some(rate) = rate_limit {
+        simulator = simulator.rate_limit(rate);
+    }
+    if let Some(attempts) = max_reconnect_attempts {
+        simulator = simulator.max_reconnect_attempts(attempts);
+    }
+    // certificates presented by a forwarder are loaded once
+    if ca_cert.is_some() || client_auth.is_some() {
+        simulator = simulator.reload_tls(ca_cert, client_auth);
+    }
+    if let Some(topic) = matches.value_of("history-topic") {
+        simulator = simulator.history(topic.to_string(), history_size);
+    }
+    if let Some(path) = matches.value_of("record") {
+        simulator = simulator
+            .record(path)
+            .with_context(|| format!("Failed to create {}", path))?;
+    }
+    if let Some((filters, prefix)) = echo {
+        simulator = simulator.echo(filters, prefix);
+    }
+    if let Some(topic) = matches.value_of("control-topic") {
+        simulator = simulator.control_topic(topic.to_string());
+    }
+    if let Some(topic) = matches.value_of("ping-topic") {
+        simulator = simulator.ping(topic.to_string(), ping_interval);
+    }
+    if let Some(topic) = schema_topic {
+        simulator = simulator.schema(topic, schema_payload);
+    }
+    if let Some(topic) = error_topic {
+        simulator = simulator.error_topic(topic);
+    }
+    if let Some(topic) = status_topic {
+        simulator = simulator.status_topic(topic);
+    }
+    if let Some(timeout) = freshness_timeout {
+        simulator = simulator.freshness_timeout(timeout);
+    }
+    if let Some(path) = replay {
+        simulator = simulator.replay(path, replay_speed);
+    }
+    if let Some(count) = bench {
+        simulator = simulator.bench(count);
+    }
+    if let Some(timeout) = stall_timeout {
+        simulator = simulator.stall_timeout(timeout, flag(&matches, "exit-on-stall"));
+    }
+    if let Some(path) = matches.value_of("metrics-csv") {
+        simulator = simulator
+            .metrics_csv(path, metrics_interval)
+            .with_context(|| format!("Failed to open {}", path))?;
+    }
+    if let Some(port) = admin_port {
+        let listener = tokio::net::TcpListener::bind(("0.0.0.0", port))
+            .await
+            .with_context(|| format!("Failed to listen on admin port {}", port))?;
+        log::info!("Serving the admin API on port {}", port);
+        simulator = simulator.admin(listener);
+    }
+    if let Some(addr) = metrics_addr {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen on metrics address {}", addr))?;
+        log::info!("Serving Prometheus metrics on {}", addr);
+        simulator = simulator.prometheus(listener);
+    }
+    let res = simulator
+        .run_with_shutdown(async {
+            signal::ctrl_c().await.ok();
+            log::info!("Shutting down");
+        })
+        .join()
+        .await;
+    if let Some(pid_file) = pid_file {
+        if let Err(e) = std::fs::remove_file(pid_file) {
+            log::warn!("Failed to remove pid file {}: {}", pid_file, e);
+        }
+    }
+    res
+}
 
-    let eventloop_task = task::spawn(eventloop_task(eventloop));
+#[cfg(test)]
+mod tests {
+    use clap::ErrorKind;
 
-    let loop2 = task::spawn(sender(
-        data_rx,
-        requests_tx,
-        interval(Duration::from_millis(send_interval)),
-    ));
-    select! {
-        res = watcher => {
-            bail!("Watcher died: {:?}", res)
-        }
-        res = loop2 => {
-            bail!("Sender died: {:?}", res)
-        },
-        res = eventloop_task => {
-            bail!("Eventloop died: {:?}", res)
+    use super::*;
+
+    /// Parse `args` after the binary name and a data file and get the kind of the error.
+    fn rejected(args: &[&str]) -> Option<ErrorKind> {
+        let (app, _) = app();
+        let args = ["mqtt-simulator", "data.json"].iter().chain(args);
+        app.get_matches_from_safe(args).err().map(|e| e.kind)
+    }
+
+    #[test]
+    fn tls_server_name_requires_tls() {
+        assert_eq!(
+            rejected(&["--tls-server-name", "broker.test"]),
+            Some(ErrorKind::MissingRequiredArgument)
+        );
+        assert_eq!(
+            rejected(&["--tls", "--tls-server-name", "broker.test"]),
+            None
+        );
+    }
+
+    #[test]
+    fn tls_conflicts_with_forwarded_routes() {
+        for route in [
+            ["--bind-address", "127.0.0.1"],
+            ["--http-proxy", "proxy.test:3128"],
+        ] {
+            let args = [&["--tls"][..], &route].concat();
+            assert_eq!(rejected(&args), Some(ErrorKind::ArgumentConflict));
+            assert_eq!(rejected(&route), None);
         }
-    };
+    }
 }
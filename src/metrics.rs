@@ -0,0 +1,76 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tokio::time::interval;
+
+use crate::stats::Stats;
+
+const HEADER: &str = "timestamp_ms,messages,bytes,rate,reconnects,deduplicated,stale";
+
+/// Writer of the simulator's counters to a CSV file, one row per interval.
+pub struct MetricsCsv {
+    writer: LineWriter<File>,
+    stats: Arc<Stats>,
+    /// Time and message count of the previous row, the rate covers the time since.
+    last: (Instant, u64),
+}
+
+impl MetricsCsv {
+    /// Open the CSV file at `path`, appending to an existing file and writing the header to a new one.
+    pub fn open(path: &str, stats: Arc<Stats>) -> Result<Self, io::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut writer = LineWriter::new(file);
+        if writer.get_ref().metadata()?.len() == 0 {
+            writeln!(writer, "{}", HEADER)?;
+        }
+        let last = (Instant::now(), stats.messages());
+        Ok(MetricsCsv {
+            writer,
+            stats,
+            last,
+        })
+    }
+
+    /// Append a row with the current counters, every row is flushed to the file right away.
+    pub fn write_row(&mut self) -> Result<(), io::Error> {
+        let now = Instant::now();
+        let messages = self.stats.messages();
+        let secs = now.duration_since(self.last.0).as_secs_f64();
+        let rate = if secs > 0. {
+            (messages - self.last.1) as f64 / secs
+        } else {
+            0.
+        };
+        self.last = (now, messages);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        writeln!(
+            self.writer,
+            "{},{},{},{:.1},{},{},{}",
+            timestamp,
+            messages,
+            self.stats.bytes(),
+            rate,
+            self.stats.reconnects(),
+            self.stats.deduplicated(),
+            self.stats.stale()
+        )
+    }
+}
+
+/// Write a row of metrics every `period` until the task is aborted.
+pub async fn write_metrics(metrics: Arc<Mutex<MetricsCsv>>, period: Duration) {
+    let mut interval = interval(period);
+    // the first tick completes right away and the counters are still zero
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if let Err(e) = metrics.lock().unwrap().write_row() {
+            log::warn!("Failed to write metrics: {}", e);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Number of round trip times kept for the latency statistics.
+const WINDOW: usize = 1000;
+
+/// Broker round trip time measurement.
+///
+/// Pings carry the time they were sent at, relative to the start of the measurement, so the round
+/// trip time can be computed once they are received back from the broker.
+pub struct Ping {
+    topic: String,
+    start: Instant,
+    samples: Mutex<VecDeque<Duration>>,
+    received: AtomicUsize,
+}
+
+impl Ping {
+    pub fn new(topic: String) -> Self {
+        Ping {
+            topic,
+            start: Instant::now(),
+            samples: Mutex::new(VecDeque::with_capacity(WINDOW)),
+            received: AtomicUsize::new(0),
+        }
+    }
+
+    /// Get the topic pings are sent to.
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Get the payload for a ping sent now.
+    pub fn payload(&self) -> Vec<u8> {
+        (self.start.elapsed().as_nanos() as u64)
+            .to_be_bytes()
+            .to_vec()
+    }
+
+    /// Record the round trip time of a received ping.
+    ///
+    /// Payloads that weren't sent by this measurement are ignored.
+    pub fn record(&self, payload: &[u8]) {
+        let sent = match payload.try_into() {
+            Ok(sent) => Duration::from_nanos(u64::from_be_bytes(sent)),
+            Err(_) => return,
+        };
+        let rtt = match self.start.elapsed().checked_sub(sent) {
+            Some(rtt) => rtt,
+            None => return,
+        };
+        log::debug!("Ping round trip time {:?}", rtt);
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() == WINDOW {
+            samples.pop_front();
+        }
+        samples.push_back(rtt);
+        // log the statistics for every tenth ping
+        if self.received.fetch_add(1, Ordering::Relaxed) % 10 == 9 {
+            let mut sorted = samples.iter().copied().collect::<Vec<_>>();
+            sorted.sort();
+            let percentile = |p: usize| sorted[(sorted.len() - 1) * p / 100];
+            log::info!(
+                "Ping round trip over {} samples: min {:?}, p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+                sorted.len(),
+                sorted[0],
+                percentile(50),
+                percentile(90),
+                percentile(99),
+                sorted[sorted.len() - 1]
+            );
+        }
+    }
+}
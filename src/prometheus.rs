@@ -0,0 +1,116 @@
+use std::fmt::Write as _;
+use std::io;
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task;
+
+use crate::stats::Stats;
+
+/// Serve the counters of `stats` as a Prometheus text page on `/metrics` until the listener fails.
+///
+/// Like the admin API, only the request line is interpreted and every response closes the connection.
+pub async fn serve(listener: TcpListener, stats: Arc<Stats>) -> Result<(), io::Error> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let stats = Arc::clone(&stats);
+        task::spawn(async move {
+            if let Err(e) = handle(stream, &stats).await {
+                log::debug!("Metrics connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle(stream: TcpStream, stats: &Stats) -> Result<(), io::Error> {
+    let mut stream = BufReader::new(stream);
+    let mut request = String::new();
+    stream.read_line(&mut request).await?;
+    let mut header = String::new();
+    while stream.read_line(&mut header).await? > 0 && header.trim_end() != "" {
+        header.clear();
+    }
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let (status, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", render(stats)),
+        (_, "/metrics") => ("405 Method Not Allowed", String::new()),
+        _ => ("404 Not Found", String::new()),
+    };
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+    let stream = stream.get_mut();
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Render the counters in the Prometheus text exposition format.
+fn render(stats: &Stats) -> String {
+    let totals = [
+        (
+            "mqtt_simulator_published_messages_total",
+            "Messages handed to the MQTT client.",
+            stats.messages(),
+        ),
+        (
+            "mqtt_simulator_published_bytes_total",
+            "Payload bytes handed to the MQTT client.",
+            stats.bytes(),
+        ),
+        (
+            "mqtt_simulator_connects_total",
+            "Established connections to the broker.",
+            stats.connects(),
+        ),
+        (
+            "mqtt_simulator_disconnects_total",
+            "Lost connections to the broker.",
+            stats.disconnects(),
+        ),
+        (
+            "mqtt_simulator_reconnects_total",
+            "Connections re-established after losing them.",
+            stats.reconnects(),
+        ),
+    ];
+    // writing to a string can't fail
+    let mut page = String::new();
+    for (name, help, value) in totals {
+        let _ = writeln!(page, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+        let _ = writeln!(page, "{} {}", name, value);
+    }
+    let topics = stats.topics();
+    for (name, help, bytes) in [
+        (
+            "mqtt_simulator_topic_messages_total",
+            "Messages handed to the MQTT client per topic.",
+            false,
+        ),
+        (
+            "mqtt_simulator_topic_bytes_total",
+            "Payload bytes handed to the MQTT client per topic.",
+            true,
+        ),
+    ] {
+        let _ = writeln!(page, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+        for (topic, (messages, total)) in &topics {
+            let value = if bytes { total } else { messages };
+            let _ = writeln!(page, "{}{{topic=\"{}\"}} {}", name, escape(topic), value);
+        }
+    }
+    page
+}
+
+/// Escape a label value, topics may contain backslashes and quotes.
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
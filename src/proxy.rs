@@ -0,0 +1,94 @@
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use base64::Engine;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Upper bound of the proxy's response head, to not buffer a misbehaving proxy forever.
+const MAX_RESPONSE_HEAD: usize = 8192;
+
+/// HTTP proxy that tunnels broker connections with the CONNECT method.
+#[derive(Clone)]
+pub struct HttpProxy {
+    host: String,
+    port: u16,
+    /// `user:password` sent with basic authentication.
+    credentials: Option<String>,
+}
+
+impl FromStr for HttpProxy {
+    type Err = String;
+
+    /// Parse `[user:password@]host:port`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (credentials, addr) = match s.rsplit_once('@') {
+            Some((credentials, addr)) => (Some(credentials.to_string()), addr),
+            None => (None, s),
+        };
+        let (host, port) = addr
+            .rsplit_once(':')
+            .ok_or_else(|| format!("proxy {} has no port", addr))?;
+        let port = port
+            .parse()
+            .map_err(|e| format!("invalid proxy port {}: {}", port, e))?;
+        // IPv6 addresses are written in brackets like in URLs
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(format!("proxy {} has no host", addr));
+        }
+        Ok(HttpProxy {
+            host: host.to_string(),
+            port,
+            credentials,
+        })
+    }
+}
+
+impl fmt::Display for HttpProxy {
+    /// Show the proxy's address without the credentials.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+impl HttpProxy {
+    /// Open a tunnel to `broker` through the proxy.
+    pub async fn connect(&self, broker: &(String, u16)) -> Result<TcpStream, io::Error> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let target = if broker.0.contains(':') {
+            format!("[{}]:{}", broker.0, broker.1)
+        } else {
+            format!("{}:{}", broker.0, broker.1)
+        };
+        let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+        if let Some(credentials) = &self.credentials {
+            let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+            request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", encoded));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // read byte by byte, anything after the head already belongs to the tunnel
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() == MAX_RESPONSE_HEAD {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "proxy response head is too long",
+                ));
+            }
+            head.push(stream.read_u8().await?);
+        }
+        let head = String::from_utf8_lossy(&head);
+        let status = head.lines().next().unwrap_or_default();
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(stream),
+            _ => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("proxy refused the tunnel: {}", status),
+            )),
+        }
+    }
+}
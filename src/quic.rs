@@ -0,0 +1,90 @@
+use std::convert::TryFrom;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::{Context as _, Result};
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::{ClientConfig, Endpoint};
+use tokio::net::{lookup_host, TcpListener, TcpStream};
+use tokio::{select, task};
+
+/// Forwarder of broker connections over MQTT over QUIC.
+///
+/// rumqttc only speaks TCP, so like the `Forwarder` of bound connections the client connects to
+/// a loopback listener and every accepted connection is forwarded over a bidirectional stream of
+/// its own QUIC connection to the broker.
+pub struct QuicForwarder {
+    listener: TcpListener,
+    config: ClientConfig,
+    broker: (String, u16),
+}
+
+impl QuicForwarder {
+    /// Listen on a loopback port for connections to forward to `broker` with `tls`.
+    pub async fn bind(broker: (String, u16), tls: rustls::ClientConfig) -> Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let crypto = QuicClientConfig::try_from(tls).context("Invalid QUIC TLS configuration")?;
+        Ok(QuicForwarder {
+            listener,
+            config: ClientConfig::new(Arc::new(crypto)),
+            broker,
+        })
+    }
+
+    /// Get the loopback address the client should connect to.
+    pub fn addr(&self) -> Result<SocketAddr, io::Error> {
+        self.listener.local_addr()
+    }
+
+    /// Forward accepted connections until the listener fails.
+    pub async fn run(self) -> Result<(), io::Error> {
+        loop {
+            let (inbound, _) = self.listener.accept().await?;
+            let config = self.config.clone();
+            let broker = self.broker.clone();
+            task::spawn(async move {
+                if let Err(e) = forward(inbound, config, &broker).await {
+                    log::error!(
+                        "QUIC connection to {}:{} failed: {:#}",
+                        broker.0,
+                        broker.1,
+                        e
+                    );
+                }
+            });
+        }
+    }
+}
+
+/// Forward `inbound` over a QUIC stream to `broker` until either side closes.
+async fn forward(
+    mut inbound: TcpStream,
+    config: ClientConfig,
+    broker: &(String, u16),
+) -> Result<()> {
+    let addr = lookup_host((broker.0.as_str(), broker.1))
+        .await?
+        .next()
+        .with_context(|| format!("No address for {}", broker.0))?;
+    let local = if addr.is_ipv4() {
+        SocketAddr::from(([0, 0, 0, 0], 0))
+    } else {
+        SocketAddr::from(([0u16; 8], 0))
+    };
+    let mut endpoint = Endpoint::client(local)?;
+    endpoint.set_default_client_config(config);
+    let connection = endpoint.connect(addr, &broker.0)?.await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+    let (mut read, mut write) = inbound.split();
+    let closed = select! {
+        res = tokio::io::copy(&mut read, &mut send) => res,
+        res = tokio::io::copy(&mut recv, &mut write) => res,
+    };
+    connection.close(0u32.into(), b"");
+    endpoint.wait_idle().await;
+    if let Err(e) = closed {
+        log::debug!("Forwarded QUIC connection closed: {}", e);
+    }
+    Ok(())
+}
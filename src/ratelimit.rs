@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use tokio::time::{sleep_until, Instant};
+
+/// Token bucket limiting the aggregate rate of publishes.
+///
+/// The bucket holds up to a second's worth of tokens, so short bursts pass right away and longer
+/// ones are smoothed to the rate.
+#[derive(Debug)]
+pub struct RateLimit {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+    throttling: bool,
+}
+
+impl RateLimit {
+    /// Allow up to `rate` messages per second.
+    pub fn new(rate: f64) -> Self {
+        RateLimit {
+            rate,
+            tokens: rate.max(1.),
+            updated: Instant::now(),
+            throttling: false,
+        }
+    }
+
+    /// Take a token, waiting until one is available if the bucket is empty.
+    pub async fn acquire(&mut self) {
+        let now = Instant::now();
+        let capacity = self.rate.max(1.);
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(capacity);
+        self.updated = now;
+        if self.tokens >= 1. {
+            self.throttling = false;
+        } else {
+            if !self.throttling {
+                log::debug!("Throttling publishes to {} messages per second", self.rate);
+                self.throttling = true;
+            }
+            let wait = Duration::from_secs_f64((1. - self.tokens) / self.rate);
+            sleep_until(now + wait).await;
+            self.tokens = 1.;
+            self.updated = now + wait;
+        }
+        self.tokens -= 1.;
+    }
+}
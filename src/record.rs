@@ -0,0 +1,116 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context as _, Result};
+use rumqttc::{Publish, QoS, Request, Sender};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::time::{sleep_until, Instant};
+
+use crate::data::Qos;
+use crate::stats::Stats;
+
+/// A published message, stored as one JSON object per line of a recording.
+#[derive(Deserialize, Serialize)]
+struct Record {
+    /// Milliseconds since the epoch when the message was published.
+    timestamp_ms: u64,
+    topic: String,
+    qos: Qos,
+    retain: bool,
+    payload_hex: String,
+}
+
+/// Interval of flushing the buffered messages of a recording to the file.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Writer of the messages published by the simulator to a recording.
+pub struct Recorder {
+    /// Buffered writer along with the time of its last flush, the rest is flushed when dropped.
+    writer: Mutex<(BufWriter<File>, Instant)>,
+}
+
+impl Recorder {
+    /// Create the recording at `path`, truncating an existing file.
+    pub fn create(path: &str) -> Result<Self, io::Error> {
+        Ok(Recorder {
+            writer: Mutex::new((BufWriter::new(File::create(path)?), Instant::now())),
+        })
+    }
+
+    /// Append a published message, buffered messages are flushed to the file once a second.
+    pub fn record(
+        &self,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+        payload: &[u8],
+    ) -> Result<(), io::Error> {
+        let record = Record {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            topic: topic.to_string(),
+            qos: Qos(qos),
+            retain,
+            payload_hex: hex::encode(payload),
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        let mut writer = self.writer.lock().unwrap();
+        let (writer, flushed) = &mut *writer;
+        writer.write_all(&line)?;
+        if flushed.elapsed() >= FLUSH_INTERVAL {
+            *flushed = Instant::now();
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Republish the messages of the recording at `path`, preserving their relative timing.
+///
+/// The delays between the messages are divided by `speed`, so 2 replays twice as fast.
+pub async fn replay(
+    path: String,
+    speed: f64,
+    sink: Sender<Request>,
+    stats: Arc<Stats>,
+) -> Result<()> {
+    let file = fs::File::open(&path)
+        .await
+        .with_context(|| format!("Failed to open recording {}", path))?;
+    let mut lines = BufReader::new(file).lines();
+    let start = Instant::now();
+    let mut first = None;
+    let mut line_number = 0;
+    let mut replayed = 0;
+    while let Some(line) = lines.next_line().await? {
+        line_number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str::<Record>(&line)
+            .with_context(|| format!("Invalid record on line {} of {}", line_number, path))?;
+        let payload = hex::decode(&record.payload_hex)
+            .with_context(|| format!("Invalid payload on line {} of {}", line_number, path))?;
+        let first = *first.get_or_insert(record.timestamp_ms);
+        let offset = record.timestamp_ms.saturating_sub(first);
+        sleep_until(start + Duration::from_millis(offset).div_f64(speed)).await;
+        stats.record_sender_progress();
+        stats.record_publish(&record.topic, payload.len());
+        let mut msg = Publish::new(record.topic, record.qos.0, payload);
+        msg.retain = record.retain;
+        sink.send(Request::Publish(msg))
+            .await
+            .expect("Eventloop rx seems to be dead.");
+        stats.record_sender_idle();
+        replayed += 1;
+    }
+    log::info!("Replayed {} messages from {}", replayed, path);
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use anyhow::{Context as _, Result};
+use reqwest::header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Client, StatusCode};
+
+use crate::data::{self, Data, Format};
+
+/// Time a fetch of the data file may take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Data file served over HTTP, polled for changes.
+///
+/// The validators of the last response are sent along with every poll, so servers supporting
+/// them only answer with the file once it changed.
+pub struct Remote {
+    url: String,
+    poll_interval: Duration,
+    client: Client,
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+    body: Option<String>,
+}
+
+impl Remote {
+    pub fn new(url: String, poll_interval: Duration) -> Self {
+        Remote {
+            url,
+            poll_interval,
+            client: Client::new(),
+            etag: None,
+            last_modified: None,
+            body: None,
+        }
+    }
+
+    /// Get the URL of the data file.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Get the time between polls.
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    /// Fetch and parse the data file regardless of whether it changed.
+    pub async fn load(&mut self, format: Format, seed: Option<u64>) -> Result<Vec<Data>> {
+        let body = self
+            .fetch(true)
+            .await
+            .with_context(|| format!("Failed to fetch {}", self.url))?
+            .unwrap_or_default();
+        data::load(&body, format, seed).with_context(|| format!("Failed to parse {}", self.url))
+    }
+
+    /// Fetch the data file, `None` if it didn't change since the last fetch unless `force` is set.
+    pub async fn fetch(&mut self, force: bool) -> Result<Option<String>, reqwest::Error> {
+        let mut request = self.client.get(&self.url).timeout(TIMEOUT);
+        if !force {
+            if let Some(etag) = &self.etag {
+                request = request.header(IF_NONE_MATCH, etag.clone());
+            }
+            if let Some(last_modified) = &self.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified.clone());
+            }
+        }
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+        self.etag = response.headers().get(ETAG).cloned();
+        self.last_modified = response.headers().get(LAST_MODIFIED).cloned();
+        let body = response.text().await?;
+        // servers without validators send the whole file on every poll
+        if !force && self.body.as_ref() == Some(&body) {
+            return Ok(None);
+        }
+        self.body = Some(body.clone());
+        Ok(Some(body))
+    }
+}
@@ -0,0 +1,407 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value as JsonValue};
+
+use crate::data::{Data, ParseAs, Value};
+use crate::encoder::EncoderKind;
+
+/// Description of the published topics and the types of their payloads.
+#[derive(Serialize)]
+pub struct Schema {
+    topics: Vec<TopicSchema>,
+}
+
+#[derive(Serialize)]
+struct TopicSchema {
+    topic: String,
+    encoder: EncoderKind,
+    #[serde(flatten)]
+    value: JsonValue,
+}
+
+impl Schema {
+    /// Describe the entries of a data file, `encoder` applies to entries that don't override it.
+    pub fn new(data: &[Data], encoder: EncoderKind) -> Self {
+        let topics = data
+            .iter()
+            .flat_map(|entry| {
+                let encoder = entry.encoder().unwrap_or(encoder);
+                entry.topics().iter().flat_map(move |topic| {
+                    entry
+                        .values()
+                        .into_iter()
+                        .map(move |(component, value)| TopicSchema {
+                            topic: match component {
+                                Some(component) => format!("{}/{}", topic, component),
+                                None => topic.clone(),
+                            },
+                            encoder,
+                            value: describe(value),
+                        })
+                })
+            })
+            .collect();
+        Schema { topics }
+    }
+}
+
+/// Describe the type of a value without its contents.
+fn describe(value: &Value) -> JsonValue {
+    let (kind, details) = match value {
+        Value::Bool(_) => ("bool", json!({})),
+        Value::TextNumber {
+            decimals, encoding, ..
+        } => (
+            "text_number",
+            json!({ "decimals": decimals, "encoding": encoding }),
+        ),
+        Value::FixedPoint {
+            scale,
+            endian,
+            width,
+            ..
+        } => (
+            "fixed_point",
+            json!({ "scale": scale, "endian": endian, "width": width }),
+        ),
+        Value::UInt { endian, width, .. } => ("uint", json!({ "endian": endian, "width": width })),
+        Value::Int { endian, width, .. } => ("int", json!({ "endian": endian, "width": width })),
+        Value::Float { endian, width, .. } => {
+            ("float", json!({ "endian": endian, "width": width }))
+        }
+        Value::Enum {
+            variants,
+            endian,
+            width,
+            ..
+        } => (
+            "enum",
+            json!({ "variants": variants, "endian": endian, "width": width }),
+        ),
+        Value::String {
+            encoding, framing, ..
+        } => (
+            "string",
+            json!({ "encoding": encoding, "framing": framing }),
+        ),
+        Value::Bytes { bytes } => ("bytes", json!({ "length": bytes.len() })),
+        Value::Array(values) => (
+            "array",
+            json!({ "items": values.iter().map(describe).collect::<Vec<_>>() }),
+        ),
+        Value::PrefixedArray {
+            items,
+            prefix,
+            endian,
+        } => (
+            "array",
+            json!({
+                "items": items.iter().map(describe).collect::<Vec<_>>(),
+                "prefix": prefix,
+                "endian": endian,
+            }),
+        ),
+        Value::Uptime {
+            unit,
+            endian,
+            width,
+        } => (
+            "uptime",
+            json!({ "unit": unit, "endian": endian, "width": width }),
+        ),
+        Value::Index { index } => (
+            "index",
+            json!({ "endian": index.endian, "width": index.width }),
+        ),
+        Value::ReconnectCount { reconnects } => (
+            "reconnect_count",
+            json!({ "endian": reconnects.endian, "width": reconnects.width }),
+        ),
+        Value::ReloadCount { reloads } => (
+            "reload_count",
+            json!({ "endian": reloads.endian, "width": reloads.width }),
+        ),
+        Value::ConnectionState { connection_state } => (
+            "connection_state",
+            json!({
+                "endian": connection_state.endian,
+                "width": connection_state.width,
+                "mapping": connection_state.mapping,
+            }),
+        ),
+        Value::ModbusRegisters {
+            registers,
+            word_order,
+        } => (
+            "modbus_registers",
+            json!({ "registers": registers.len(), "word_order": word_order }),
+        ),
+        Value::Samples {
+            kind,
+            values,
+            endian,
+            width,
+        } => (
+            "samples",
+            json!({ "kind": kind, "count": values.len(), "endian": endian, "width": width }),
+        ),
+        Value::MonotonicTime {
+            min_gap_ms,
+            max_gap_ms,
+            endian,
+            width,
+            ..
+        } => (
+            "monotonic_time",
+            json!({
+                "min_gap_ms": min_gap_ms,
+                "max_gap_ms": max_gap_ms,
+                "endian": endian,
+                "width": width,
+            }),
+        ),
+        Value::MovingAverage {
+            source,
+            window,
+            endian,
+            width,
+        } => (
+            "moving_average",
+            json!({ "source": source, "window": window, "endian": endian, "width": width }),
+        ),
+        Value::RandomWalk {
+            start,
+            step_stddev,
+            min,
+            max,
+            endian,
+            width,
+            ..
+        } => (
+            "random_walk",
+            json!({
+                "start": start,
+                "step_stddev": step_stddev,
+                "min": min,
+                "max": max,
+                "endian": endian,
+                "width": width
+            }),
+        ),
+        Value::RandomInt {
+            min,
+            max,
+            endian,
+            width,
+        } => (
+            "random_int",
+            json!({ "min": min, "max": max, "endian": endian, "width": width }),
+        ),
+        Value::RandomFloat {
+            min,
+            max,
+            endian,
+            width,
+        } => (
+            "random_float",
+            json!({ "min": min, "max": max, "endian": endian, "width": width }),
+        ),
+        Value::RandomBytes { length, .. } => ("random_bytes", json!({ "length": length })),
+        Value::Uuid {
+            version,
+            format,
+            endian,
+        } => (
+            "uuid",
+            json!({ "version": version, "format": format, "endian": endian }),
+        ),
+        Value::Timestamp {
+            format,
+            endian,
+            width,
+        } => (
+            "timestamp",
+            json!({ "format": format, "endian": endian, "width": width }),
+        ),
+        Value::DateTime {
+            format,
+            timezone,
+            encoding,
+            framing,
+        } => (
+            "date_time",
+            json!({
+                "format": format,
+                "timezone": timezone,
+                "encoding": encoding,
+                "framing": framing
+            }),
+        ),
+        Value::Stats { stats } => (
+            "stats",
+            json!({ "source": stats.source, "window": stats.window }),
+        ),
+        Value::Counter {
+            start,
+            step,
+            endian,
+            width,
+            ..
+        } => (
+            "counter",
+            json!({ "start": start, "step": step, "endian": endian, "width": width }),
+        ),
+        Value::Waveform {
+            shape,
+            period_ms,
+            endian,
+            width,
+            ..
+        } => (
+            "waveform",
+            json!({ "shape": shape, "period_ms": period_ms, "endian": endian, "width": width }),
+        ),
+        Value::DutyCycle { on_ms, off_ms } => {
+            ("duty_cycle", json!({ "on_ms": on_ms, "off_ms": off_ms }))
+        }
+        Value::LineProtocol {
+            measurement,
+            tags,
+            fields,
+            timestamp,
+            encoding,
+            ..
+        } => (
+            "line_protocol",
+            json!({
+                "measurement": measurement,
+                "tags": tags.keys().collect::<Vec<_>>(),
+                "fields": fields
+                    .iter()
+                    .map(|(key, value)| (key.clone(), describe(value)))
+                    .collect::<Map<_, _>>(),
+                "timestamp": timestamp,
+                "encoding": encoding,
+            }),
+        ),
+        Value::Phased { phases, .. } => (
+            "phased",
+            json!({
+                "phases": phases
+                    .iter()
+                    .map(|phase| {
+                        let mut value = describe(&phase.value);
+                        value["count"] = json!(phase.count);
+                        value
+                    })
+                    .collect::<Vec<_>>(),
+            }),
+        ),
+        Value::Flags {
+            mapping,
+            bit_order,
+            endian,
+            width,
+            ..
+        } => (
+            "flags",
+            json!({
+                "mapping": mapping,
+                "bit_order": bit_order,
+                "endian": endian,
+                "width": width,
+            }),
+        ),
+        Value::Bits {
+            fields,
+            endian,
+            width,
+        } => (
+            "bits",
+            json!({
+                "fields": fields
+                    .iter()
+                    .map(|field| json!({ "bit_offset": field.bit_offset, "bits": field.bits }))
+                    .collect::<Vec<_>>(),
+                "endian": endian,
+                "width": width,
+            }),
+        ),
+        #[cfg(feature = "http")]
+        Value::Http(source) => ("http", json!({ "parse_as": parsed(source.parse_as()) })),
+        Value::SysFile(file) => ("sysfile", json!({ "parse_as": parsed(file.parse_as()) })),
+        #[cfg(feature = "sqlite")]
+        Value::Sqlite(source) => ("sqlite", json!({ "parse_as": parsed(source.parse_as()) })),
+        Value::File { path, bytes } => (
+            "file",
+            json!({ "path": path, "size": bytes.get().map(Vec::len) }),
+        ),
+        Value::Expr(expr) => ("expr", json!({ "output": parsed(expr.output()) })),
+        Value::Frame {
+            fields,
+            checksum,
+            align,
+        } => (
+            "frame",
+            json!({
+                "fields": fields.iter().map(describe).collect::<Vec<_>>(),
+                "checksum": checksum.algorithm,
+                "align": align,
+            }),
+        ),
+        Value::PackedStruct { size, fields } => (
+            "packed_struct",
+            json!({
+                "size": size,
+                "fields": fields
+                    .iter()
+                    .map(|field| {
+                        let mut value = describe(&field.value);
+                        value["offset"] = json!(field.offset);
+                        value
+                    })
+                    .collect::<Vec<_>>(),
+            }),
+        ),
+        // the inner value is not described to avoid hinting at the plaintext
+        Value::Encrypted(encrypted) => ("encrypted", json!({ "algorithm": encrypted.algorithm() })),
+        Value::Signed(signed) => (
+            "signed",
+            json!({ "algorithm": signed.algorithm(), "inner": describe(signed.inner()) }),
+        ),
+        Value::Anomalous(anomalous) => {
+            let (spike_probability, dropout_probability) = anomalous.probabilities();
+            (
+                "anomalous",
+                json!({
+                    "base": describe(anomalous.base()),
+                    "spike_probability": spike_probability,
+                    "dropout_probability": dropout_probability,
+                }),
+            )
+        }
+        Value::Tail(tail) => ("tail", json!({ "encoding": tail.encoding() })),
+        Value::Cbor { .. } => ("cbor", json!({})),
+        Value::MsgPack { .. } => ("msgpack", json!({})),
+        Value::EncodedJSON {
+            encoding, pretty, ..
+        } => ("json", json!({ "encoding": encoding, "pretty": pretty })),
+        Value::JSON(_) => ("json", json!({})),
+    };
+    let mut schema = Map::new();
+    schema.insert("type".to_string(), json!(kind));
+    if let JsonValue::Object(details) = details {
+        schema.extend(details);
+    }
+    JsonValue::Object(schema)
+}
+
+/// Get the type name of a value parsed from text.
+fn parsed(parse_as: ParseAs) -> &'static str {
+    match parse_as {
+        ParseAs::Bool => "bool",
+        ParseAs::Int => "int",
+        ParseAs::UInt => "uint",
+        ParseAs::Float => "float",
+        ParseAs::String => "string",
+    }
+}
@@ -0,0 +1,969 @@
+use std::collections::HashMap;
+use std::future;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, Context as _, Result};
+use rand::{rngs::StdRng, SeedableRng};
+use rumqttc::{EventLoop, MqttOptions, Transport};
+use tokio::net::TcpListener;
+use tokio::select;
+use tokio::sync::{mpsc, watch};
+use tokio::task::{self, JoinHandle, JoinSet};
+use tokio::time::interval;
+
+use crate::admin::Admin;
+use crate::backoff::Backoff;
+use crate::bench::Bench;
+use crate::chaos::Chaos;
+use crate::data::{self, Context, Data};
+use crate::encoder::EncoderKind;
+use crate::engine::{
+    discard_requests, eventloop_task, fan_out, pinger, schema_publisher, sender, with_broker,
+    Backpressure, Handlers, Queue, SenderConfig, Sessions, Shutdown, MAX_PAYLOAD_BYTES,
+    REQUEST_CAPACITY,
+};
+use crate::history::History;
+use crate::metrics::{write_metrics, MetricsCsv};
+use crate::ping::Ping;
+use crate::prometheus;
+use crate::ratelimit::RateLimit;
+use crate::record::{self, Recorder};
+use crate::stats::Stats;
+use crate::tls::{rotate_certificates, TlsFiles};
+use crate::watchdog::Watchdog;
+use crate::watcher::{data_watcher, publish_status, Source, WatcherConfig};
+
+/// Builder of a simulator publishing data entries to an MQTT broker.
+///
+/// Entries are published every `interval` unless they set their own, with the same semantics as
+/// the entries of a data file. Every other setting matches the option of the command line tool
+/// with the same name and is off unless it is set.
+pub struct Simulator {
+    opts: MqttOptions,
+    data: Vec<Data>,
+    stats: Arc<Stats>,
+    /// Name of the broker of `opts`, its host unless it's given.
+    broker_name: Option<String>,
+    /// Brokers receiving a copy of every publish, in addition to the one of `opts`, by name.
+    mirrors: Vec<(String, String, u16)>,
+    interval: Duration,
+    jitter: Duration,
+    send_delay: Duration,
+    value_ttl: Option<Duration>,
+    encoder: EncoderKind,
+    seed: Option<u64>,
+    utf16_bom: bool,
+    warn_precision_loss: bool,
+    drop_rate: f64,
+    shuffle: bool,
+    stagger: bool,
+    dedup: bool,
+    max_packet_size: Option<usize>,
+    max_payload_bytes: usize,
+    strict_payload_size: bool,
+    rate_limit: Option<f64>,
+    backpressure: Backpressure,
+    request_capacity: usize,
+    backoff: Backoff,
+    max_reconnect_attempts: Option<usize>,
+    tls_files: Option<TlsFiles>,
+    drain_timeout: Duration,
+    chaos: Chaos,
+    history: Option<(String, usize)>,
+    recording: Option<Recorder>,
+    echo: Option<(Vec<String>, String)>,
+    control_topic: Option<String>,
+    standby: bool,
+    ping: Option<(String, Duration)>,
+    schema: Option<(String, Option<String>)>,
+    source: Option<Source>,
+    error_topic: Option<String>,
+    error_threshold: usize,
+    status_topic: Option<String>,
+    freshness_timeout: Option<Duration>,
+    every_revision: bool,
+    replay: Option<(String, f64)>,
+    bench: Option<u64>,
+    admin: Option<TcpListener>,
+    prometheus: Option<TcpListener>,
+    stall_timeout: Option<(Duration, bool)>,
+    metrics: Option<(MetricsCsv, Duration)>,
+    dry_run: bool,
+    once: bool,
+    summary: bool,
+}
+
+impl Simulator {
+    /// Create a simulator connecting with `opts` and publishing `data` every second.
+    pub fn new(opts: MqttOptions, data: Vec<Data>) -> Self {
+        Simulator {
+            opts,
+            data,
+            stats: Arc::new(Stats::default()),
+            broker_name: None,
+            mirrors: Vec::new(),
+            interval: Duration::from_secs(1),
+            jitter: Duration::ZERO,
+            send_delay: Duration::ZERO,
+            value_ttl: None,
+            encoder: EncoderKind::Binary,
+            seed: None,
+            utf16_bom: true,
+            warn_precision_loss: false,
+            drop_rate: 0.,
+            shuffle: false,
+            stagger: false,
+            dedup: false,
+            max_packet_size: None,
+            max_payload_bytes: MAX_PAYLOAD_BYTES,
+            strict_payload_size: false,
+            rate_limit: None,
+            backpressure: Backpressure::Block,
+            request_capacity: REQUEST_CAPACITY,
+            backoff: Backoff::new(Duration::from_secs(1), Duration::from_secs(60)),
+            max_reconnect_attempts: None,
+            tls_files: None,
+            drain_timeout: Duration::from_secs(5),
+            chaos: Chaos::default(),
+            history: None,
+            recording: None,
+            echo: None,
+            control_topic: None,
+            standby: false,
+            ping: None,
+            schema: None,
+            source: None,
+            error_topic: None,
+            error_threshold: 1,
+            status_topic: None,
+            freshness_timeout: None,
+            every_revision: false,
+            replay: None,
+            bench: None,
+            admin: None,
+            prometheus: None,
+            stall_timeout: None,
+            metrics: None,
+            dry_run: false,
+            once: false,
+            summary: false,
+        }
+    }
+
+    /// Also publish everything to the broker at `host` and `port`, with the same options.
+    ///
+    /// The first broker sets the pace, the others drop publishes while they fall behind.
+    pub fn mirror(mut self, host: String, port: u16) -> Self {
+        self.mirrors.push((host.clone(), host, port));
+        self
+    }
+
+    /// Mirror the broker at `host` and `port` like [`mirror`](Self::mirror) under another name
+    /// than its host.
+    pub fn named_mirror(mut self, name: String, host: String, port: u16) -> Self {
+        self.mirrors.push((name, host, port));
+        self
+    }
+
+    /// Name the broker of the options for the `broker` of entries, rather than after its host.
+    ///
+    /// Entries naming a broker are only published to the first broker of that name, the others
+    /// to every broker.
+    pub fn broker_name(mut self, name: String) -> Self {
+        self.broker_name = Some(name);
+        self
+    }
+
+    /// Set the time between publishes of entries without their own interval.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set the maximum random deviation from the interval of entries without their own.
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Delay every publish by this long after its payload was generated.
+    pub fn send_delay(mut self, send_delay: Duration) -> Self {
+        self.send_delay = send_delay;
+        self
+    }
+
+    /// Drop publishes that couldn't be handed to the event loop within this long.
+    pub fn value_ttl(mut self, value_ttl: Duration) -> Self {
+        self.value_ttl = Some(value_ttl);
+        self
+    }
+
+    /// Set the encoder of entries that don't choose their own.
+    pub fn encoder(mut self, encoder: EncoderKind) -> Self {
+        self.encoder = encoder;
+        self
+    }
+
+    /// Seed the random values, like `--seed`, to publish the same sequences on every run.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Set whether UTF16 strings without their own setting start with a BOM.
+    pub fn utf16_bom(mut self, utf16_bom: bool) -> Self {
+        self.utf16_bom = utf16_bom;
+        self
+    }
+
+    /// Set whether narrowing a float to 32 bits warns if it loses precision.
+    pub fn warn_precision_loss(mut self, warn: bool) -> Self {
+        self.warn_precision_loss = warn;
+        self
+    }
+
+    /// Set the fraction of publishes to drop for entries without their own drop rate.
+    pub fn drop_rate(mut self, drop_rate: f64) -> Self {
+        self.drop_rate = drop_rate;
+        self
+    }
+
+    /// Set whether the entries are published in a random order on every tick.
+    pub fn shuffle(mut self, shuffle: bool) -> Self {
+        self.shuffle = shuffle;
+        self
+    }
+
+    /// Set whether the first publishes of entries without a phase are spread over their interval.
+    pub fn stagger(mut self, stagger: bool) -> Self {
+        self.stagger = stagger;
+        self
+    }
+
+    /// Set whether identical consecutive payloads are skipped for entries without their own setting.
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Skip publishes whose packet would exceed this many bytes.
+    pub fn max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size = Some(max_packet_size);
+        self
+    }
+
+    /// Set the maximum size of a payload.
+    pub fn max_payload_bytes(mut self, max_payload_bytes: usize) -> Self {
+        self.max_payload_bytes = max_payload_bytes;
+        self
+    }
+
+    /// Set whether an oversized payload is skipped instead of only warned about.
+    pub fn strict_payload_size(mut self, strict: bool) -> Self {
+        self.strict_payload_size = strict;
+        self
+    }
+
+    /// Limit the publishes to this many messages per second.
+    pub fn rate_limit(mut self, rate: f64) -> Self {
+        self.rate_limit = Some(rate);
+        self
+    }
+
+    /// Set how publishes are handled while the queue of the event loop is full.
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Set the number of requests queued for an event loop.
+    pub fn request_capacity(mut self, capacity: usize) -> Self {
+        self.request_capacity = capacity;
+        self
+    }
+
+    /// Set the minimum and maximum delay between reconnection attempts.
+    pub fn reconnect_backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.backoff = Backoff::new(min, max);
+        self
+    }
+
+    /// Fail after this many consecutive failed connection attempts instead of retrying forever.
+    pub fn max_reconnect_attempts(mut self, attempts: usize) -> Self {
+        self.max_reconnect_attempts = Some(attempts);
+        self
+    }
+
+    /// Reconnect with the new certificates once `ca_cert` or the client certificate and key of
+    /// the TLS transport in the options change.
+    pub fn reload_tls(mut self, ca_cert: Option<&str>, client_auth: Option<(&str, &str)>) -> Self {
+        self.tls_files = Some(TlsFiles {
+            ca_cert: ca_cert.map(str::to_string),
+            client_auth: client_auth.map(|(cert, key)| (cert.to_string(), key.to_string())),
+        });
+        self
+    }
+
+    /// Set the maximum time a shutdown waits for the broker to acknowledge pending publishes.
+    pub fn drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// Inject the faults of `chaos` into the publishes and the connection.
+    ///
+    /// Chaos drops apply to entries without their own drop rate, on top of the drop rate.
+    pub fn chaos(mut self, chaos: Chaos) -> Self {
+        self.chaos = chaos;
+        self
+    }
+
+    /// Keep the last `size` payloads of every topic and publish them on requests to `topic`.
+    pub fn history(mut self, topic: String, size: usize) -> Self {
+        self.history = Some((topic, size));
+        self
+    }
+
+    /// Record every published data message to the file at `path`.
+    pub fn record(mut self, path: &str) -> Result<Self, io::Error> {
+        self.recording = Some(Recorder::create(path)?);
+        Ok(self)
+    }
+
+    /// Subscribe to `filters` and republish their messages under `prefix`.
+    pub fn echo(mut self, filters: Vec<String>, prefix: String) -> Self {
+        self.echo = Some((filters, prefix));
+        self
+    }
+
+    /// Pause and resume the publishes on messages to `topic`.
+    pub fn control_topic(mut self, topic: String) -> Self {
+        self.control_topic = Some(topic);
+        self
+    }
+
+    /// Set whether the simulator starts paused until it is resumed.
+    pub fn standby(mut self, standby: bool) -> Self {
+        self.standby = standby;
+        self
+    }
+
+    /// Publish a ping to `topic` every `interval` and measure the round trip.
+    pub fn ping(mut self, topic: String, interval: Duration) -> Self {
+        self.ping = Some((topic, interval));
+        self
+    }
+
+    /// Publish the schema of the data, or a fixed `payload`, to `topic` after every connect.
+    pub fn schema(mut self, topic: String, payload: Option<String>) -> Self {
+        self.schema = Some((topic, payload));
+        self
+    }
+
+    /// Reload the data from `source` whenever it changes.
+    ///
+    /// The data passed to `new` is the one loaded from the source beforehand.
+    pub fn watch(mut self, source: Source) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Publish the errors of reloaded data to `topic`.
+    pub fn error_topic(mut self, topic: String) -> Self {
+        self.error_topic = Some(topic);
+        self
+    }
+
+    /// Set the failed attempts to load a revision of the data before it is reported.
+    pub fn error_threshold(mut self, threshold: usize) -> Self {
+        self.error_threshold = threshold;
+        self
+    }
+
+    /// Publish the retained status of the data to `topic` on startup and after every reload.
+    pub fn status_topic(mut self, topic: String) -> Self {
+        self.status_topic = Some(topic);
+        self
+    }
+
+    /// Stop publishing while the watched data wasn't modified for this long.
+    pub fn freshness_timeout(mut self, timeout: Duration) -> Self {
+        self.freshness_timeout = Some(timeout);
+        self
+    }
+
+    /// Set whether every reloaded revision is published at least once.
+    pub fn publish_every_revision(mut self, every_revision: bool) -> Self {
+        self.every_revision = every_revision;
+        self
+    }
+
+    /// Republish the recording at `path` sped up by `speed` instead of the data.
+    pub fn replay(mut self, path: String, speed: f64) -> Self {
+        self.replay = Some((path, speed));
+        self
+    }
+
+    /// Publish `count` messages as fast as possible, report the throughput and stop.
+    pub fn bench(mut self, count: u64) -> Self {
+        self.bench = Some(count);
+        self
+    }
+
+    /// Serve the HTTP admin API on `listener`.
+    pub fn admin(mut self, listener: TcpListener) -> Self {
+        self.admin = Some(listener);
+        self
+    }
+
+    /// Serve Prometheus metrics on `listener`.
+    pub fn prometheus(mut self, listener: TcpListener) -> Self {
+        self.prometheus = Some(listener);
+        self
+    }
+
+    /// Report a stall once publishing makes no progress for `timeout`, failing if `exit` is set.
+    pub fn stall_timeout(mut self, timeout: Duration, exit: bool) -> Self {
+        self.stall_timeout = Some((timeout, exit));
+        self
+    }
+
+    /// Write the counters to the CSV file at `path` every `interval`.
+    pub fn metrics_csv(mut self, path: &str, interval: Duration) -> Result<Self, io::Error> {
+        let metrics = MetricsCsv::open(path, Arc::clone(&self.stats))?;
+        self.metrics = Some((metrics, interval));
+        Ok(self)
+    }
+
+    /// Set whether the payloads are printed instead of connecting to the broker.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Set whether every entry is published once before the simulator stops.
+    pub fn once(mut self, once: bool) -> Self {
+        self.once = once;
+        self
+    }
+
+    /// Set whether a summary of the activity is logged once the simulator stopped.
+    pub fn summary(mut self, summary: bool) -> Self {
+        self.summary = summary;
+        self
+    }
+
+    /// Start publishing until the returned handle shuts the simulator down.
+    ///
+    /// Must be called within a tokio runtime.
+    pub fn run(self) -> SimulatorHandle {
+        let start = Instant::now();
+        let stats = self.stats;
+        let context = Context {
+            start,
+            stats: Arc::clone(&stats),
+            utf16_bom: self.utf16_bom,
+            index: 0,
+            topic: Arc::from(""),
+            averages: Default::default(),
+            warn_precision_loss: self.warn_precision_loss,
+            seed: self.seed,
+            entry_rng: Default::default(),
+            constants: Default::default(),
+        };
+        let drop_rate = combined_drop_rate(self.drop_rate, self.chaos.drop);
+        let rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let (drain_tx, drain_rx) = watch::channel(false);
+        let shutdown = Shutdown {
+            signal: drain_rx,
+            drain_timeout: self.drain_timeout,
+        };
+        let mut sessions = Sessions::new(
+            self.opts.clone(),
+            self.request_capacity,
+            self.backoff,
+            Arc::clone(&stats),
+            shutdown.clone(),
+        );
+        // helpers that end along with the simulator
+        let mut tasks = JoinSet::new();
+        // every session and broker reconnects once the certificates are rotated
+        let transports = match self.tls_files {
+            Some(files) if !self.dry_run && is_tls(&self.opts.transport()) => {
+                let (transports_tx, transports) = watch::channel(self.opts.transport());
+                tasks.spawn(rotate_certificates(files, transports_tx));
+                sessions = sessions.reload_transport(transports.clone());
+                Some(transports)
+            }
+            _ => None,
+        };
+        // every broker after the first one gets a copy of the requests
+        let mut brokers = JoinSet::new();
+        // queue of every broker by name, for the entries published to a single one
+        let mut routes = HashMap::new();
+        let (host, port) = self.opts.broker_address();
+        let broker_name = self.broker_name.unwrap_or_else(|| host.clone());
+        let (eventloop, queue) = if self.dry_run {
+            let (sink, requests) = async_channel::bounded(self.request_capacity);
+            (Err(requests.clone()), Queue { sink, requests })
+        } else if self.mirrors.is_empty() {
+            let eventloop = EventLoop::new(self.opts, self.request_capacity);
+            let queue = Queue::of(&eventloop);
+            routes.insert(broker_name, queue.clone());
+            (Ok(eventloop), queue)
+        } else {
+            let mut sinks = Vec::new();
+            for (name, host, port) in self.mirrors {
+                let opts = with_broker(&self.opts, host.clone(), port);
+                let eventloop = EventLoop::new(opts, self.request_capacity);
+                sinks.push((format!("{}:{}", host, port), eventloop.handle()));
+                routes.entry(name).or_insert_with(|| Queue::of(&eventloop));
+                let handlers = Handlers {
+                    transports: transports.clone(),
+                    ..Handlers::default()
+                };
+                brokers.spawn(eventloop_task(
+                    eventloop,
+                    handlers,
+                    self.backoff,
+                    self.max_reconnect_attempts,
+                    Arc::clone(&stats),
+                    shutdown.clone(),
+                ));
+            }
+            let eventloop = EventLoop::new(self.opts, self.request_capacity);
+            sinks.insert(0, (format!("{}:{}", host, port), eventloop.handle()));
+            // the first broker takes precedence over mirrors of the same name
+            routes.insert(broker_name, Queue::of(&eventloop));
+            let (sink, requests) = async_channel::bounded(self.request_capacity);
+            tasks.spawn(fan_out(requests.clone(), sinks));
+            (Ok(eventloop), Queue { sink, requests })
+        };
+        let requests_tx = queue.sink.clone();
+        if self.replay.is_none() {
+            log::info!("Loaded values:\n{:#?}", self.data);
+        }
+        let (data_tx, data_rx) = watch::channel(self.data);
+        let loaded = self
+            .source
+            .as_ref()
+            .map_or_else(SystemTime::now, Source::loaded);
+        let (mtime_tx, mtime_rx) = watch::channel(loaded);
+        let (reload_tx, reload_rx) = watch::channel(());
+        let (revisions_tx, revisions_rx) = if self.every_revision {
+            let (tx, rx) = mpsc::unbounded_channel();
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+        let (active_tx, active_rx) = watch::channel(!self.standby);
+
+        if let Some(listener) = self.admin {
+            let admin = Admin::new(
+                start,
+                Arc::clone(&stats),
+                data_rx.clone(),
+                active_tx.clone(),
+                reload_tx,
+            );
+            tasks.spawn(async move {
+                if let Err(e) = admin.run(listener).await {
+                    log::error!("Admin API failed: {}", e);
+                }
+            });
+        }
+
+        let mut prometheus = match self.prometheus {
+            Some(listener) => task::spawn(prometheus::serve(listener, Arc::clone(&stats))),
+            None => task::spawn(future::pending()),
+        };
+
+        let mut watchdog = match self.stall_timeout {
+            // dry runs neither count publishes nor poll an event loop
+            Some(_) if self.dry_run => {
+                log::warn!("--stall-timeout has no effect in a dry run");
+                task::spawn(future::pending())
+            }
+            Some((timeout, exit)) => {
+                task::spawn(Watchdog::new(Arc::clone(&stats), timeout, exit).run())
+            }
+            None => task::spawn(future::pending()),
+        };
+
+        let data = data_tx.clone();
+        // a replay doesn't publish the data, so there's nothing to watch
+        let mut watcher = match self.source {
+            Some(source) if self.replay.is_none() => {
+                let sink = requests_tx.clone();
+                let config = WatcherConfig {
+                    error_topic: self.error_topic,
+                    status_topic: self.status_topic,
+                    error_threshold: self.error_threshold,
+                    reload: reload_rx,
+                    loaded,
+                    revisions: revisions_tx,
+                    stats: Arc::clone(&stats),
+                };
+                task::spawn(async move {
+                    if let Some(topic) = &config.status_topic {
+                        let entries = data_tx.borrow().len();
+                        publish_status(&sink, topic, source.label(), entries).await?;
+                    }
+                    data_watcher(source, data_tx, mtime_tx, sink, config).await
+                })
+            }
+            _ => task::spawn(future::pending()),
+        };
+
+        let (connects_tx, connects_rx) = watch::channel(0);
+        if let Some((topic, payload)) = self.schema {
+            let schema = schema_publisher(
+                topic,
+                payload,
+                self.encoder,
+                data_rx.clone(),
+                connects_rx.clone(),
+                requests_tx.clone(),
+            );
+            // stops once the event loop doesn't count connects anymore
+            tasks.spawn(async move {
+                schema.await.ok();
+            });
+        }
+        let history = self
+            .history
+            .map(|(topic, size)| (topic, Arc::new(History::new(size))));
+        let recorder = history.as_ref().map(|(_, history)| Arc::clone(history));
+        let ping = self
+            .ping
+            .map(|(topic, interval)| (Arc::new(Ping::new(topic)), interval));
+        if let Some((ping, period)) = &ping {
+            let ticks = interval(*period);
+            let pinger = pinger(Arc::clone(ping), requests_tx.clone(), ticks);
+            tasks.spawn(async move {
+                pinger.await.ok();
+            });
+        }
+        let metrics = self.metrics.map(|(metrics, period)| {
+            let metrics = Arc::new(Mutex::new(metrics));
+            tasks.spawn(write_metrics(Arc::clone(&metrics), period));
+            metrics
+        });
+        let handlers = Handlers {
+            history,
+            ping: ping.map(|(ping, _)| ping),
+            control: self.control_topic.map(|topic| (topic, active_tx)),
+            connects: Some(connects_tx),
+            echo: self.echo,
+            reports_state: true,
+            transports,
+        };
+        let mut eventloop = match eventloop {
+            Ok(eventloop) => task::spawn(eventloop_task(
+                eventloop,
+                handlers,
+                self.backoff,
+                self.max_reconnect_attempts,
+                Arc::clone(&stats),
+                shutdown,
+            )),
+            Err(requests_rx) => task::spawn(discard_requests(requests_rx, shutdown)),
+        };
+
+        let replaying = self.replay.is_some();
+        let once = self.once;
+        let bench = self.bench.map(|count| Arc::new(Bench::new(count)));
+        let mut publisher = match (self.replay, &bench) {
+            (Some((path, speed)), _) => {
+                log::info!("Replaying {}", path);
+                task::spawn(record::replay(path, speed, requests_tx, Arc::clone(&stats)))
+            }
+            (None, Some(bench)) => {
+                let data = data_rx.borrow().clone();
+                task::spawn(Arc::clone(bench).run(
+                    data,
+                    self.encoder,
+                    context,
+                    requests_tx,
+                    connects_rx,
+                ))
+            }
+            (None, None) => {
+                let config = SenderConfig {
+                    interval: self.interval,
+                    jitter: self.jitter,
+                    send_delay: self.send_delay,
+                    value_ttl: self.value_ttl,
+                    encoder: self.encoder,
+                    drop_rate,
+                    shuffle: self.shuffle,
+                    stagger: self.stagger,
+                    dedup: self.dedup,
+                    max_packet_size: self.max_packet_size,
+                    max_payload_bytes: self.max_payload_bytes,
+                    strict_payload_size: self.strict_payload_size,
+                    rng,
+                    history: recorder,
+                    recording: self.recording,
+                    sessions,
+                    brokers: routes,
+                    freshness: self.freshness_timeout.map(|timeout| (timeout, mtime_rx)),
+                    active: active_rx,
+                    revisions: revisions_rx,
+                    dry_run: self.dry_run,
+                    once,
+                    chaos: self.chaos,
+                    rate_limit: self.rate_limit.map(RateLimit::new),
+                    backpressure: self.backpressure,
+                    context,
+                };
+                task::spawn(sender(data_rx, queue, config))
+            }
+        };
+
+        let summary = self.summary;
+        let (shutdown_tx, mut requested) = watch::channel(false);
+        let task_stats = Arc::clone(&stats);
+        let task = task::spawn(async move {
+            let graceful = select! {
+                res = &mut watcher => Err(anyhow!("Watcher died: {:?}", res)),
+                res = &mut publisher => match res {
+                    Ok(Ok(())) if replaying => {
+                        log::info!("Replay finished, shutting down");
+                        Ok(())
+                    }
+                    Ok(Ok(())) if once => {
+                        log::info!("Published every entry once, shutting down");
+                        Ok(())
+                    }
+                    Ok(Ok(())) if bench.is_some() => {
+                        log::info!(
+                            "Benchmark messages handed to the event loop, waiting for the broker"
+                        );
+                        Ok(())
+                    }
+                    res => Err(anyhow!("Sender died: {:?}", res)),
+                },
+                res = &mut eventloop => match res {
+                    Ok(Err(e)) => Err(e),
+                    res => Err(anyhow!("Eventloop died: {:?}", res)),
+                },
+                Some(res) = brokers.join_next() => match res {
+                    Ok(Err(e)) => Err(e),
+                    res => Err(anyhow!("Eventloop died: {:?}", res)),
+                },
+                res = &mut watchdog => match res {
+                    Ok(Err(e)) => Err(e),
+                    res => Err(anyhow!("Watchdog died: {:?}", res)),
+                },
+                res = &mut prometheus => match res {
+                    Ok(Err(e)) => Err(e).context("Serving Prometheus metrics failed"),
+                    res => Err(anyhow!("Metrics server died: {:?}", res)),
+                },
+                _ = requested.wait_for(|requested| *requested) => Ok(()),
+            };
+            watcher.abort();
+            watchdog.abort();
+            prometheus.abort();
+            let res = match graceful {
+                Ok(()) => {
+                    publisher.abort();
+                    drain_tx.send_replace(true);
+                    let mut res = eventloop
+                        .await
+                        .unwrap_or_else(|e| Err(anyhow!("Eventloop died: {:?}", e)));
+                    // the other brokers drain as well, the first error is reported
+                    while let Some(drained) = brokers.join_next().await {
+                        let drained =
+                            drained.unwrap_or_else(|e| Err(anyhow!("Eventloop died: {:?}", e)));
+                        res = res.and(drained);
+                    }
+                    res
+                }
+                Err(e) => {
+                    publisher.abort();
+                    eventloop.abort();
+                    Err(e)
+                }
+            };
+            tasks.abort_all();
+            if let (Ok(()), Some(bench)) = (&res, &bench) {
+                bench.report();
+            }
+            if let Some(metrics) = metrics {
+                // a last row with the counters after the drain
+                if let Err(e) = metrics.lock().unwrap().write_row() {
+                    log::warn!("Failed to write metrics: {}", e);
+                }
+            }
+            if summary {
+                task_stats.log_summary(start.elapsed());
+            }
+            res
+        });
+        SimulatorHandle {
+            data,
+            shutdown: shutdown_tx,
+            stats,
+            task,
+        }
+    }
+
+    /// Start publishing until `signal` completes, e.g. `tokio::signal::ctrl_c()`.
+    ///
+    /// Must be called within a tokio runtime.
+    pub fn run_with_shutdown<F>(self, signal: F) -> SimulatorHandle
+    where
+        F: std::future::Future + Send + 'static,
+    {
+        let handle = self.run();
+        let shutdown = handle.shutdown.clone();
+        task::spawn(async move {
+            signal.await;
+            shutdown.send_replace(true);
+        });
+        handle
+    }
+}
+
+/// Check whether `transport` is secured with certificates that can be rotated.
+fn is_tls(transport: &Transport) -> bool {
+    match transport {
+        Transport::Tls(_) => true,
+        #[cfg(feature = "websocket")]
+        Transport::Wss(_) => true,
+        _ => false,
+    }
+}
+
+/// Get the fraction of publishes dropped by either the drop rate or the chaos drops.
+///
+/// A publish kept by one of them can still be dropped by the other one.
+fn combined_drop_rate(rate: f64, chaos: f64) -> f64 {
+    if chaos <= 0. {
+        return rate;
+    }
+    if rate <= 0. {
+        return chaos;
+    }
+    let combined = 1. - (1. - rate) * (1. - chaos);
+    log::info!(
+        "Dropping {:.2}% of the publishes, the drop rate {} combined with the chaos drops {}",
+        combined * 100.,
+        rate,
+        chaos
+    );
+    combined
+}
+
+/// Handle of a running simulator.
+pub struct SimulatorHandle {
+    data: watch::Sender<Vec<Data>>,
+    shutdown: watch::Sender<bool>,
+    stats: Arc<Stats>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl SimulatorHandle {
+    /// Replace the published entries, like a reload of the data file.
+    ///
+    /// Generators such as counters continue where they were if their entry keeps its topics.
+    pub fn set_data(&self, mut data: Vec<Data>) {
+        data::migrate_state(&self.data.borrow(), &mut data);
+        self.data.send_replace(data);
+        self.stats.record_reload();
+    }
+
+    /// Get the counters of the simulator's activity.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Stop publishing and wait for the broker to acknowledge the pending publishes.
+    pub async fn shutdown(self) -> Result<()> {
+        self.shutdown.send_replace(true);
+        self.join().await
+    }
+
+    /// Wait until the simulator stops, either after a shutdown or because it failed.
+    pub async fn join(self) -> Result<()> {
+        self.task
+            .await
+            .unwrap_or_else(|e| Err(anyhow!("Simulator died: {:?}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{runtime, time};
+
+    use super::*;
+    use crate::data::Format;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn simulator(data: Vec<Data>) -> Simulator {
+        Simulator::new(MqttOptions::new("test", "localhost", 1883), data)
+            .interval(Duration::from_millis(10))
+            .dry_run(true)
+    }
+
+    #[test]
+    fn once_stops_by_itself() {
+        let data = data::load(
+            r#"[{"topic": "t", "data": {"start": 0}}]"#,
+            Format::Json,
+            None,
+        )
+        .unwrap();
+        let res = block_on(async {
+            let handle = simulator(data).once(true).run();
+            time::timeout(Duration::from_secs(5), handle.join()).await
+        });
+        assert!(matches!(res, Ok(Ok(()))));
+    }
+
+    #[test]
+    fn watched_file_is_reloaded() {
+        let path =
+            std::env::temp_dir().join(format!("simulator-watch-{}.json", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        std::fs::write(&path, r#"[{"topic": "t", "data": {"start": 0}}]"#).unwrap();
+        let reloads = block_on(async {
+            let mut source = Source::new(&[path.as_str()], Duration::ZERO).unwrap();
+            let data = source.load().await.unwrap();
+            let handle = simulator(data).watch(source).run();
+            time::sleep(Duration::from_millis(50)).await;
+            std::fs::write(&path, r#"[{"topic": "u", "data": {"start": 0}}]"#).unwrap();
+            let modified = std::fs::File::options().write(true).open(&path).unwrap();
+            modified
+                .set_modified(SystemTime::now() + Duration::from_secs(1))
+                .unwrap();
+            time::sleep(Duration::from_millis(500)).await;
+            let reloads = handle.stats().reloads();
+            handle.shutdown().await.unwrap();
+            reloads
+        });
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reloads, 1);
+    }
+
+    #[test]
+    fn chaos_drops_combine_with_the_drop_rate() {
+        assert_eq!(combined_drop_rate(0.1, 0.), 0.1);
+        assert_eq!(combined_drop_rate(0., 0.1), 0.1);
+        assert!((combined_drop_rate(0.1, 0.1) - 0.19).abs() < 1e-12);
+        assert_eq!(combined_drop_rate(1., 0.5), 1.);
+    }
+}
@@ -0,0 +1,170 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags, Statement};
+use serde::{Deserialize, Serialize};
+
+use crate::data::{Endian, IntWidth, ParseAs, Value};
+
+/// Value read from the rows of a SQLite query, one row per publish.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SqliteSource {
+    sqlite: PathBuf,
+    query: String,
+    /// Name of the published column, the first one by default.
+    #[serde(default)]
+    column: Option<String>,
+    #[serde(default)]
+    parse_as: ParseAs,
+    #[serde(default)]
+    endian: Option<Endian>,
+    #[serde(default)]
+    width: IntWidth,
+    #[serde(default)]
+    exhausted: Exhausted,
+    /// Result of the query, run on the first publish.
+    #[serde(skip)]
+    cursor: Arc<Mutex<Option<Cursor>>>,
+}
+
+/// Texts of the column in every row of a result and the position of the next row.
+#[derive(Debug)]
+struct Cursor {
+    rows: Vec<String>,
+    next: usize,
+}
+
+/// What to publish once every row of the result was published.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Exhausted {
+    /// Start over with the first row.
+    #[default]
+    Loop,
+    /// Keep publishing the last row.
+    Hold,
+    /// Skip the following publishes.
+    Stop,
+}
+
+impl SqliteSource {
+    /// Get the type the column is parsed as.
+    pub fn parse_as(&self) -> ParseAs {
+        self.parse_as
+    }
+
+    /// Check that the database can be opened and the query has the column.
+    pub fn check(&self) -> Result<(), io::Error> {
+        let connection = self.open()?;
+        let statement = connection.prepare(&self.query).map_err(|e| self.error(e))?;
+        self.column_index(&statement).map(drop)
+    }
+
+    /// Parse the next row of the result, running the query on the first call.
+    ///
+    /// Returns `None` once every row was published if the source stops then.
+    pub fn next(&self) -> Result<Option<Value>, io::Error> {
+        let mut cursor = self.cursor.lock().unwrap();
+        let cursor = match &mut *cursor {
+            Some(cursor) => cursor,
+            None => cursor.insert(Cursor {
+                rows: self.query()?,
+                next: 0,
+            }),
+        };
+        if cursor.rows.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{:?} returned no rows", self.query),
+            ));
+        }
+        let row = match (cursor.next < cursor.rows.len(), self.exhausted) {
+            (true, _) => cursor.next,
+            (false, Exhausted::Loop) => 0,
+            (false, Exhausted::Hold) => cursor.rows.len() - 1,
+            (false, Exhausted::Stop) => {
+                if cursor.next == cursor.rows.len() {
+                    log::info!(
+                        "Published all {} rows of {:?}, stopping",
+                        cursor.rows.len(),
+                        self.query
+                    );
+                    cursor.next += 1;
+                }
+                return Ok(None);
+            }
+        };
+        cursor.next = row + 1;
+        let value = self
+            .parse_as
+            .parse(&cursor.rows[row], self.endian, self.width)?;
+        Ok(Some(value))
+    }
+
+    /// Parse the next row like `next`, failing once every row was published.
+    pub fn value(&self) -> Result<Value, io::Error> {
+        self.next()?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("published all rows of {:?}", self.query),
+            )
+        })
+    }
+
+    /// Continue at the row of `previous` if it runs the same query on the same database.
+    pub fn adopt_state(&mut self, previous: &SqliteSource) {
+        if self.sqlite == previous.sqlite && self.query == previous.query {
+            self.cursor = Arc::clone(&previous.cursor);
+        }
+    }
+
+    /// Use the endianness of the entry unless the source sets its own.
+    pub fn inherit(&mut self, endian: Endian) {
+        self.endian.get_or_insert(endian);
+    }
+
+    fn open(&self) -> Result<Connection, io::Error> {
+        Connection::open_with_flags(&self.sqlite, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|e| self.error(e))
+    }
+
+    /// Run the query and read the column of every row as text.
+    fn query(&self) -> Result<Vec<String>, io::Error> {
+        let connection = self.open()?;
+        let mut statement = connection.prepare(&self.query).map_err(|e| self.error(e))?;
+        let index = self.column_index(&statement)?;
+        let mut rows = statement.query([]).map_err(|e| self.error(e))?;
+        let mut texts = Vec::new();
+        while let Some(row) = rows.next().map_err(|e| self.error(e))? {
+            let text = match row.get_ref(index).map_err(|e| self.error(e))? {
+                ValueRef::Null => String::new(),
+                ValueRef::Integer(value) => value.to_string(),
+                ValueRef::Real(value) => value.to_string(),
+                ValueRef::Text(text) => String::from_utf8_lossy(text).into_owned(),
+                ValueRef::Blob(blob) => hex::encode(blob),
+            };
+            texts.push(text);
+        }
+        Ok(texts)
+    }
+
+    fn column_index(&self, statement: &Statement) -> Result<usize, io::Error> {
+        match &self.column {
+            Some(column) => statement.column_index(column).map_err(|e| self.error(e)),
+            None if statement.column_count() == 0 => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} has no columns", self.query),
+            )),
+            None => Ok(0),
+        }
+    }
+
+    fn error(&self, e: rusqlite::Error) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", self.sqlite.display(), e),
+        )
+    }
+}
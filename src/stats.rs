@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// State of the main session's connection to the broker.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Not connected yet since the simulator started.
+    Connecting,
+    Connected,
+    /// Lost the connection and trying to re-establish it.
+    Reconnecting,
+}
+
+/// Counters tracking the simulator's activity.
+#[derive(Default)]
+pub struct Stats {
+    messages: AtomicU64,
+    bytes: AtomicU64,
+    reconnects: AtomicU64,
+    deduplicated: AtomicU64,
+    stale: AtomicU64,
+    shed: AtomicU64,
+    connects: AtomicU64,
+    disconnects: AtomicU64,
+    reloads: AtomicU64,
+    connection: AtomicU8,
+    /// Number of messages and payload bytes published to each topic.
+    topics: Mutex<BTreeMap<String, (u64, u64)>>,
+    /// Time the sender last made progress on its due publishes, `None` while it waits for more.
+    sender_progress: Mutex<Option<Instant>>,
+    /// Time of the last event the event loop of any session returned.
+    last_poll: Mutex<Option<Instant>>,
+}
+
+impl Stats {
+    /// Record a publish to `topic` with a payload of `bytes` bytes.
+    pub fn record_publish(&self, topic: &str, bytes: usize) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        let mut topics = self.topics.lock().unwrap();
+        // looked up first so publishes to known topics don't allocate
+        match topics.get_mut(topic) {
+            Some((messages, total)) => {
+                *messages += 1;
+                *total += bytes as u64;
+            }
+            None => {
+                topics.insert(topic.to_string(), (1, bytes as u64));
+            }
+        }
+    }
+
+    /// Record an event returned by the event loop of a session.
+    pub fn record_poll(&self) {
+        *self.last_poll.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Record progress of the sender on publishes that are due.
+    pub fn record_sender_progress(&self) {
+        *self.sender_progress.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Record that the sender waits for the next publishes on purpose, e.g. for their interval.
+    pub fn record_sender_idle(&self) {
+        *self.sender_progress.lock().unwrap() = None;
+    }
+
+    /// Get the time the sender last made progress, `None` while it's idle.
+    pub fn sender_progress(&self) -> Option<Instant> {
+        *self.sender_progress.lock().unwrap()
+    }
+
+    /// Get the time of the last event of an event loop, `None` before the first one.
+    pub fn last_poll(&self) -> Option<Instant> {
+        *self.last_poll.lock().unwrap()
+    }
+
+    /// Record an established connection to the broker, of the main or an entry's own session.
+    pub fn record_connect(&self) {
+        self.connects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the loss of an established connection to the broker.
+    pub fn record_disconnect(&self) {
+        self.disconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a connection to the broker re-established after losing it.
+    pub fn record_reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a successful reload of the data.
+    pub fn record_reload(&self) {
+        self.reloads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a publish skipped because its payload didn't change.
+    pub fn record_dedup(&self) {
+        self.deduplicated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a publish skipped because its value outlived the TTL before it could be sent.
+    pub fn record_stale(&self) {
+        self.stale.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a publish dropped from or instead of queueing it because the request queue was full.
+    pub fn record_shed(&self) {
+        self.shed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a change of the connection state.
+    pub fn set_connection(&self, state: ConnectionState) {
+        self.connection.store(state as u8, Ordering::Relaxed);
+    }
+
+    /// Get the current connection state.
+    pub fn connection(&self) -> ConnectionState {
+        match self.connection.load(Ordering::Relaxed) {
+            0 => ConnectionState::Connecting,
+            1 => ConnectionState::Connected,
+            _ => ConnectionState::Reconnecting,
+        }
+    }
+
+    /// Get the number of published messages.
+    pub fn messages(&self) -> u64 {
+        self.messages.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of published payload bytes.
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of connections re-established after losing them.
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of successful reloads of the data since the start.
+    pub fn reloads(&self) -> u64 {
+        self.reloads.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of publishes skipped because their payload didn't change.
+    pub fn deduplicated(&self) -> u64 {
+        self.deduplicated.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of publishes skipped because their value outlived the TTL.
+    pub fn stale(&self) -> u64 {
+        self.stale.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of publishes dropped because the request queue was full.
+    pub fn shed(&self) -> u64 {
+        self.shed.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of established connections to the broker.
+    pub fn connects(&self) -> u64 {
+        self.connects.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of lost connections to the broker.
+    pub fn disconnects(&self) -> u64 {
+        self.disconnects.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of messages and payload bytes published to each topic.
+    pub fn topics(&self) -> BTreeMap<String, (u64, u64)> {
+        self.topics.lock().unwrap().clone()
+    }
+
+    /// Log a summary of the counters for a run lasting `elapsed`.
+    pub fn log_summary(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let rate = if secs > 0. {
+            self.messages() as f64 / secs
+        } else {
+            0.
+        };
+        log::info!(
+            "Sent {} messages ({} bytes) in {:.1}s, {:.1} msg/s, {} reconnects, {} deduplicated, {} stale, {} shed",
+            self.messages(),
+            self.bytes(),
+            secs,
+            rate,
+            self.reconnects(),
+            self.deduplicated(),
+            self.stale(),
+            self.shed()
+        );
+    }
+}
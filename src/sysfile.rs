@@ -0,0 +1,67 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::{Endian, IntWidth, ParseAs, Value};
+
+/// Value read from a file before every publish, e.g. a sysfs attribute on Linux.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SysFile {
+    sysfile: PathBuf,
+    #[serde(default)]
+    parse_as: ParseAs,
+    #[serde(default)]
+    endian: Option<Endian>,
+    #[serde(default)]
+    width: IntWidth,
+    /// Text published in place of the file's contents if it can't be read.
+    #[serde(default)]
+    default: Option<String>,
+}
+
+impl SysFile {
+    /// Get the type the file's contents are parsed as.
+    pub fn parse_as(&self) -> ParseAs {
+        self.parse_as
+    }
+
+    /// Use the endianness of the entry unless the file sets its own.
+    pub fn inherit(&mut self, endian: Endian) {
+        self.endian.get_or_insert(endian);
+    }
+
+    /// Read and parse the current contents of the file.
+    pub fn value(&self) -> Result<Value, io::Error> {
+        let text = match (fs::read_to_string(&self.sysfile), &self.default) {
+            (Ok(text), _) => text,
+            (Err(e), Some(default)) => {
+                log::warn!(
+                    "Failed to read {}, publishing the default: {}",
+                    self.sysfile.display(),
+                    e
+                );
+                default.clone()
+            }
+            (Err(e), None) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("Failed to read {}: {}", self.sysfile.display(), e),
+                ))
+            }
+        };
+        self.parse_as.parse(&text, self.endian, self.width)
+    }
+
+    /// Check that the default can be parsed.
+    pub fn check(&self) -> Result<(), io::Error> {
+        match &self.default {
+            Some(default) => self
+                .parse_as
+                .parse(default, self.endian, self.width)
+                .map(drop),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::fs::{File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::{Framing, StringEncoding, Value};
+
+/// Lines appended to a file, published one by one as they appear.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Tail {
+    tail: PathBuf,
+    /// Publish the lines already in the file when it is first read instead of skipping them.
+    #[serde(default)]
+    from_start: bool,
+    #[serde(default)]
+    encoding: StringEncoding,
+    #[serde(default)]
+    bom: Option<bool>,
+    #[serde(skip)]
+    position: Arc<Mutex<Option<Position>>>,
+}
+
+/// Read position within the tailed file.
+#[derive(Debug)]
+struct Position {
+    /// Identity of the file, a new one means the file was rotated.
+    id: Option<u64>,
+    /// Offset behind the last complete line that was read.
+    offset: u64,
+}
+
+impl Tail {
+    /// Read the complete lines appended since the last call as string values.
+    ///
+    /// A line is only read once its newline was written. The file is read from the start again
+    /// if it shrinks or is replaced, e.g. by log rotation, and a missing file has no new lines.
+    pub fn read_lines(&self) -> Result<Vec<Value>, io::Error> {
+        let mut file = match File::open(&self.tail) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                log::debug!("{} doesn't exist, waiting for it", self.tail.display());
+                return Ok(Vec::new());
+            }
+            Err(e) => return Err(e),
+        };
+        let meta = file.metadata()?;
+        let id = file_id(&meta);
+        let mut position = self.position.lock().unwrap();
+        let position = position.get_or_insert_with(|| Position {
+            id,
+            offset: if self.from_start { 0 } else { meta.len() },
+        });
+        if position.id != id || meta.len() < position.offset {
+            log::info!(
+                "{} was rotated or truncated, reading from the start",
+                self.tail.display()
+            );
+            *position = Position { id, offset: 0 };
+        }
+        file.seek(SeekFrom::Start(position.offset))?;
+        let mut appended = Vec::new();
+        file.read_to_end(&mut appended)?;
+        let complete = match appended.iter().rposition(|b| *b == b'\n') {
+            Some(end) => &appended[..=end],
+            None => return Ok(Vec::new()),
+        };
+        position.offset += complete.len() as u64;
+        let lines = complete[..complete.len() - 1]
+            .split(|b| *b == b'\n')
+            .map(|line| Value::String {
+                value: String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line))
+                    .into_owned(),
+                encoding: self.encoding,
+                framing: Framing {
+                    bom: self.bom,
+                    ..Framing::default()
+                },
+            })
+            .collect();
+        Ok(lines)
+    }
+
+    /// Continue reading at the position of `previous` if it tails the same file.
+    pub fn adopt_state(&mut self, previous: &Tail) {
+        if self.tail == previous.tail {
+            self.position = Arc::clone(&previous.position);
+        }
+    }
+
+    /// Get the encoding the lines are published in.
+    pub fn encoding(&self) -> StringEncoding {
+        self.encoding
+    }
+}
+
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.ino())
+}
+
+#[cfg(not(unix))]
+fn file_id(_: &Metadata) -> Option<u64> {
+    None
+}
@@ -0,0 +1,283 @@
+use std::fs::File;
+use std::io::BufReader;
+#[cfg(feature = "quic")]
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use rumqttc::{
+    certs, pkcs8_private_keys, rsa_private_keys, ClientConfig, TlsConfiguration, Transport,
+};
+use tokio::sync::watch;
+use tokio::time::interval;
+
+/// Build the TLS configuration of broker connections.
+///
+/// The broker's certificate is verified against `ca_cert`, or the system root store without one.
+/// `client_auth` holds the paths of a PEM client certificate chain and its PKCS#8 or RSA key.
+pub fn configuration(
+    ca_cert: Option<&str>,
+    client_auth: Option<(&str, &str)>,
+) -> Result<TlsConfiguration> {
+    client_config(ca_cert, client_auth).map(TlsConfiguration::from)
+}
+
+/// Build the rustls configuration of broker connections, see [`configuration`].
+pub fn client_config(
+    ca_cert: Option<&str>,
+    client_auth: Option<(&str, &str)>,
+) -> Result<ClientConfig> {
+    let mut config = ClientConfig::new();
+    match ca_cert {
+        Some(path) => {
+            let (added, _) = config
+                .root_store
+                .add_pem_file(&mut open(path)?)
+                .map_err(|()| anyhow!("Invalid CA certificate {}", path))?;
+            if added == 0 {
+                bail!("No CA certificate in {}", path);
+            }
+        }
+        None => {
+            config.root_store = match rustls_native_certs::load_native_certs() {
+                Ok(store) => store,
+                Err((Some(store), e)) => {
+                    log::warn!("Skipped some system root certificates: {}", e);
+                    store
+                }
+                Err((None, e)) => {
+                    return Err(e).context("Failed to load the system root certificates")
+                }
+            };
+        }
+    }
+    if let Some((cert, key)) = client_auth {
+        let chain =
+            certs(&mut open(cert)?).map_err(|()| anyhow!("Invalid client certificate {}", cert))?;
+        if chain.is_empty() {
+            bail!("No client certificate in {}", cert);
+        }
+        let mut keys = pkcs8_private_keys(&mut open(key)?)
+            .map_err(|()| anyhow!("Invalid client key {}", key))?;
+        if keys.is_empty() {
+            keys = rsa_private_keys(&mut open(key)?)
+                .map_err(|()| anyhow!("Invalid client key {}", key))?;
+        }
+        if keys.is_empty() {
+            bail!("No PKCS#8 or RSA private key in {}", key);
+        }
+        config
+            .set_single_client_cert(chain, keys.remove(0))
+            .with_context(|| format!("Client key {} doesn't match {}", key, cert))?;
+    }
+    Ok(config)
+}
+
+/// Certificate files of a TLS transport, watched for rotations.
+pub(crate) struct TlsFiles {
+    pub ca_cert: Option<String>,
+    pub client_auth: Option<(String, String)>,
+}
+
+impl TlsFiles {
+    fn paths(&self) -> impl Iterator<Item = &str> {
+        let client_auth = self.client_auth.iter();
+        self.ca_cert
+            .iter()
+            .map(String::as_str)
+            .chain(client_auth.flat_map(|(cert, key)| [cert.as_str(), key.as_str()]))
+    }
+
+    /// Get the newest modification time of the files, `None` if one can't be read.
+    fn modified(&self) -> Option<SystemTime> {
+        self.paths()
+            .map(|path| {
+                std::fs::metadata(path)
+                    .and_then(|meta| meta.modified())
+                    .ok()
+            })
+            .try_fold(SystemTime::UNIX_EPOCH, |newest, modified| {
+                Some(newest.max(modified?))
+            })
+    }
+
+    /// Rebuild `transport` from the current files.
+    fn transport(&self, transport: &Transport) -> Result<Transport> {
+        let client_auth = self
+            .client_auth
+            .as_ref()
+            .map(|(cert, key)| (cert.as_str(), key.as_str()));
+        let config = configuration(self.ca_cert.as_deref(), client_auth)?;
+        Ok(match transport {
+            #[cfg(feature = "websocket")]
+            Transport::Wss(_) => Transport::wss_with_config(config),
+            _ => Transport::tls_with_config(config),
+        })
+    }
+}
+
+/// Send the transport rebuilt from `files` to reconnect with every time they change.
+///
+/// Certificates and keys are rarely replaced at once, a revision that doesn't load, e.g. a new
+/// certificate without its key, keeps the current transport until the files change again.
+pub(crate) async fn rotate_certificates(files: TlsFiles, transports: watch::Sender<Transport>) {
+    let mut interval = interval(Duration::from_secs(1));
+    let mut loaded = files.modified();
+    let mut failed = None;
+    loop {
+        interval.tick().await;
+        let modified = files.modified();
+        if modified.is_none() || modified == loaded || modified == failed {
+            continue;
+        }
+        let transport = files.transport(&transports.borrow());
+        match transport {
+            Ok(transport) => {
+                log::info!("Loaded the rotated TLS certificates");
+                transports.send_replace(transport);
+                loaded = modified;
+            }
+            Err(e) => {
+                log::warn!("Keeping the current TLS certificates: {:#}", e);
+                failed = modified;
+            }
+        }
+    }
+}
+
+fn open(path: &str) -> Result<BufReader<File>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    Ok(BufReader::new(file))
+}
+
+/// Build the TLS 1.3 configuration of QUIC broker connections from the same files.
+#[cfg(feature = "quic")]
+pub fn quic_configuration(
+    ca_cert: Option<&str>,
+    client_auth: Option<(&str, &str)>,
+) -> Result<rustls::ClientConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    match ca_cert {
+        Some(path) => {
+            for cert in rustls_pemfile::certs(&mut open(path)?) {
+                let cert = cert.with_context(|| format!("Invalid CA certificate {}", path))?;
+                roots
+                    .add(cert)
+                    .with_context(|| format!("Invalid CA certificate {}", path))?;
+            }
+            if roots.is_empty() {
+                bail!("No CA certificate in {}", path);
+            }
+        }
+        None => {
+            let native = native_certs::load_native_certs();
+            for e in &native.errors {
+                log::warn!("Skipped some system root certificates: {}", e);
+            }
+            let (_, ignored) = roots.add_parsable_certificates(native.certs);
+            if ignored > 0 {
+                log::warn!("Skipped {} invalid system root certificates", ignored);
+            }
+            if roots.is_empty() {
+                bail!("Failed to load the system root certificates");
+            }
+        }
+    }
+    let builder = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_protocol_versions(&[&rustls::version::TLS13])?
+    .with_root_certificates(roots);
+    let mut config = match client_auth {
+        Some((cert, key)) => {
+            let chain = rustls_pemfile::certs(&mut open(cert)?)
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("Invalid client certificate {}", cert))?;
+            if chain.is_empty() {
+                bail!("No client certificate in {}", cert);
+            }
+            let key_der = rustls_pemfile::private_key(&mut open(key)?)
+                .with_context(|| format!("Invalid client key {}", key))?
+                .ok_or_else(|| anyhow!("No private key in {}", key))?;
+            builder
+                .with_client_auth_cert(chain, key_der)
+                .with_context(|| format!("Client key {} doesn't match {}", key, cert))?
+        }
+        None => builder.with_no_client_auth(),
+    };
+    config.alpn_protocols = vec![b"mqtt".to_vec()];
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tokio::{runtime, time};
+
+    use super::*;
+
+    const CA_CERT: &str = include_str!("../testdata/ca.pem");
+    const CERT: &str = include_str!("../testdata/cert.pem");
+    const KEY: &str = include_str!("../testdata/key.pem");
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// Write a CA certificate and a client certificate it signed with its key.
+    fn files(name: &str) -> (TlsFiles, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("tls-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ca_cert = dir.join("ca.pem");
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&ca_cert, CA_CERT).unwrap();
+        std::fs::write(&cert, CERT).unwrap();
+        std::fs::write(&key, KEY).unwrap();
+        let path = |path: &PathBuf| path.to_str().unwrap().to_string();
+        let files = TlsFiles {
+            ca_cert: Some(path(&ca_cert)),
+            client_auth: Some((path(&cert), path(&key))),
+        };
+        (files, dir)
+    }
+
+    /// Replace the file at `path`, dated ahead so the change is seen within the same second.
+    fn rewrite(path: &PathBuf, contents: &str) {
+        std::fs::write(path, contents).unwrap();
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(1))
+            .unwrap();
+    }
+
+    /// Rewrite the key of a watched transport and wait a little for a new transport.
+    fn rotate(name: &str, key: &str) -> bool {
+        let (files, dir) = files(name);
+        let transport = files.transport(&Transport::Tcp).unwrap();
+        let (transports, mut rotated) = watch::channel(transport);
+        let changed = block_on(async {
+            let rotation = tokio::spawn(rotate_certificates(files, transports));
+            time::sleep(Duration::from_millis(50)).await;
+            rewrite(&dir.join("key.pem"), key);
+            let changed = time::timeout(Duration::from_secs(2), rotated.changed()).await;
+            rotation.abort();
+            changed.is_ok()
+        });
+        std::fs::remove_dir_all(dir).unwrap();
+        changed
+    }
+
+    #[test]
+    fn rewritten_certificates_send_a_new_transport() {
+        assert!(rotate("rewritten", KEY));
+    }
+
+    #[test]
+    fn certificates_that_dont_load_yet_keep_the_transport() {
+        assert!(!rotate("incomplete", ""));
+    }
+}
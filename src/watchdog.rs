@@ -0,0 +1,143 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use tokio::time::interval;
+
+use crate::stats::Stats;
+
+/// Watchdog reporting when the sender or the event loop stop making progress.
+///
+/// The sender only counts as stalled while it has due publishes, waiting for the next interval,
+/// on standby or for a fresh data file is intended.
+pub struct Watchdog {
+    stats: Arc<Stats>,
+    timeout: Duration,
+    /// Fail once a stall is detected instead of only logging it.
+    exit: bool,
+}
+
+impl Watchdog {
+    pub fn new(stats: Arc<Stats>, timeout: Duration, exit: bool) -> Self {
+        Watchdog {
+            stats,
+            timeout,
+            exit,
+        }
+    }
+
+    /// Check for stalls until one ends the watchdog, each stall is logged once.
+    pub async fn run(self) -> Result<()> {
+        let started = Instant::now();
+        let mut interval = interval(self.timeout / 4);
+        let (mut sender_stalled, mut polls_stalled) = (false, false);
+        loop {
+            interval.tick().await;
+            let progress = self.stats.sender_progress();
+            let stalled = progress.is_some_and(|progress| progress.elapsed() > self.timeout);
+            if stalled && !sender_stalled {
+                log::error!(
+                    "The sender made no progress for {:.1?}, it seems stuck",
+                    progress
+                        .map(|progress| progress.elapsed())
+                        .unwrap_or_default()
+                );
+                if self.exit {
+                    anyhow::bail!("Publishing stalled for more than {:?}", self.timeout);
+                }
+            } else if !stalled && sender_stalled {
+                log::info!("Publishing resumed");
+            }
+            sender_stalled = stalled;
+            let poll = self
+                .stats
+                .last_poll()
+                .map_or(started, |last| last.max(started));
+            let stalled = poll.elapsed() > self.timeout;
+            if stalled && !polls_stalled {
+                log::error!(
+                    "The event loop made no progress for {:.1?}, the connection seems stuck",
+                    poll.elapsed()
+                );
+                if self.exit {
+                    anyhow::bail!("The event loop stalled for more than {:?}", self.timeout);
+                }
+            } else if !stalled && polls_stalled {
+                log::info!("The event loop made progress again");
+            }
+            polls_stalled = stalled;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rumqttc::{EventLoop, MqttOptions};
+    use tokio::sync::watch;
+    use tokio::{runtime, task, time};
+
+    use super::*;
+    use crate::data::{self, Format};
+    use crate::engine::{self, tests::config, Queue};
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// Record an event of the event loop every few milliseconds, so only the sender can stall.
+    fn keep_polling(stats: &Arc<Stats>) {
+        let stats = Arc::clone(stats);
+        task::spawn(async move {
+            loop {
+                stats.record_poll();
+                time::sleep(Duration::from_millis(5)).await;
+            }
+        });
+    }
+
+    /// Run the sender on a queue of one request that is never taken, along with the watchdog.
+    fn run_sender(entries: &str, interval: Duration, timeout: Duration) -> Option<Result<()>> {
+        let vals = data::load(entries, Format::Json, None).unwrap();
+        block_on(async {
+            let stats = Arc::new(Stats::default());
+            keep_polling(&stats);
+            let eventloop = EventLoop::new(MqttOptions::new("test", "localhost", 1883), 1);
+            let (_data, rx) = watch::channel(vals);
+            let sender = engine::sender(rx, Queue::of(&eventloop), config(&stats, interval));
+            task::spawn(sender);
+            let watchdog = Watchdog::new(Arc::clone(&stats), timeout, true);
+            time::timeout(timeout * 5, watchdog.run()).await.ok()
+        })
+    }
+
+    #[test]
+    fn stalled_sender_trips_the_watchdog() {
+        let entries = r#"[
+            {"topic": "a", "data": {"value": 1}},
+            {"topic": "b", "data": {"value": 2}}
+        ]"#;
+        let stall = run_sender(entries, Duration::from_secs(1), Duration::from_millis(50));
+        let err = stall.expect("watchdog didn't trip").unwrap_err();
+        assert!(err.to_string().contains("Publishing stalled"), "{}", err);
+    }
+
+    #[test]
+    fn waiting_for_the_interval_is_no_stall() {
+        // the single publish fits the queue, then the sender waits far beyond the timeout
+        let entries = r#"[{"topic": "a", "data": {"value": 1}}]"#;
+        let stall = run_sender(entries, Duration::from_secs(60), Duration::from_millis(50));
+        assert!(stall.is_none(), "watchdog tripped: {:?}", stall);
+    }
+
+    #[test]
+    fn stalled_event_loop_trips_the_watchdog() {
+        let stats = Arc::new(Stats::default());
+        let watchdog = Watchdog::new(stats, Duration::from_millis(20), true);
+        let err = block_on(watchdog.run()).unwrap_err();
+        assert!(err.to_string().contains("event loop"), "{}", err);
+    }
+}
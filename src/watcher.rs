@@ -0,0 +1,434 @@
+//! Loading the data and reloading it once it changes.
+
+use std::future;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context as _, Result};
+use rumqttc::{Publish, QoS, Request, Sender};
+#[cfg(feature = "http")]
+use tokio::select;
+use tokio::sync::{mpsc, watch};
+use tokio::{fs, time::interval};
+
+use crate::data::{self, Data, Format};
+#[cfg(feature = "http")]
+use crate::remote;
+use crate::stats::Stats;
+
+/// Settings of the data file watcher task.
+pub(crate) struct WatcherConfig {
+    pub error_topic: Option<String>,
+    pub status_topic: Option<String>,
+    /// Failed attempts to load a revision before reporting it.
+    pub error_threshold: usize,
+    /// Requests to reload the data file even though it didn't change.
+    pub reload: watch::Receiver<()>,
+    /// Modification time of the data file loaded on startup.
+    pub loaded: SystemTime,
+    /// Queue of every loaded revision, for publishing each of them at least once.
+    pub revisions: Option<mpsc::UnboundedSender<Vec<Data>>>,
+    /// Counters of the simulator, which count the reloads.
+    pub stats: Arc<Stats>,
+}
+
+/// Location the data is loaded from and watched for changes.
+pub struct Source {
+    kind: Kind,
+    /// Locations of the data, joined for logs and status messages.
+    label: String,
+    /// Modification time of the data loaded last.
+    loaded: SystemTime,
+    /// Seed of the constants of the devices.
+    seed: Option<u64>,
+}
+
+enum Kind {
+    /// Data files merged in order, with the format of each.
+    Files(Vec<(String, Format)>),
+    /// Data read from stdin once, there are no changes to watch.
+    Stdin(Format),
+    #[cfg(feature = "http")]
+    Remote(remote::Remote, Format),
+}
+
+impl Source {
+    /// Get the source of the data files at `paths`, which are merged in order.
+    ///
+    /// The format of every file follows from its extension. The path `-` reads stdin and URLs are
+    /// polled every `poll_interval`, both have to be the only path.
+    #[cfg_attr(not(feature = "http"), allow(unused_variables))]
+    pub fn new(paths: &[&str], poll_interval: Duration) -> Result<Self> {
+        if paths.len() > 1 {
+            if let Some(path) = paths.iter().find(|path| **path == "-" || is_url(path)) {
+                anyhow::bail!("{} can't be merged with other data files", path);
+            }
+        }
+        let kind = match paths {
+            ["-"] => Kind::Stdin(Format::Json),
+            #[cfg(feature = "http")]
+            [url] if is_url(url) => Kind::Remote(
+                remote::Remote::new(url.to_string(), poll_interval),
+                data_format(url),
+            ),
+            #[cfg(not(feature = "http"))]
+            [url] if is_url(url) => {
+                anyhow::bail!("Loading the data from {} needs the http feature", url)
+            }
+            paths => Kind::Files(
+                paths
+                    .iter()
+                    .map(|path| (path.to_string(), data_format(path)))
+                    .collect(),
+            ),
+        };
+        Ok(Source {
+            kind,
+            label: paths.join(", "),
+            loaded: SystemTime::UNIX_EPOCH,
+            seed: None,
+        })
+    }
+
+    /// Draw the constants of the devices from `seed` rather than from entropy, like `--seed`.
+    pub fn seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Get the locations of the data, joined by commas.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Get the modification time of the data loaded last.
+    pub fn loaded(&self) -> SystemTime {
+        self.loaded
+    }
+
+    /// Read, parse and merge the data, failing if any of it can't be loaded.
+    pub async fn load(&mut self) -> Result<Vec<Data>> {
+        let seed = self.seed;
+        let (vals, loaded) = match &mut self.kind {
+            Kind::Files(files) => load_files(files, seed)?,
+            Kind::Stdin(format) => {
+                let values = io::read_to_string(io::stdin()).context("Failed to read stdin")?;
+                let vals = data::load(&values, *format, seed).context("Failed to parse stdin")?;
+                (vals, SystemTime::now())
+            }
+            #[cfg(feature = "http")]
+            Kind::Remote(remote, format) => (remote.load(*format, seed).await?, SystemTime::now()),
+        };
+        self.loaded = loaded;
+        Ok(vals)
+    }
+}
+
+/// Check whether the data is loaded from `path` over HTTP rather than from a file.
+fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Watch the source of the data and hand every revision that loads over to the sender.
+pub(crate) async fn data_watcher(
+    source: Source,
+    tx: watch::Sender<Vec<Data>>,
+    mtime_tx: watch::Sender<SystemTime>,
+    sink: Sender<Request>,
+    config: WatcherConfig,
+) -> Result<()> {
+    match source.kind {
+        Kind::Files(files) => file_watcher(files, source.seed, tx, mtime_tx, sink, config).await,
+        Kind::Stdin(_) => {
+            log::info!("Read the data from stdin, it isn't reloaded");
+            future::pending().await
+        }
+        #[cfg(feature = "http")]
+        Kind::Remote(remote, format) => {
+            remote_watcher(remote, format, source.seed, tx, mtime_tx, sink, config).await
+        }
+    }
+}
+
+/// Hand reloaded data over to the sender, keeping the state of the entries that didn't change.
+async fn replace_data(
+    mut vals: Vec<Data>,
+    path: &str,
+    tx: &watch::Sender<Vec<Data>>,
+    revisions: Option<&mpsc::UnboundedSender<Vec<Data>>>,
+    sink: &Sender<Request>,
+    status_topic: Option<&str>,
+    stats: &Stats,
+) -> Result<()> {
+    data::migrate_state(&tx.borrow(), &mut vals);
+    let changes = data::changes(&tx.borrow(), &vals);
+    if changes.is_empty() {
+        log::info!("Replacing values of {} without changes", path);
+    } else {
+        log::info!("Replacing values of {}, {}", path, changes);
+    }
+    log::debug!("Replacing values with:\n{:#?}", vals);
+    let entries = vals.len();
+    if let Some(revisions) = revisions {
+        revisions.send(vals.clone()).expect("Sender died");
+    }
+    tx.send(vals).map_err(|_| "").expect("Watchers died");
+    stats.record_reload();
+    if let Some(topic) = status_topic {
+        publish_status(sink, topic, path, entries).await?;
+    }
+    Ok(())
+}
+
+/// Poll the data file served at the URL of `remote`, keeping the current values while it fails.
+///
+/// Every revision is complete once it was served, so one that fails to parse is reported right
+/// away.
+#[cfg(feature = "http")]
+async fn remote_watcher(
+    mut remote: remote::Remote,
+    format: Format,
+    seed: Option<u64>,
+    tx: watch::Sender<Vec<Data>>,
+    mtime_tx: watch::Sender<SystemTime>,
+    sink: Sender<Request>,
+    config: WatcherConfig,
+) -> Result<()> {
+    let WatcherConfig {
+        error_topic,
+        status_topic,
+        mut reload,
+        revisions,
+        stats,
+        ..
+    } = config;
+    let url = remote.url().to_string();
+    let mut interval = interval(remote.poll_interval());
+    // the data was fetched on startup
+    interval.tick().await;
+    let mut unreachable = false;
+    loop {
+        let force = select! {
+            _ = interval.tick() => false,
+            Ok(()) = reload.changed() => {
+                log::info!("Reloading {} on request", url);
+                true
+            }
+        };
+        let values = match remote.fetch(force).await {
+            Ok(values) => values,
+            Err(e) => {
+                if !unreachable {
+                    log::warn!("Failed to fetch {}, keeping the current values: {}", url, e);
+                    unreachable = true;
+                }
+                continue;
+            }
+        };
+        if unreachable {
+            log::info!("{} is reachable again", url);
+            unreachable = false;
+        }
+        let values = match values {
+            Some(values) => values,
+            None => continue,
+        };
+        match data::load(&values, format, seed) {
+            Ok(vals) => {
+                mtime_tx.send_replace(SystemTime::now());
+                let status_topic = status_topic.as_deref();
+                let revisions = revisions.as_ref();
+                replace_data(vals, &url, &tx, revisions, &sink, status_topic, &stats).await?;
+            }
+            Err(e) => {
+                log::error!("{} fails to load, keeping the current values: {}", url, e);
+                if let Some(topic) = &error_topic {
+                    let msg = Publish::new(topic, QoS::AtLeastOnce, format!("{}: {}", url, e));
+                    sink.send(Request::Publish(msg))
+                        .await
+                        .expect("Eventloop rx seems to be dead.");
+                }
+            }
+        }
+    }
+}
+
+async fn file_watcher(
+    files: Vec<(String, Format)>,
+    seed: Option<u64>,
+    tx: watch::Sender<Vec<Data>>,
+    mtime_tx: watch::Sender<SystemTime>,
+    sink: Sender<Request>,
+    config: WatcherConfig,
+) -> Result<()> {
+    let WatcherConfig {
+        error_topic,
+        status_topic,
+        error_threshold,
+        mut reload,
+        loaded,
+        revisions,
+        stats,
+    } = config;
+    let label = files
+        .iter()
+        .map(|(path, _)| path.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut interval = interval(Duration::from_millis(100));
+    let mut modified = loaded;
+    let mut failed = SystemTime::UNIX_EPOCH;
+    // consecutive failed attempts to load the revision modified at `failed`
+    let mut failures = 0;
+    // editors replacing the file atomically make it disappear briefly
+    let mut unreadable = false;
+    'watch: loop {
+        // an edit of any of the files reloads all of them
+        let mut last_mod = SystemTime::UNIX_EPOCH;
+        for (path, _) in &files {
+            match fs::metadata(path).await.and_then(|meta| meta.modified()) {
+                Ok(file_mod) => last_mod = last_mod.max(file_mod),
+                Err(e) => {
+                    if !unreadable {
+                        log::warn!("Failed to read {}, keeping the current values: {}", path, e);
+                        unreadable = true;
+                    }
+                    interval.tick().await;
+                    continue 'watch;
+                }
+            }
+        }
+        if unreadable {
+            log::info!("{} is readable again", label);
+            unreadable = false;
+        }
+        if *mtime_tx.borrow() != last_mod {
+            mtime_tx.send_replace(last_mod);
+        }
+        if reload.has_changed().unwrap_or(false) {
+            reload.borrow_and_update();
+            log::info!("Reloading {} on request", label);
+            modified = SystemTime::UNIX_EPOCH;
+        }
+        if modified < last_mod {
+            let mut merged = Ok(Vec::new());
+            for (path, format) in &files {
+                let values = match fs::read_to_string(path).await {
+                    Ok(values) => values,
+                    Err(e) => {
+                        log::debug!("Failed to read {}: {}", path, e);
+                        interval.tick().await;
+                        continue 'watch;
+                    }
+                };
+                match data::load(&values, *format, seed) {
+                    Ok(vals) => merged = merged.map(|merged| data::merge(merged, vals)),
+                    Err(e) => {
+                        log::debug!("Failed to read values: {:?}\n{}", e, values);
+                        merged = Err((path, e));
+                        break;
+                    }
+                }
+            }
+            match merged {
+                Ok(vals) => {
+                    let status_topic = status_topic.as_deref();
+                    let revisions = revisions.as_ref();
+                    replace_data(vals, &label, &tx, revisions, &sink, status_topic, &stats).await?;
+                    modified = last_mod;
+                }
+                Err((path, e)) => {
+                    if failed < last_mod {
+                        failed = last_mod;
+                        failures = 0;
+                    }
+                    failures += 1;
+                    // a partially written file is fixed by the next write, only report each
+                    // revision once after it stayed broken for the threshold
+                    if failures == error_threshold {
+                        log::error!(
+                            "{} still fails to load after {} attempts: {}",
+                            path,
+                            failures,
+                            e
+                        );
+                        if let Some(topic) = &error_topic {
+                            let msg =
+                                Publish::new(topic, QoS::AtLeastOnce, format!("{}: {}", path, e));
+                            sink.send(Request::Publish(msg))
+                                .await
+                                .expect("Eventloop rx seems to be dead.");
+                        }
+                    }
+                }
+            }
+        }
+        interval.tick().await;
+    }
+}
+
+/// Publish the retained reload status of the data file.
+pub(crate) async fn publish_status(
+    sink: &Sender<Request>,
+    topic: &str,
+    path: &str,
+    entries: usize,
+) -> Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let status = serde_json::json!({
+        "path": path,
+        "reloaded_ms": timestamp,
+        "entries": entries,
+    });
+    let mut msg = Publish::new(topic, QoS::AtLeastOnce, serde_json::to_vec(&status)?);
+    msg.retain = true;
+    sink.send(Request::Publish(msg))
+        .await
+        .expect("Eventloop rx seems to be dead.");
+    Ok(())
+}
+
+/// Get the format of the data file at `path` from its extension.
+fn data_format(path: &str) -> Format {
+    if path == "-" {
+        return Format::Json;
+    }
+    // the query of a URL isn't part of the file name
+    let name = if is_url(path) {
+        path.split(&['?', '#'][..]).next().unwrap_or(path)
+    } else {
+        path
+    };
+    Format::from_path(name).unwrap_or_else(|| {
+        log::info!("Unknown extension of {}, parsing it as JSON", path);
+        Format::Json
+    })
+}
+
+/// Read, parse and merge the data files along with the newest modification time.
+fn load_files(files: &[(String, Format)], seed: Option<u64>) -> Result<(Vec<Data>, SystemTime)> {
+    let mut merged = Vec::new();
+    let mut newest = SystemTime::UNIX_EPOCH;
+    for (path, format) in files {
+        let (vals, modified) = load_data(path, *format, seed)?;
+        merged = data::merge(merged, vals);
+        newest = newest.max(modified);
+    }
+    Ok((merged, newest))
+}
+
+/// Read and parse the data file along with its modification time.
+fn load_data(path: &str, format: Format, seed: Option<u64>) -> Result<(Vec<Data>, SystemTime)> {
+    let modified = std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .with_context(|| format!("Failed to read {}", path))?;
+    let values =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    let vals =
+        data::load(&values, format, seed).with_context(|| format!("Failed to parse {}", path))?;
+    Ok((vals, modified))
+}